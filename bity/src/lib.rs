@@ -1,28 +1,21 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use errors::Discriminant;
+use core::fmt;
 
-#[derive(Clone, Debug)]
-#[repr(u8)]
+use errors::{Discriminant, FromDiscriminant};
+
+#[derive(Clone, Debug, Discriminant, FromDiscriminant)]
 pub enum Error {
 	BufferOverflow,
 	BufferUnderflow,
-	MAX
 }
 
-impl Discriminant for Error {
-	fn discriminant(&self) -> u8 {
-		// SAFETY: Because `Self` is marked `repr(u8)`, its layout is a `repr(C)` `union`
-		// between `repr(C)` structs, each of which has the `u8` discriminant as its first
-		// field, so we can read the discriminant without offsetting the pointer.
-		unsafe { *<*const _>::from(self).cast::<u8>() }
-	}
-	fn discriminant_max() -> u8 {
-			Error::MAX as u8
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		Self::describe(self.discriminant(), f)
 	}
 }
 
-
 pub struct BitWriter<'a> {
 	buffer: &'a mut [u8],
 	byte_pos: usize,
@@ -0,0 +1,306 @@
+//! `#[derive(Discriminant, FromDiscriminant)]`, re-exported by `errors`
+//!
+//! Variants get discriminants in the order they're declared. A unit variant takes one,
+//! a variant holding a single nested error takes its whole `discriminant_max()` range.
+//! `#[discriminant(at = N)]` starts a variant at `N` and `#[discriminant(size = N)]`
+//! reserves `N` for the whole enum, both are checked by `errors::__derive::layout`.
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+	parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Generics, Ident,
+	LitInt, Type,
+};
+
+enum Kind {
+	Unit,
+	Nested(Box<Type>),
+}
+
+struct Variant {
+	ident: Ident,
+	kind: Kind,
+	at: Option<u8>,
+}
+
+impl Variant {
+	/// How many discriminants it takes
+	fn size(&self) -> TokenStream {
+		match &self.kind {
+			Kind::Unit => quote!(1u16),
+			Kind::Nested(ty) => {
+				quote!(<#ty as ::errors::Discriminant>::discriminant_max() as u16)
+			}
+		}
+	}
+	/// Same, when it's known at compile time
+	fn const_size(&self) -> TokenStream {
+		match &self.kind {
+			Kind::Unit => quote!(::core::option::Option::Some(1u16)),
+			Kind::Nested(ty) => quote!(<#ty as ::errors::Discriminant>::DISCRIMINANT_COUNT),
+		}
+	}
+	fn at(&self) -> TokenStream {
+		match self.at {
+			Some(at) => quote!(::core::option::Option::Some(#at as u16)),
+			None => quote!(::core::option::Option::None),
+		}
+	}
+	/// `InvalidBoardCode` -> `invalid board code`
+	fn description(&self) -> String {
+		let name = self.ident.to_string();
+		let chars: Vec<char> = name.chars().collect();
+		let mut out = String::new();
+		for (i, c) in chars.iter().enumerate() {
+			let prev_lower = i > 0 && !chars[i - 1].is_uppercase();
+			let next_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+			if c.is_uppercase() && i > 0 && (prev_lower || next_lower) {
+				out.push(' ');
+			}
+			out.extend(c.to_lowercase());
+		}
+		out
+	}
+}
+
+/// The `N` of `#[discriminant(<name> = N)]`
+fn attribute(attrs: &[Attribute], name: &str) -> syn::Result<Option<u8>> {
+	let mut value = None;
+	for attr in attrs
+		.iter()
+		.filter(|attr| attr.path().is_ident("discriminant"))
+	{
+		attr.parse_nested_meta(|meta| {
+			if !meta.path.is_ident(name) {
+				return Err(meta.error(format!("expected `{name} = <u8>`")));
+			}
+			value = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+			Ok(())
+		})?;
+	}
+	Ok(value)
+}
+
+struct Layout {
+	variants: Vec<Variant>,
+	size: Option<u8>,
+}
+
+impl Layout {
+	fn size(&self) -> TokenStream {
+		match self.size {
+			Some(size) => quote!(::core::option::Option::Some(#size as u16)),
+			None => quote!(::core::option::Option::None),
+		}
+	}
+	/// Where each variant starts, at runtime
+	fn starts(&self) -> TokenStream {
+		let variants = self.variants.iter().map(|variant| {
+			let at = variant.at();
+			let size = variant.size();
+			quote!((#at, ::core::option::Option::Some(#size)))
+		});
+		let size = self.size();
+		quote!(::errors::__derive::layout([#(#variants),*], #size).starts)
+	}
+	fn count(&self, variant_size: impl Fn(&Variant) -> TokenStream) -> TokenStream {
+		let variants = self.variants.iter().map(|variant| {
+			let at = variant.at();
+			let size = variant_size(variant);
+			quote!((#at, #size))
+		});
+		let size = self.size();
+		quote!(::errors::__derive::layout([#(#variants),*], #size).count)
+	}
+}
+
+fn layout(input: &DeriveInput) -> syn::Result<Layout> {
+	let Data::Enum(data) = &input.data else {
+		return Err(syn::Error::new_spanned(
+			&input.ident,
+			"only enums can be errors",
+		));
+	};
+	let variants = data
+		.variants
+		.iter()
+		.map(|variant| {
+			let kind = match &variant.fields {
+				Fields::Unit => Kind::Unit,
+				Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+					Kind::Nested(Box::new(fields.unnamed[0].ty.clone()))
+				}
+				fields => {
+					return Err(syn::Error::new_spanned(
+						fields,
+						"variants have to be unit or hold a single nested error",
+					))
+				}
+			};
+			Ok(Variant {
+				ident: variant.ident.clone(),
+				kind,
+				at: attribute(&variant.attrs, "at")?,
+			})
+		})
+		.collect::<syn::Result<_>>()?;
+	Ok(Layout {
+		variants,
+		size: attribute(&input.attrs, "size")?,
+	})
+}
+
+/// Every type parameter has to be an error too
+fn bounded(generics: &Generics, bound: TokenStream) -> Generics {
+	let mut generics = generics.clone();
+	for param in generics.type_params_mut() {
+		param.bounds.push(parse_quote!(#bound));
+	}
+	generics
+}
+
+fn expand(input: DeriveInput, body: impl FnOnce(&Layout) -> TokenStream) -> TokenStream {
+	match layout(&input) {
+		Ok(layout) => body(&layout),
+		Err(err) => err.to_compile_error(),
+	}
+}
+
+#[proc_macro_derive(Discriminant, attributes(discriminant))]
+pub fn derive_discriminant(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let ident = input.ident.clone();
+	let generics = bounded(&input.generics, quote!(::errors::Discriminant));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+	let is_generic = input.generics.type_params().next().is_some();
+
+	expand(input.clone(), |layout| {
+		let starts = layout.starts();
+		let const_count = layout.count(Variant::const_size);
+		let count = layout.count(|variant| {
+			let size = variant.size();
+			quote!(::core::option::Option::Some(#size))
+		});
+		let arms = layout.variants.iter().enumerate().map(|(i, variant)| {
+			let name = &variant.ident;
+			match variant.kind {
+				Kind::Unit => quote!(Self::#name => starts[#i] as u8,),
+				Kind::Nested(_) => quote! {
+					Self::#name(err) => starts[#i] as u8 + ::errors::Discriminant::discriminant(err),
+				},
+			}
+		});
+		let describe = layout.variants.iter().enumerate().map(|(i, variant)| {
+			let description = variant.description();
+			let prefix = format!("{description}: ");
+			let size = variant.size();
+			let describe_nested = match &variant.kind {
+				Kind::Unit => quote!(f.write_str(#description)),
+				Kind::Nested(ty) => quote! {
+					f.write_str(#prefix)?;
+					<#ty as ::errors::Discriminant>::describe(discriminant - starts[#i] as u8, f)
+				},
+			};
+			quote! {
+				if (discriminant as u16).wrapping_sub(starts[#i]) < #size {
+					return { #describe_nested };
+				}
+			}
+		});
+		// Generic errors are checked once they're used with a type
+		let check = (!is_generic).then(|| {
+			quote! {
+				const _: ::core::option::Option<u16> =
+					<#ident as ::errors::Discriminant>::DISCRIMINANT_COUNT;
+			}
+		});
+
+		quote! {
+			impl #impl_generics ::errors::Discriminant for #ident #ty_generics #where_clause {
+				const DISCRIMINANT_COUNT: ::core::option::Option<u16> = #const_count;
+				fn discriminant(&self) -> u8 {
+					let starts = #starts;
+					match self {
+						#(#arms)*
+					}
+				}
+				fn discriminant_max() -> u8 {
+					// Always known at runtime, and checked to fit
+					#count.unwrap_or(0) as u8
+				}
+				fn describe(
+					discriminant: u8,
+					f: &mut ::core::fmt::Formatter<'_>,
+				) -> ::core::fmt::Result {
+					let starts = #starts;
+					#(#describe)*
+					::core::write!(f, "unknown error {}", discriminant)
+				}
+			}
+			#check
+		}
+	})
+	.into()
+}
+
+#[proc_macro_derive(FromDiscriminant, attributes(discriminant))]
+pub fn derive_from_discriminant(
+	input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let ident = input.ident.clone();
+	let generics = bounded(&input.generics, quote!(::errors::FromDiscriminant));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	expand(input.clone(), |layout| {
+		let starts = layout.starts();
+		let checks = layout.variants.iter().enumerate().map(|(i, variant)| {
+			let name = &variant.ident;
+			let size = variant.size();
+			let error = match &variant.kind {
+				Kind::Unit => quote!(::core::option::Option::Some(Self::#name)),
+				Kind::Nested(ty) => quote! {
+					<#ty as ::errors::FromDiscriminant>::from_discriminant(
+						discriminant - starts[#i] as u8,
+					)
+					.map(Self::#name)
+				},
+			};
+			quote! {
+				if (discriminant as u16).wrapping_sub(starts[#i]) < #size {
+					return #error;
+				}
+			}
+		});
+
+		quote! {
+			impl #impl_generics ::errors::FromDiscriminant for #ident #ty_generics #where_clause {
+				fn from_discriminant(discriminant: u8) -> ::core::option::Option<Self> {
+					let starts = #starts;
+					#(#checks)*
+					::core::option::Option::None
+				}
+			}
+		}
+	})
+	.into()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn descriptions() {
+		let variant = |name: &str| Variant {
+			ident: Ident::new(name, proc_macro2::Span::call_site()),
+			kind: Kind::Unit,
+			at: None,
+		};
+		assert_eq!(
+			variant("InvalidBoardCode").description(),
+			"invalid board code"
+		);
+		assert_eq!(variant("SPIError").description(), "spi error");
+		assert_eq!(variant("Timeout").description(), "timeout");
+	}
+}
@@ -1,6 +1,158 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+use core::fmt;
+use core::marker::PhantomData;
+
+/// Derives lay variants out one after another, nested errors take their whole range
+///
+/// `#[discriminant(at = N)]` on a variant starts it at `N`, `#[discriminant(size = N)]` on
+/// the enum makes it take `N` whatever its variants use. Leave room with them so adding
+/// a variant doesn't move the codes after it, or the codes of errors it's nested in.
+pub use errors_derive::{Discriminant, FromDiscriminant};
+
+/// Squashes an error (and the errors nested in it) into one `u8`, so firmware can
+/// blink it or send it
+///
+/// `#[derive(Discriminant)]` numbers the variants in order and puts a nested error's
+/// whole range at its variant, [`Discriminant::describe`] walks the same ranges back.
 pub trait Discriminant {
 	fn discriminant(&self) -> u8;
+	/// Discriminants of this error are always below this
 	fn discriminant_max() -> u8;
-}
\ No newline at end of file
+	/// [`Discriminant::discriminant_max`] when it's known at compile time
+	///
+	/// Derived errors fill it in, so a layout that doesn't fit in a `u8` doesn't build.
+	const DISCRIMINANT_COUNT: Option<u16> = None;
+	/// Writes what `discriminant` stands for, outermost error first
+	///
+	/// Errors that can't tell just write the number.
+	fn describe(discriminant: u8, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "error {discriminant}")
+	}
+}
+
+/// The error behind a discriminant, for errors that don't carry anything else
+pub trait FromDiscriminant: Discriminant + Sized {
+	/// `None` for numbers that aren't one of ours
+	fn from_discriminant(discriminant: u8) -> Option<Self>;
+}
+
+/// [`Display`](fmt::Display)s a discriminant the way `T` describes it
+///
+/// `describe::<Error<nrf24::Error<..>>>(code).to_string()`
+pub struct Described<T> {
+	discriminant: u8,
+	_error: PhantomData<T>,
+}
+
+pub fn describe<T: Discriminant>(discriminant: u8) -> Described<T> {
+	Described {
+		discriminant,
+		_error: PhantomData,
+	}
+}
+
+impl<T: Discriminant> fmt::Display for Described<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		T::describe(self.discriminant, f)
+	}
+}
+
+/// [`Discriminant::describe`] for errors that implement [`FromDiscriminant`] and `Display`
+pub fn describe_from<T: FromDiscriminant + fmt::Display>(
+	discriminant: u8,
+	f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+	match T::from_discriminant(discriminant) {
+		Some(err) => err.fmt(f),
+		None => write!(f, "unknown error {discriminant}"),
+	}
+}
+
+/// What the derives expand to, not meant to be used directly
+#[doc(hidden)]
+pub mod __derive {
+	pub struct Layout<const N: usize> {
+		/// First discriminant of each variant
+		pub starts: [u16; N],
+		/// None when a nested error's size isn't known yet (at compile time)
+		pub count: Option<u16>,
+	}
+
+	/// Lays out `variants`, each one (explicit start, size), `size` is the enum's own
+	///
+	/// Panics when they overlap or don't fit, at compile time when the sizes are known.
+	pub const fn layout<const N: usize>(
+		variants: [(Option<u16>, Option<u16>); N],
+		size: Option<u16>,
+	) -> Layout<N> {
+		let mut starts = [0u16; N];
+		let mut next = Some(0u16);
+		let mut i = 0;
+		while i < N {
+			let (at, len) = variants[i];
+			let start = match (at, next) {
+				(Some(at), Some(next)) => {
+					assert!(at >= next, "a variant starts inside the one before it");
+					Some(at)
+				}
+				(Some(at), None) => Some(at),
+				(None, next) => next,
+			};
+			if let Some(start) = start {
+				starts[i] = start;
+			}
+			next = match (start, len) {
+				(Some(start), Some(len)) => Some(start.saturating_add(len)),
+				_ => None,
+			};
+			i += 1;
+		}
+		let count = match (next, size) {
+			(Some(next), Some(size)) => {
+				assert!(
+					next <= size,
+					"variants take more discriminants than the error's size"
+				);
+				Some(size)
+			}
+			(None, Some(size)) => Some(size),
+			(next, None) => next,
+		};
+		if let Some(count) = count {
+			assert!(count <= u8::MAX as u16, "discriminants don't fit in a u8");
+		}
+		Layout { starts, count }
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::__derive::layout;
+
+	#[test]
+	fn layouts() {
+		let packed = layout(
+			[(None, Some(2)), (Some(3), Some(1)), (None, Some(1))],
+			Some(20),
+		);
+		assert_eq!(packed.starts, [0, 3, 4]);
+		assert_eq!(packed.count, Some(20));
+		// A nested size only known at runtime
+		let nested = layout([(None, None), (Some(10), Some(1)), (None, None)], None);
+		assert_eq!(nested.starts, [0, 10, 11]);
+		assert_eq!(nested.count, None);
+	}
+
+	#[test]
+	#[should_panic(expected = "starts inside")]
+	fn overlapping_layout() {
+		layout([(None, Some(4)), (Some(3), Some(1))], None);
+	}
+
+	#[test]
+	#[should_panic(expected = "fit in a u8")]
+	fn layout_past_u8() {
+		layout([(None, Some(200)), (None, Some(100))], None);
+	}
+}
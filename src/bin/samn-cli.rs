@@ -0,0 +1,488 @@
+//! Talks to the network through an HQ radio, for debugging without flashing anything
+//!
+//! `samn-cli [--serial <port> | --udp <bind addr> <peer addr>] [--pcap <file>]
+//! [--logs <file>] [--id <command id>] <command>`
+//! - `sniff` decodes and prints everything HQ's radio receives
+//! - `send <addr> info|limbs|toggle <limb>|set <limb> on|off|<json limb type>`
+//!   sends a command to a node address and prints the answer
+//! - `nodes <db_dir>` lists the nodes stored in HQ's database
+//! - `decode <hex>` decodes captured payload (or bare message) bytes
+//!
+//! `--pcap` also writes everything received to a capture, open it with `tools/samn.lua`.
+//! `--logs` takes the `.samn_log` section of the nodes' firmware, `sniff` then also
+//! prints the logs nodes send (see `samn_common::log`).
+//! `send` numbers commands one after the other, the last id is kept in a file in the
+//! temp dir so the node doesn't drop the next one as a repeat. `--id` picks one instead.
+//! The serial port has to be set up beforehand (`stty -F <port> raw 115200`).
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::{Duration, Instant};
+
+use samn_common::json::{to_json, JsonLimbType};
+use samn_common::log::{LogAssembler, LogTable};
+use samn_common::node::{
+	Actuator, Command, Limb, LimbId, LimbType, Message, MessageData, NodeAddress,
+};
+use samn_common::radio::ip::UdpRadio;
+use samn_common::radio::pcap::{Captured, PcapWriter};
+use samn_common::radio::serial::SerialRadio;
+use samn_common::radio::{NoIrq, Payload, Radio, NRF24_HQ_PIPES};
+use samn_common::sonnerie::TimeseriesQuery;
+use samn_common::transport::{RadioTransport, Transport};
+
+const USAGE: &str =
+	"usage: samn-cli [--serial <port> | --udp <bind addr> <peer addr>] [--pcap <file>]
+                [--logs <file>] [--id <0-63>] <command>
+  sniff
+  send <addr> info|limbs|toggle <limb>|set <limb> on|off|<json limb type>
+  nodes <db_dir>
+  decode <hex>";
+
+/// How long `send` waits for the node to answer
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+/// Command ids go up to this
+const COMMAND_ID_MAX: u8 = 63;
+/// In the temp dir, holds the last command id sent
+const LAST_ID_FILE: &str = "samn-cli-command-id";
+
+fn usage() -> ! {
+	eprintln!("{USAGE}");
+	std::process::exit(2)
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+	eprintln!("{message}");
+	std::process::exit(1)
+}
+
+struct StdDelay;
+impl embedded_hal::delay::DelayNs for StdDelay {
+	fn delay_ns(&mut self, ns: u32) {
+		std::thread::sleep(Duration::from_nanos(ns as u64));
+	}
+}
+
+/// Accepts `0x` prefixes and spaces or colons between bytes
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+	let hex: String = hex
+		.split_whitespace()
+		.flat_map(|part| part.split(':'))
+		.map(|part| part.trim_start_matches("0x"))
+		.collect();
+	if !hex.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+		.collect()
+}
+
+/// `0x0204` or `516`
+fn parse_address(address: &str) -> Option<NodeAddress> {
+	match address.strip_prefix("0x") {
+		Some(hex) => NodeAddress::from_str_radix(hex, 16).ok(),
+		None => address.parse().ok(),
+	}
+}
+
+fn parse_command(args: &[String]) -> Option<Command> {
+	let limb_id =
+		|arg: Option<&String>| -> Option<LimbId> { arg?.parse().ok().filter(|id| *id < 16) };
+	let command = match args.first()?.as_str() {
+		"info" => Command::Info,
+		"limbs" => Command::Limbs,
+		"toggle" => Command::ToggleLimb(limb_id(args.get(1))?),
+		"set" => {
+			let limb_id = limb_id(args.get(1))?;
+			let value = args.get(2)?;
+			let limb_type = match value.to_ascii_lowercase().as_str() {
+				"on" => LimbType::Actuator(Actuator::Light(true)),
+				"off" => LimbType::Actuator(Actuator::Light(false)),
+				_ => serde_json::from_str::<JsonLimbType>(value)
+					.ok()?
+					.try_into()
+					.ok()?,
+			};
+			Command::SetLimb(Limb(limb_id, limb_type))
+		}
+		_ => return None,
+	};
+	Some(command)
+}
+
+/// The id after `last`, wraps around
+fn next_id(last: Option<u8>) -> u8 {
+	match last {
+		Some(last) if last < COMMAND_ID_MAX => last + 1,
+		_ => 0,
+	}
+}
+
+/// The id after the one last sent, from any run
+fn command_id() -> u8 {
+	let path = std::env::temp_dir().join(LAST_ID_FILE);
+	let last = std::fs::read_to_string(&path)
+		.ok()
+		.and_then(|last| last.trim().parse().ok());
+	let id = next_id(last);
+	if let Err(err) = std::fs::write(&path, id.to_string()) {
+		eprintln!("can't keep the command id in {}: {err}", path.display());
+	}
+	id
+}
+
+fn print_payload(payload: &Payload) {
+	let mut header = format!("pipe 0x{:02x}", payload.pipe());
+	if let Some(address) = payload.address() {
+		header += &format!(" addr 0x{address:04x}");
+	}
+	if let Some(sequence) = payload.sequence() {
+		header += &format!(" seq {sequence}");
+	}
+	match Message::deserialize_from_bytes(payload.data()) {
+		Ok((message, _)) => println!("{header} {}", to_json(&message)),
+		Err(err) => println!(
+			"{header} undecodable ({err:?}): {}",
+			payload
+				.data()
+				.iter()
+				.map(|b| format!("{b:02x}"))
+				.collect::<String>()
+		),
+	}
+}
+
+fn decode(hex: &str) {
+	let Some(bytes) = parse_hex(hex) else {
+		fail("not hex")
+	};
+	if let Some(payload) = Payload::from_bytes(&bytes) {
+		print_payload(&payload);
+		return;
+	}
+	match Message::deserialize_from_bytes(&bytes) {
+		Ok((message, _)) => println!("{}", to_json(&message)),
+		Err(err) => fail(format!("neither a payload nor a message: {err:?}")),
+	}
+}
+
+fn nodes(dir: &str) {
+	let query = TimeseriesQuery::open(dir.as_ref())
+		.unwrap_or_else(|err| fail(format!("can't open {dir}: {err}")));
+	println!(
+		"{:>10}  {:>7}  {:<12}  last heartbeat",
+		"node", "addr", "board"
+	);
+	for node in query.nodes() {
+		let address = node
+			.address
+			.map(|address| format!("0x{address:04x}"))
+			.unwrap_or_default();
+		let board = node
+			.info
+			.map(|info| format!("{:?}", info.board))
+			.unwrap_or_default();
+		let heartbeat = node
+			.last_heartbeat
+			.map(|at| samn_common::sonnerie::timestamp_to_datetime(at).to_string())
+			.unwrap_or_default();
+		println!(
+			"{:>10}  {address:>7}  {board:<12}  {heartbeat}",
+			node.node_id
+		);
+	}
+}
+
+/// Waits for whatever `receive` gives back
+fn poll<T>(
+	mut receive: impl FnMut() -> nb::Result<T, ()>,
+	until: Option<Instant>,
+) -> Option<T> {
+	loop {
+		match receive() {
+			Ok(value) => return Some(value),
+			Err(nb::Error::WouldBlock) => {}
+			Err(nb::Error::Other(())) => return None,
+		}
+		if until.is_some_and(|until| Instant::now() >= until) {
+			return None;
+		}
+		std::thread::sleep(Duration::from_millis(2));
+	}
+}
+
+/// Captures to `path` when there is one
+fn capture<R>(radio: R, path: Option<&str>) -> Captured<R, BufWriter<File>> {
+	let pcap = path.map(|path| {
+		File::create(path)
+			.and_then(|file| PcapWriter::new(BufWriter::new(file)))
+			.unwrap_or_else(|err| fail(format!("can't write {path}: {err}")))
+	});
+	Captured::new(radio, pcap)
+}
+
+/// `objcopy -O binary --only-section=.samn_log firmware.elf <path>`
+fn log_table(path: &str) -> LogTable {
+	let section =
+		std::fs::read(path).unwrap_or_else(|err| fail(format!("can't read {path}: {err}")));
+	LogTable::from_section(&section).unwrap_or_else(|err| fail(format!("{path}: {err}")))
+}
+
+fn sniff<E: Debug, R: Radio<E>>(
+	mut radio: Captured<R, BufWriter<File>>,
+	rx_pipes: &[u8],
+	logs: Option<LogTable>,
+) {
+	let mut assembler = LogAssembler::new();
+	radio
+		.set_rx_filter(rx_pipes)
+		.and_then(|_| radio.to_rx())
+		.unwrap_or_else(|err| fail(format!("radio error: {err:?}")));
+	loop {
+		let payload = poll(
+			|| {
+				radio
+					.receive(&mut NoIrq, None)
+					.map_err(|err| err.map(|err| eprintln!("radio error: {err:?}")))
+			},
+			None,
+		);
+		match payload {
+			Some(payload) => {
+				print_payload(&payload);
+				if let (Some(table), Ok((Message::Log(node_id, chunk), _))) =
+					(&logs, Message::deserialize_from_bytes(payload.data()))
+				{
+					if let Some(log) = assembler.push(node_id, &chunk) {
+						println!("log {node_id}: {}", table.format(&log));
+					}
+				}
+			}
+			None => std::process::exit(1),
+		}
+		if let Some(err) = radio.take_error() {
+			eprintln!("capture stopped: {err}");
+		}
+	}
+}
+
+fn send<E: Debug, R: Radio<E>>(
+	mut radio: R,
+	address: NodeAddress,
+	command: Command,
+	id: u8,
+) {
+	radio
+		.set_rx_filter(&NRF24_HQ_PIPES)
+		.unwrap_or_else(|err| fail(format!("radio error: {err:?}")));
+	let message = Message::Message(MessageData::Command { id, command });
+
+	let mut transport = RadioTransport::hq(radio, NoIrq, StdDelay);
+	match transport.send(address, &message) {
+		Ok(true) => {}
+		Ok(false) => eprintln!("not acked, waiting for an answer anyway"),
+		Err(_) => fail("sending failed"),
+	}
+	if let Err(err) = transport.radio().to_rx() {
+		fail(format!("radio error: {err:?}"))
+	}
+
+	let until = Instant::now() + ANSWER_TIMEOUT;
+	let answer = poll(
+		|| match transport.receive() {
+			Ok((
+				from,
+				Message::Message(MessageData::Response {
+					id: Some(to),
+					response,
+				}),
+			)) if from == address && to == id => Ok(response),
+			// Not our answer, or not decodable
+			Ok(_) | Err(nb::Error::Other(_)) => Err(nb::Error::WouldBlock),
+			Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
+		},
+		Some(until),
+	);
+	match answer {
+		Some(response) => println!(
+			"{}",
+			to_json(&Message::Message(MessageData::Response {
+				id: Some(id),
+				response
+			}))
+		),
+		None => fail("no answer"),
+	}
+}
+
+enum Link {
+	Serial(String),
+	Udp(String, String),
+}
+
+fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let mut link = None;
+	let mut pcap = None;
+	let mut logs = None;
+	let mut id = None;
+	let mut rest = args.as_slice();
+	loop {
+		match rest {
+			[flag, port, tail @ ..] if flag == "--serial" => {
+				link = Some(Link::Serial(port.clone()));
+				rest = tail;
+			}
+			[flag, bind, peer, tail @ ..] if flag == "--udp" => {
+				link = Some(Link::Udp(bind.clone(), peer.clone()));
+				rest = tail;
+			}
+			[flag, path, tail @ ..] if flag == "--pcap" => {
+				pcap = Some(path.clone());
+				rest = tail;
+			}
+			[flag, path, tail @ ..] if flag == "--logs" => {
+				logs = Some(log_table(path));
+				rest = tail;
+			}
+			[flag, value, tail @ ..] if flag == "--id" => {
+				id = Some(
+					value
+						.parse()
+						.ok()
+						.filter(|id| *id <= COMMAND_ID_MAX)
+						.unwrap_or_else(|| usage()),
+				);
+				rest = tail;
+			}
+			[flag, ..] if flag == "-h" || flag == "--help" => {
+				println!("{USAGE}");
+				return;
+			}
+			_ => break,
+		}
+	}
+
+	// These don't need a radio
+	match rest {
+		[command, hex @ ..] if command == "decode" && !hex.is_empty() => {
+			return decode(&hex.join(" "))
+		}
+		[command, dir] if command == "nodes" => return nodes(dir),
+		_ => {}
+	}
+
+	let to_send = match rest {
+		[command, address, args @ ..] if command == "send" => Some((
+			parse_address(address).unwrap_or_else(|| usage()),
+			parse_command(args).unwrap_or_else(|| usage()),
+			id.unwrap_or_else(command_id),
+		)),
+		[command] if command == "sniff" => None,
+		_ => usage(),
+	};
+
+	match link {
+		Some(Link::Serial(port)) => {
+			let port = std::fs::OpenOptions::new()
+				.read(true)
+				.write(true)
+				.open(&port)
+				.unwrap_or_else(|err| fail(format!("can't open {port}: {err}")));
+			let mut radio = capture(SerialRadio::new(port), pcap.as_deref());
+			if let Err(err) = radio.init(&mut StdDelay) {
+				fail(format!("dongle didn't init: {err:?}"))
+			}
+			match to_send {
+				Some((address, command, id)) => send(radio, address, command, id),
+				// What HQ's radio can hear
+				None => sniff(radio, &NRF24_HQ_PIPES, logs),
+			}
+		}
+		Some(Link::Udp(bind, peer)) => {
+			let peer = peer
+				.parse()
+				.unwrap_or_else(|err| fail(format!("bad peer address {peer}: {err}")));
+			let radio = UdpRadio::bind(&bind, peer)
+				.unwrap_or_else(|err| fail(format!("can't bind {bind}: {err}")));
+			let radio = capture(radio, pcap.as_deref());
+			match to_send {
+				Some((address, command, id)) => send(radio, address, command, id),
+				// Every pipe
+				None => sniff(radio, &[], logs),
+			}
+		}
+		None => usage(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn args(args: &str) -> Vec<String> {
+		args.split_whitespace().map(String::from).collect()
+	}
+
+	#[test]
+	fn parses_hex() {
+		assert_eq!(
+			parse_hex("0x98 0x04:02 ab"),
+			Some(vec![0x98, 0x04, 0x02, 0xab])
+		);
+		assert_eq!(parse_hex("9804"), Some(vec![0x98, 0x04]));
+		assert_eq!(parse_hex(""), Some(vec![]));
+		assert_eq!(parse_hex("980"), None);
+		assert_eq!(parse_hex("zz"), None);
+		assert_eq!(parse_hex("é"), None);
+	}
+
+	#[test]
+	fn parses_addresses() {
+		assert_eq!(parse_address("0x0204"), Some(0x0204));
+		assert_eq!(parse_address("516"), Some(0x0204));
+		assert_eq!(parse_address("0x10000"), None);
+		assert_eq!(parse_address("node"), None);
+	}
+
+	#[test]
+	fn parses_commands() {
+		assert_eq!(parse_command(&args("info")), Some(Command::Info));
+		assert_eq!(parse_command(&args("limbs")), Some(Command::Limbs));
+		assert_eq!(
+			parse_command(&args("toggle 3")),
+			Some(Command::ToggleLimb(3))
+		);
+		assert_eq!(
+			parse_command(&args("set 2 ON")),
+			Some(Command::SetLimb(Limb(
+				2,
+				LimbType::Actuator(Actuator::Light(true))
+			)))
+		);
+		assert_eq!(
+			parse_command(&args("set 2 off")),
+			Some(Command::SetLimb(Limb(
+				2,
+				LimbType::Actuator(Actuator::Light(false))
+			)))
+		);
+		assert_eq!(parse_command(&args("toggle 16")), None);
+		assert_eq!(parse_command(&args("toggle")), None);
+		assert_eq!(parse_command(&args("set 2")), None);
+		assert_eq!(parse_command(&args("set 2 {")), None);
+		assert_eq!(parse_command(&args("reboot")), None);
+		assert_eq!(parse_command(&[]), None);
+	}
+
+	#[test]
+	fn command_ids_go_round() {
+		assert_eq!(next_id(None), 0);
+		assert_eq!(next_id(Some(0)), 1);
+		assert_eq!(next_id(Some(COMMAND_ID_MAX)), 0);
+		// Garbage in the file
+		assert_eq!(next_id(Some(200)), 0);
+	}
+}
@@ -0,0 +1,70 @@
+//! Exports stored node telemetry as CSV or newline delimited JSON
+//!
+//! `samn-export <db_dir> [--prefix node/<id>/] [--from <unix secs>] [--to <unix secs>] [--json]`
+use std::path::PathBuf;
+
+use samn_common::node::Timestamp;
+use samn_common::sonnerie::{export, ExportFormat};
+
+const USAGE: &str =
+	"usage: samn-export <db_dir> [--prefix node/<id>/] [--from <unix secs>] [--to <unix secs>] [--json]";
+
+fn usage() -> ! {
+	eprintln!("{USAGE}");
+	std::process::exit(2)
+}
+
+fn main() {
+	let mut args = std::env::args().skip(1);
+	let mut dir: Option<PathBuf> = None;
+	let mut prefix = String::from("node/");
+	let mut range = 0..Timestamp::MAX;
+	let mut format = ExportFormat::Csv;
+
+	// A flag's value, not the next flag
+	let value = |value: Option<String>| -> String {
+		value
+			.filter(|v| !v.starts_with("--"))
+			.unwrap_or_else(|| usage())
+	};
+	let timestamp =
+		|v: Option<String>| -> Timestamp { value(v).parse().unwrap_or_else(|_| usage()) };
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--prefix" => prefix = value(args.next()),
+			"--from" => range.start = timestamp(args.next()),
+			"--to" => range.end = timestamp(args.next()),
+			"--json" => format = ExportFormat::Ndjson,
+			"-h" | "--help" => {
+				println!("{USAGE}");
+				return;
+			}
+			flag if flag.starts_with("--") => usage(),
+			_ => dir = Some(arg.into()),
+		}
+	}
+	let Some(dir) = dir else { usage() };
+
+	let reader = match sonnerie::DatabaseReader::new(&dir) {
+		Ok(reader) => reader,
+		Err(err) => {
+			eprintln!("can't open {}: {err}", dir.display());
+			std::process::exit(1)
+		}
+	};
+	let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+	match export(&reader, &prefix, range, format, &mut out) {
+		Ok(summary) => {
+			if summary.skipped > 0 {
+				eprintln!(
+					"skipped {} records that couldn't be decoded",
+					summary.skipped
+				);
+			}
+		}
+		Err(err) => {
+			eprintln!("export failed: {err}");
+			std::process::exit(1)
+		}
+	}
+}
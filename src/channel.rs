@@ -0,0 +1,127 @@
+use crate::node::Timestamp;
+
+/// Node side of [`Command::ChangeChannel`](crate::node::Command::ChangeChannel)
+///
+/// Holds the channel change until the switch time, so every node moves
+/// at the same moment as HQ.
+#[derive(Clone, Debug)]
+pub struct ChannelSwitch {
+	current: u8,
+	pending: Option<(u8, Timestamp)>,
+}
+
+impl ChannelSwitch {
+	pub const fn new(channel: u8) -> Self {
+		Self {
+			current: channel,
+			pending: None,
+		}
+	}
+	pub fn current(&self) -> u8 {
+		self.current
+	}
+	pub fn pending(&self) -> Option<(u8, Timestamp)> {
+		self.pending
+	}
+
+	/// A newer change replaces one that hasn't happened yet
+	pub fn schedule(&mut self, channel: u8, at: Timestamp) {
+		self.pending = Some((channel, at));
+	}
+
+	/// The channel to put the radio on, once `now` reaches the switch time
+	///
+	/// Call with the node's clock ([`Clock::now`](crate::clock::Clock::now)),
+	/// a node that isn't synced should switch right away with `Timestamp::MAX`.
+	pub fn poll(&mut self, now: Timestamp) -> Option<u8> {
+		let (channel, at) = self.pending?;
+		if now < at {
+			return None;
+		}
+		self.pending = None;
+		self.current = channel;
+		Some(channel)
+	}
+}
+
+/// HQ side, picks the quietest channel out of `(channel, energy)` scan results
+///
+/// `current` is kept unless another channel is quieter by more than `hysteresis`,
+/// so the network doesn't move around for nothing.
+pub fn quietest_channel(scan: &[(u8, u8)], current: u8, hysteresis: u8) -> u8 {
+	let current_energy = scan
+		.iter()
+		.find(|(channel, _)| *channel == current)
+		.map(|(_, energy)| *energy);
+	let Some((quietest, energy)) = scan.iter().min_by_key(|(_, energy)| *energy) else {
+		return current;
+	};
+	match current_energy {
+		Some(current_energy) if current_energy <= energy.saturating_add(hysteresis) => {
+			current
+		}
+		_ => *quietest,
+	}
+}
+
+/// Channels to look for HQ on while joining
+///
+/// The last channel we knew HQ was on goes first, then `channels` in order,
+/// round and round. Take as many as there's time for.
+#[derive(Clone, Debug)]
+pub struct ChannelSearch<'a> {
+	last: Option<u8>,
+	channels: &'a [u8],
+	next: usize,
+}
+
+impl<'a> ChannelSearch<'a> {
+	pub fn new(last: u8, channels: &'a [u8]) -> Self {
+		Self {
+			last: Some(last),
+			channels,
+			next: 0,
+		}
+	}
+}
+
+impl Iterator for ChannelSearch<'_> {
+	type Item = u8;
+
+	fn next(&mut self) -> Option<u8> {
+		if let Some(last) = self.last.take() {
+			return Some(last);
+		}
+		if self.channels.is_empty() {
+			return None;
+		}
+		let channel = self.channels[self.next % self.channels.len()];
+		self.next = (self.next + 1) % self.channels.len();
+		Some(channel)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn channel_changes() {
+		let mut switch = ChannelSwitch::new(76);
+		assert_eq!(switch.poll(1000), None);
+		switch.schedule(110, 1060);
+		assert_eq!(switch.poll(1059), None);
+		assert_eq!(switch.current(), 76);
+		assert_eq!(switch.poll(1060), Some(110));
+		assert_eq!(switch.poll(1061), None);
+		assert_eq!(switch.current(), 110);
+
+		let scan = [(76, 120), (90, 30), (110, 40)];
+		assert_eq!(quietest_channel(&scan, 76, 20), 90);
+		assert_eq!(quietest_channel(&scan, 110, 20), 110);
+		assert_eq!(quietest_channel(&[], 110, 20), 110);
+
+		let search: Vec<_> = ChannelSearch::new(110, &[76, 90]).take(5).collect();
+		assert_eq!(search, [110, 76, 90, 76, 90]);
+	}
+}
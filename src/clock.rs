@@ -0,0 +1,154 @@
+use crate::node::{TimeSync, Timestamp};
+
+/// Syncs closer than this don't give a meaningful drift measurement
+const DRIFT_WINDOW_MIN_MS: u64 = 10 * 60 * 1000;
+/// Anything past this is a bad sync (or a reset node), not drift
+const DRIFT_PPM_MAX: i64 = 50_000;
+
+/// Node side wall clock
+///
+/// Keeps the epoch time HQ gave us on the last [`TimeSync`] and the node's
+/// free running ms counter at that moment. Between syncs it measures how much
+/// the local counter drifts from HQ's clock and corrects for it.
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+	/// Local ms counter at the last sync
+	local_at_sync: u32,
+	/// Epoch ms at the last sync
+	epoch_at_sync: u64,
+	/// Local counter drift in parts per million, positive when the local counter runs fast
+	///
+	/// None until there's been a measurement
+	drift_ppm: Option<i32>,
+	synced: bool,
+}
+
+impl Clock {
+	pub const fn new() -> Self {
+		Self {
+			local_at_sync: 0,
+			epoch_at_sync: 0,
+			drift_ppm: None,
+			synced: false,
+		}
+	}
+
+	/// Sets the clock from HQ's time, false if `time` isn't valid (millis past 999)
+	///
+	/// `local_ms` is the node's ms counter when the sync was received, it's fine for it to wrap
+	/// as long as syncs happen more often than every ~49 days.
+	pub fn sync(&mut self, local_ms: u32, time: &TimeSync) -> bool {
+		if time.millis >= 1000 {
+			return false;
+		}
+		let epoch_ms = time.epoch_ms();
+		if self.synced {
+			let local_elapsed = local_ms.wrapping_sub(self.local_at_sync) as i64;
+			let epoch_elapsed = epoch_ms.saturating_sub(self.epoch_at_sync);
+			if epoch_elapsed >= DRIFT_WINDOW_MIN_MS {
+				let epoch_elapsed = epoch_elapsed as i64;
+				let drift = (local_elapsed - epoch_elapsed) * 1_000_000 / epoch_elapsed;
+				if drift.abs() <= DRIFT_PPM_MAX {
+					// Average with what we had, one noisy sync shouldn't throw us off
+					self.drift_ppm = Some(match self.drift_ppm {
+						Some(drift_ppm) => ((drift_ppm as i64 + drift) / 2) as i32,
+						None => drift as i32,
+					});
+				}
+			}
+		}
+		self.local_at_sync = local_ms;
+		self.epoch_at_sync = epoch_ms;
+		self.synced = true;
+		true
+	}
+
+	pub fn is_synced(&self) -> bool {
+		self.synced
+	}
+
+	/// Measured drift of the local counter in parts per million
+	pub fn drift_ppm(&self) -> i32 {
+		self.drift_ppm.unwrap_or(0)
+	}
+
+	/// Epoch time in ms, None if we never synced
+	pub fn now_ms(&self, local_ms: u32) -> Option<u64> {
+		if !self.synced {
+			return None;
+		}
+		let elapsed = local_ms.wrapping_sub(self.local_at_sync) as i64;
+		let corrected = elapsed * 1_000_000 / (1_000_000 + self.drift_ppm() as i64);
+		Some(self.epoch_at_sync + corrected.max(0) as u64)
+	}
+
+	/// Epoch time in seconds, None if we never synced
+	pub fn now(&self, local_ms: u32) -> Option<Timestamp> {
+		self.now_ms(local_ms).map(|ms| (ms / 1000) as Timestamp)
+	}
+
+	/// What goes in a [`Response::Heartbeat`](crate::node::Response::Heartbeat), 0 if never synced
+	pub fn heartbeat_timestamp(&self, local_ms: u32) -> Timestamp {
+		self.now(local_ms).unwrap_or(0)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Clock;
+	use crate::node::TimeSync;
+
+	fn sync_at(seconds: u32) -> TimeSync {
+		TimeSync {
+			seconds,
+			millis: 0,
+			latency: 0,
+		}
+	}
+
+	#[test]
+	fn clock_follows_sync() {
+		let mut clock = Clock::new();
+		assert_eq!(clock.now(1000), None);
+
+		clock.sync(
+			1000,
+			&TimeSync {
+				seconds: 1_700_000_000,
+				millis: 500,
+				latency: 20,
+			},
+		);
+		assert_eq!(clock.now_ms(1000), Some(1_700_000_000_520));
+		assert_eq!(clock.now(3000), Some(1_700_000_002));
+		// Local counter wrapping around
+		clock.sync(u32::MAX - 499, &sync_at(1_700_000_000));
+		assert_eq!(clock.now_ms(500), Some(1_700_000_001_000));
+		// Not a valid time, ignored
+		let bad = TimeSync {
+			seconds: 1_800_000_000,
+			millis: 1000,
+			latency: 0,
+		};
+		assert!(!clock.sync(500, &bad));
+		assert_eq!(clock.now_ms(500), Some(1_700_000_001_000));
+	}
+
+	#[test]
+	fn clock_corrects_drift() {
+		let mut clock = Clock::new();
+		clock.sync(0, &sync_at(1_700_000_000));
+		// Local counter runs 1% fast over an hour, the first measurement is taken as is
+		clock.sync(3_636_000, &sync_at(1_700_003_600));
+		assert_eq!(clock.drift_ppm(), 10_000);
+		// Then 0.5% fast, averaged
+		clock.sync(3_636_000 + 3_618_000, &sync_at(1_700_007_200));
+		assert_eq!(clock.drift_ppm(), 7_500);
+		// Reset, a node that was off for a while shouldn't look like drift
+		clock.sync(0, &sync_at(1_700_100_000));
+		assert_eq!(clock.drift_ppm(), 7_500);
+
+		// An hour on HQ's clock is 3_627_000 local ms at 7500ppm
+		assert_eq!(clock.now(3_627_000), Some(1_700_103_600));
+	}
+}
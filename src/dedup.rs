@@ -0,0 +1,196 @@
+//! Dropping messages we've already seen
+//!
+//! An nrf24 ack can get lost after the data made it, then the sender sends again and
+//! the message would be handled twice. Payloads carry a sequence number
+//! ([`Payload::set_sequence`](crate::radio::Payload::set_sequence)) that stays the same
+//! on retries, [`DedupCache`] remembers the last one from each address.
+//!
+//! Commands that aren't safe to run twice ([`Command::is_idempotent`]) are also
+//! checked by command id with [`CommandDedup`], for retries that came with a new sequence.
+use crate::node::{Command, NodeAddress};
+
+/// Next sequence number to send with, wraps around
+#[derive(Clone, Debug, Default)]
+pub struct Sequence(u8);
+
+impl Sequence {
+	pub const fn new() -> Self {
+		Self(0)
+	}
+	/// The number to send with, then moves on
+	pub fn advance(&mut self) -> u8 {
+		let sequence = self.0;
+		self.0 = self.0.wrapping_add(1);
+		sequence
+	}
+}
+
+/// A [`Sequence`] for each of up to `N` addresses we send to
+///
+/// Each receiver sees its own numbers go up one at a time. When it's full,
+/// the address that was sent to longest ago is forgotten and starts over.
+#[derive(Clone, Debug)]
+pub struct Sequences<const N: usize> {
+	/// Most recent first
+	entries: [(NodeAddress, u8); N],
+	len: usize,
+}
+
+impl<const N: usize> Default for Sequences<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> Sequences<N> {
+	pub const fn new() -> Self {
+		Self {
+			entries: [(0, 0); N],
+			len: 0,
+		}
+	}
+
+	pub fn contains(&self, address: NodeAddress) -> bool {
+		self.entries[..self.len].iter().any(|(a, _)| *a == address)
+	}
+
+	/// Starts numbering for `address`, if it isn't already
+	pub fn insert(&mut self, address: NodeAddress) {
+		if !self.contains(address) && N > 0 {
+			self.len = (self.len + 1).min(N);
+			self.entries.copy_within(..self.len - 1, 1);
+			self.entries[0] = (address, 0);
+		}
+	}
+
+	/// The number to send `address` with, then moves on, `None` if it wasn't inserted
+	pub fn advance(&mut self, address: NodeAddress) -> Option<u8> {
+		let i = self.entries[..self.len]
+			.iter()
+			.position(|(a, _)| *a == address)?;
+		let (_, sequence) = self.entries[i];
+		// Move it to the front
+		self.entries.copy_within(..i, 1);
+		self.entries[0] = (address, sequence.wrapping_add(1));
+		Some(sequence)
+	}
+}
+
+/// The last sequence number from up to `N` addresses
+///
+/// When it's full, the address that was heard from longest ago is forgotten.
+#[derive(Clone, Debug)]
+pub struct DedupCache<const N: usize> {
+	/// Most recent first
+	entries: [(NodeAddress, u8); N],
+	len: usize,
+}
+
+impl<const N: usize> Default for DedupCache<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> DedupCache<N> {
+	pub const fn new() -> Self {
+		Self {
+			entries: [(0, 0); N],
+			len: 0,
+		}
+	}
+
+	/// Whether `sequence` is the same as the last one from `address`, remembers it if not
+	pub fn is_repeat(&mut self, address: NodeAddress, sequence: u8) -> bool {
+		let found = self.entries[..self.len]
+			.iter()
+			.position(|(a, _)| *a == address);
+		let i = match found {
+			Some(i) if self.entries[i].1 == sequence => return true,
+			Some(i) => i,
+			None if N == 0 => return false,
+			None => {
+				self.len = (self.len + 1).min(N);
+				self.len - 1
+			}
+		};
+		// Move it to the front
+		self.entries.copy_within(..i, 1);
+		self.entries[0] = (address, sequence);
+		false
+	}
+
+	pub fn clear(&mut self) {
+		self.len = 0;
+	}
+}
+
+/// Node side, makes commands that aren't idempotent run once per command id
+///
+/// HQ sends a retried command with the same id, and a new command with a new one.
+#[derive(Clone, Debug, Default)]
+pub struct CommandDedup {
+	last: Option<u8>,
+}
+
+impl CommandDedup {
+	pub const fn new() -> Self {
+		Self { last: None }
+	}
+
+	/// Whether to run `command`, `false` for a repeat of the last one that isn't idempotent
+	pub fn should_run(&mut self, id: u8, command: &Command) -> bool {
+		if command.is_idempotent() {
+			return true;
+		}
+		if self.last == Some(id) {
+			return false;
+		}
+		self.last = Some(id);
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn drops_repeats() {
+		let mut cache = DedupCache::<2>::new();
+		assert!(!cache.is_repeat(0x0204, 1));
+		assert!(cache.is_repeat(0x0204, 1));
+		assert!(!cache.is_repeat(0x0305, 1));
+		assert!(!cache.is_repeat(0x0204, 2));
+		// 0x0305 is the oldest, it's forgotten
+		assert!(!cache.is_repeat(0x0406, 7));
+		assert!(!cache.is_repeat(0x0305, 1));
+		assert!(cache.is_repeat(0x0406, 7));
+
+		let mut sequence = Sequence(255);
+		assert_eq!(sequence.advance(), 255);
+		assert_eq!(sequence.advance(), 0);
+
+		let mut sequences = Sequences::<2>::new();
+		assert_eq!(sequences.advance(0x0204), None);
+		sequences.insert(0x0204);
+		sequences.insert(0x0305);
+		assert_eq!(sequences.advance(0x0204), Some(0));
+		assert_eq!(sequences.advance(0x0204), Some(1));
+		assert_eq!(sequences.advance(0x0305), Some(0));
+		// Already numbered, it keeps going
+		sequences.insert(0x0305);
+		assert_eq!(sequences.advance(0x0305), Some(1));
+		// 0x0204 was sent to longest ago
+		sequences.insert(0x0406);
+		assert!(!sequences.contains(0x0204));
+		assert_eq!(sequences.advance(0x0305), Some(2));
+		assert_eq!(sequences.advance(0x0406), Some(0));
+
+		let mut commands = CommandDedup::new();
+		assert!(commands.should_run(3, &Command::ToggleLimb(1)));
+		assert!(!commands.should_run(3, &Command::ToggleLimb(1)));
+		assert!(commands.should_run(3, &Command::Limbs));
+		assert!(commands.should_run(4, &Command::ToggleLimb(1)));
+	}
+}
@@ -0,0 +1,251 @@
+//! Node side log of the errors it ran into, to report them to HQ later
+//!
+//! Errors are kept as their [`Discriminant`], the same error happening again only bumps
+//! its count. [`FaultLog::to_bytes`] and [`FaultLog::from_bytes`] are there to keep the
+//! log across resets (noinit RAM, eeprom), so the fault that made the watchdog bite
+//! still gets reported once the node is back up.
+use errors::Discriminant;
+
+use crate::node::{Fault, Message, NodeAddress, NodeId, ResetReason};
+use crate::transport::Transport;
+
+/// Start of a saved log, anything else is garbage (first boot, RAM lost power)
+const MAGIC: [u8; 2] = [0x5A, 0xF1];
+/// code (1) + count (2) + uptime (4) + reset (1)
+const FAULT_BYTES: usize = 8;
+
+/// The last `N` different faults, oldest first
+///
+/// When it's full the oldest fault is dropped to make room.
+#[derive(Clone, Debug)]
+pub struct FaultLog<const N: usize> {
+	faults: [Fault; N],
+	len: usize,
+	/// Why the current run started
+	reset: ResetReason,
+}
+
+impl<const N: usize> Default for FaultLog<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> FaultLog<N> {
+	/// Size of [`FaultLog::to_bytes`]
+	pub const BYTES: usize = MAGIC.len() + 1 + 1 + N * FAULT_BYTES + 2;
+
+	pub const fn new() -> Self {
+		Self {
+			faults: [Fault {
+				code: 0,
+				count: 0,
+				uptime: 0,
+				reset: ResetReason::Unknown,
+			}; N],
+			len: 0,
+			reset: ResetReason::Unknown,
+		}
+	}
+
+	/// Call once on startup, faults recorded from now on carry `reason`
+	pub fn boot(&mut self, reason: ResetReason) {
+		self.reset = reason;
+	}
+
+	/// `uptime` in seconds since boot
+	pub fn record(&mut self, code: u8, uptime: u32) {
+		if N == 0 {
+			return;
+		}
+		let reset = self.reset;
+		if let Some(fault) = self.faults[..self.len]
+			.iter_mut()
+			.find(|fault| fault.code == code)
+		{
+			fault.count = fault.count.saturating_add(1);
+			fault.uptime = uptime;
+			fault.reset = reset;
+			return;
+		}
+		if self.len == N {
+			self.faults.copy_within(1.., 0);
+			self.len -= 1;
+		}
+		self.faults[self.len] = Fault {
+			code,
+			count: 1,
+			uptime,
+			reset,
+		};
+		self.len += 1;
+	}
+
+	pub fn record_error<E: Discriminant>(&mut self, err: &E, uptime: u32) {
+		self.record(err.discriminant(), uptime)
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+	pub fn clear(&mut self) {
+		self.len = 0;
+	}
+
+	/// Faults oldest first
+	pub fn iter(&self) -> impl Iterator<Item = &Fault> {
+		self.faults[..self.len].iter()
+	}
+	pub fn first(&self) -> Option<&Fault> {
+		self.iter().next()
+	}
+	pub fn pop_first(&mut self) -> Option<Fault> {
+		let fault = *self.first()?;
+		self.faults.copy_within(1..self.len, 0);
+		self.len -= 1;
+		Some(fault)
+	}
+
+	/// Sends a [`Message::FaultReport`] per fault, oldest first, and forgets the ones
+	/// that were acked
+	///
+	/// Stops at the first one that isn't acked, the rest stay for next time.
+	/// Gives back how many went through.
+	pub fn send_reports<T: Transport>(
+		&mut self,
+		transport: &mut T,
+		address: NodeAddress,
+		node_id: NodeId,
+	) -> Result<usize, T::Error> {
+		let mut sent = 0;
+		while let Some(fault) = self.first() {
+			if !transport.send(address, &Message::FaultReport(node_id, *fault))? {
+				break;
+			}
+			self.pop_first();
+			sent += 1;
+		}
+		Ok(sent)
+	}
+
+	/// Writes the log to the start of `bytes`, which has to be at least [`Self::BYTES`] long
+	pub fn to_bytes(&self, bytes: &mut [u8]) {
+		let bytes = &mut bytes[..Self::BYTES];
+		bytes[..2].copy_from_slice(&MAGIC);
+		bytes[2] = self.len as u8;
+		bytes[3] = self.reset.code();
+		for (fault, chunk) in self
+			.faults
+			.iter()
+			.zip(bytes[4..].chunks_exact_mut(FAULT_BYTES))
+		{
+			chunk[0] = fault.code;
+			chunk[1..3].copy_from_slice(&fault.count.to_le_bytes());
+			chunk[3..7].copy_from_slice(&fault.uptime.to_le_bytes());
+			chunk[7] = fault.reset.code();
+		}
+		let checksum = fletcher16(&bytes[..Self::BYTES - 2]);
+		bytes[Self::BYTES - 2..].copy_from_slice(&checksum.to_le_bytes());
+	}
+
+	/// A log saved with [`Self::to_bytes`], an empty one if `bytes` doesn't hold one
+	pub fn from_bytes(bytes: &[u8]) -> Self {
+		let mut log = Self::new();
+		let Some(bytes) = bytes.get(..Self::BYTES) else {
+			return log;
+		};
+		let (data, checksum) = bytes.split_at(Self::BYTES - 2);
+		if data[..2] != MAGIC
+			|| checksum != fletcher16(data).to_le_bytes()
+			|| data[2] as usize > N
+		{
+			return log;
+		}
+		log.len = data[2] as usize;
+		log.reset = ResetReason::from_code(data[3]);
+		for (fault, chunk) in log
+			.faults
+			.iter_mut()
+			.zip(data[4..].chunks_exact(FAULT_BYTES))
+		{
+			*fault = Fault {
+				code: chunk[0],
+				count: u16::from_le_bytes([chunk[1], chunk[2]]),
+				uptime: u32::from_le_bytes([chunk[3], chunk[4], chunk[5], chunk[6]]),
+				reset: ResetReason::from_code(chunk[7]),
+			};
+		}
+		log
+	}
+}
+
+fn fletcher16(bytes: &[u8]) -> u16 {
+	let (mut a, mut b) = (0u16, 0u16);
+	for byte in bytes {
+		a = (a + *byte as u16) % 255;
+		b = (b + a) % 255;
+	}
+	(b << 8) | a
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Acks everything but the `fail_at`th message
+	struct Acking {
+		sent: usize,
+		fail_at: usize,
+	}
+	impl Transport for Acking {
+		type Error = ();
+		fn send(&mut self, _: NodeAddress, message: &Message) -> Result<bool, ()> {
+			assert!(matches!(message, Message::FaultReport(7, _)));
+			self.sent += 1;
+			Ok(self.sent != self.fail_at)
+		}
+		fn receive(&mut self) -> nb::Result<(NodeAddress, Message), ()> {
+			Err(nb::Error::WouldBlock)
+		}
+	}
+
+	#[test]
+	fn faults_survive_and_get_reported() {
+		let mut log = FaultLog::<3>::new();
+		log.boot(ResetReason::PowerOn);
+		log.record(1, 10);
+		log.record(2, 20);
+		log.record(1, 30);
+		log.boot(ResetReason::Watchdog);
+		log.record(3, 5);
+		// Full, drops the oldest
+		log.record(4, 6);
+		let codes: Vec<_> = log.iter().map(|fault| (fault.code, fault.count)).collect();
+		assert_eq!(codes, [(2, 1), (3, 1), (4, 1)]);
+		assert!(matches!(log.first().unwrap().reset, ResetReason::PowerOn));
+
+		let mut bytes = [0u8; FaultLog::<3>::BYTES];
+		log.to_bytes(&mut bytes);
+		let mut log = FaultLog::<3>::from_bytes(&bytes);
+		assert_eq!(log.len(), 3);
+		assert!(matches!(
+			log.iter().last().unwrap().reset,
+			ResetReason::Watchdog
+		));
+		bytes[5] ^= 1;
+		assert!(FaultLog::<3>::from_bytes(&bytes).is_empty());
+		assert!(FaultLog::<3>::from_bytes(&[0; 4]).is_empty());
+
+		let mut transport = Acking {
+			sent: 0,
+			fail_at: 2,
+		};
+		assert_eq!(log.send_reports(&mut transport, 0x0204, 7), Ok(1));
+		assert_eq!(log.first().unwrap().code, 3);
+		assert_eq!(log.send_reports(&mut transport, 0x0204, 7), Ok(2));
+		assert!(log.is_empty());
+	}
+}
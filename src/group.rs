@@ -0,0 +1,96 @@
+use crate::node::{group_address, Command, NodeAddress};
+
+/// Node side of [`Command::JoinGroup`] and [`Command::LeaveGroup`]
+///
+/// Keeps the node's own address and up to `N - 1` group addresses,
+/// [`Groups::rx_addresses`] is what to pass to [`Radio::receive`](crate::radio::Radio::receive).
+/// Broadcasts get through without joining anything.
+#[derive(Clone, Debug)]
+pub struct Groups<const N: usize> {
+	/// The node's address, then the groups
+	addresses: [NodeAddress; N],
+	len: usize,
+}
+
+impl<const N: usize> Groups<N> {
+	pub const fn new(node_addr: NodeAddress) -> Self {
+		assert!(N > 0, "Groups needs room for the node's address");
+		let mut addresses = [0; N];
+		addresses[0] = node_addr;
+		Self { addresses, len: 1 }
+	}
+
+	/// HQ gave us a new address, the groups stay
+	pub fn set_node_addr(&mut self, node_addr: NodeAddress) {
+		self.addresses[0] = node_addr;
+	}
+
+	fn groups(&self) -> &[NodeAddress] {
+		&self.addresses[1..self.len]
+	}
+
+	pub fn contains(&self, group: u8) -> bool {
+		self.groups().contains(&group_address(group))
+	}
+
+	/// `false` when there's no room left
+	pub fn join(&mut self, group: u8) -> bool {
+		if self.contains(group) {
+			return true;
+		}
+		if self.len == N {
+			return false;
+		}
+		self.addresses[self.len] = group_address(group);
+		self.len += 1;
+		true
+	}
+
+	pub fn leave(&mut self, group: u8) {
+		let address = group_address(group);
+		if let Some(i) = self.groups().iter().position(|a| *a == address) {
+			self.addresses.copy_within(i + 2..self.len, i + 1);
+			self.len -= 1;
+		}
+	}
+
+	/// Joins or leaves on the group commands, `None` for any other command
+	pub fn handle(&mut self, command: &Command) -> Option<bool> {
+		match command {
+			Command::JoinGroup(group) => Some(self.join(*group)),
+			Command::LeaveGroup(group) => {
+				self.leave(*group);
+				Some(true)
+			}
+			_ => None,
+		}
+	}
+
+	/// The node's address and the groups it's in
+	pub fn rx_addresses(&self) -> &[NodeAddress] {
+		&self.addresses[..self.len]
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn join_and_leave() {
+		let mut groups = Groups::<3>::new(0x0204);
+		assert_eq!(groups.rx_addresses(), [0x0204]);
+		assert_eq!(groups.handle(&Command::JoinGroup(1)), Some(true));
+		assert!(groups.join(2));
+		assert!(groups.join(2));
+		assert!(!groups.join(3));
+		assert_eq!(groups.handle(&Command::Info), None);
+		assert_eq!(groups.rx_addresses(), [0x0204, 0xFF01, 0xFF02]);
+
+		groups.leave(1);
+		groups.leave(7);
+		groups.set_node_addr(0x0305);
+		assert_eq!(groups.rx_addresses(), [0x0305, 0xFF02]);
+		assert!(!groups.contains(1));
+	}
+}
@@ -0,0 +1,114 @@
+use crate::node::{LimbId, Sensor, SensorHistory, Timestamp, HISTORY_PAGE_MAX};
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+	pub limb: LimbId,
+	pub timestamp: Timestamp,
+	pub sensor: Sensor,
+}
+
+/// Node side ring buffer of sensor readings
+///
+/// Readings are kept here while HQ can't be reached, once full the oldest
+/// readings get overwritten. HQ gets them back with
+/// [`Command::FetchHistory`](crate::node::Command::FetchHistory).
+pub struct History<const N: usize> {
+	samples: [Option<Sample>; N],
+	/// Where the next sample goes, also the oldest sample once we've wrapped
+	next: usize,
+}
+
+impl<const N: usize> Default for History<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> History<N> {
+	pub fn new() -> Self {
+		Self {
+			samples: core::array::from_fn(|_| None),
+			next: 0,
+		}
+	}
+
+	pub fn push(&mut self, limb: LimbId, timestamp: Timestamp, sensor: Sensor) {
+		if N == 0 {
+			return;
+		}
+		self.samples[self.next] = Some(Sample {
+			limb,
+			timestamp,
+			sensor,
+		});
+		self.next = (self.next + 1) % N;
+	}
+
+	pub fn len(&self) -> usize {
+		self.samples.iter().flatten().count()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.samples.iter().all(Option::is_none)
+	}
+	pub fn clear(&mut self) {
+		self.samples.iter_mut().for_each(|sample| *sample = None);
+		self.next = 0;
+	}
+
+	/// Samples oldest first
+	pub fn iter(&self) -> impl Iterator<Item = &Sample> {
+		let (newer, older) = self.samples.split_at(self.next);
+		older.iter().chain(newer.iter()).flatten()
+	}
+
+	/// Up to `max` (capped to [`HISTORY_PAGE_MAX`]) samples for `limb` taken at or after `since`
+	///
+	/// If the page doesn't fit in a payload (serializing gives `BufferOverflow`),
+	/// ask again with a smaller `max`.
+	pub fn page(&self, limb: LimbId, since: Timestamp, max: usize) -> Option<SensorHistory> {
+		let mut history = SensorHistory {
+			limb,
+			samples: Default::default(),
+		};
+		let samples = self
+			.iter()
+			.filter(|sample| sample.limb == limb && sample.timestamp >= since);
+		for (slot, sample) in history
+			.samples
+			.iter_mut()
+			.take(max.min(HISTORY_PAGE_MAX))
+			.zip(samples)
+		{
+			*slot = Some((sample.timestamp, sample.sensor.clone()));
+		}
+		history.samples[0].is_some().then_some(history)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::History;
+	use crate::node::Sensor;
+
+	#[test]
+	fn history_wraps_and_pages() {
+		let mut history = History::<4>::new();
+		assert!(history.page(0, 0, 6).is_none());
+
+		for i in 0..6u8 {
+			history.push(i % 2, 100 + i as u32, Sensor::Battery(i));
+		}
+		assert_eq!(history.len(), 4);
+		// Oldest two got overwritten
+		let times: Vec<_> = history.iter().map(|s| s.timestamp).collect();
+		assert_eq!(times, [102, 103, 104, 105]);
+
+		let page = history.page(1, 104, 6).unwrap();
+		assert_eq!(page.samples[0], Some((105, Sensor::Battery(5))));
+		assert_eq!(page.samples[1], None);
+
+		let page = history.page(0, 0, 1).unwrap();
+		assert_eq!(page.samples[0], Some((102, Sensor::Battery(2))));
+		assert_eq!(page.samples[1], None);
+	}
+}
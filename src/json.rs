@@ -0,0 +1,830 @@
+//! JSON mapping of the node types, for web clients
+//!
+//! The node types keep serde's default representation because postcard can't do
+//! internally tagged enums, so JSON goes through the `Json*` mirror types here:
+//! every enum is tagged with a `"type"` field, every value has a name and
+//! temperatures are decimal °C.
+//!
+//! ```json
+//! {"type": "message", "data": {"type": "response", "id": 3, "response":
+//!   {"type": "limbs_at", "timestamp": 1700000000, "limbs": [
+//!     {"id": 0, "type": "sensor", "report_interval": 300,
+//!      "data": {"type": "temp_hum", "temperature": 21.5, "humidity": 50}},
+//!     null, null]}}}
+//! ```
+//!
+//! The schema from [`schema`] is checked in at `schema/message.schema.json`.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::node::*;
+
+/// A JSON value that doesn't fit in the node types
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonValueError(pub &'static str);
+
+impl core::fmt::Display for JsonValueError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(f, "{} out of range", self.0)
+	}
+}
+impl std::error::Error for JsonValueError {}
+
+type JsonResult<T> = Result<T, JsonValueError>;
+
+fn check<T: PartialOrd>(value: T, max: T, name: &'static str) -> JsonResult<T> {
+	if value > max {
+		return Err(JsonValueError(name));
+	}
+	Ok(value)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonMessage {
+	Message {
+		data: JsonMessageData,
+	},
+	RelayMessage {
+		node_id: NodeId,
+		data: JsonMessageData,
+	},
+	SearchingNetwork {
+		node_id: NodeId,
+	},
+	Network {
+		node_id: NodeId,
+		address: NodeAddress,
+	},
+	DebugMessage {
+		node_id: NodeId,
+		/// Up to 20 bytes of text, bytes that aren't utf8 are replaced
+		text: String,
+	},
+	FaultReport {
+		node_id: NodeId,
+		/// Error discriminant, depends on the node's firmware
+		code: u8,
+		count: u16,
+		/// Seconds since boot
+		uptime: u32,
+		reset: JsonResetReason,
+	},
+	Log {
+		node_id: NodeId,
+		/// Tells the node's logs apart (0 - 15)
+		log: u8,
+		/// Position in the log (0 - 15)
+		part: u8,
+		last: bool,
+		/// Up to 20 bytes of the packed log
+		data: Vec<u8>,
+	},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonMessageData {
+	Command {
+		/// 0-63
+		id: u8,
+		command: JsonCommand,
+	},
+	Response {
+		/// 1-63, the command this responds to
+		id: Option<u8>,
+		response: JsonResponse,
+	},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonCommand {
+	Info,
+	Limbs,
+	SetLimb {
+		limb: JsonLimb,
+	},
+	ToggleLimb {
+		limb_id: LimbId,
+	},
+	SetLimbType {
+		limb_type: JsonLimbType,
+	},
+	SyncTime {
+		/// Seconds since the unix epoch
+		seconds: Timestamp,
+		/// 0-999
+		millis: u16,
+		/// Expected transmit latency in ms
+		latency: u8,
+	},
+	FetchHistory {
+		limb_id: LimbId,
+		since: Timestamp,
+	},
+	ChangeChannel {
+		channel: u8,
+		/// Node clock time to switch at
+		at: Timestamp,
+	},
+	JoinGroup {
+		group: u8,
+	},
+	LeaveGroup {
+		group: u8,
+	},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonResponse {
+	Ok,
+	Info {
+		board: JsonBoard,
+		/// Seconds
+		heartbeat_interval: u16,
+	},
+	Limbs {
+		limbs: [Option<JsonLimb>; LIMBS_MAX],
+	},
+	Heartbeat {
+		/// Node clock, seconds since the unix epoch, 0 if never synced
+		timestamp: Timestamp,
+	},
+	LimbsAt {
+		timestamp: Timestamp,
+		limbs: [Option<JsonLimb>; LIMBS_MAX],
+	},
+	History {
+		limb_id: LimbId,
+		/// Oldest first, up to 6
+		samples: Vec<JsonSample>,
+	},
+	ErrLimbNotFound,
+	ErrLimbTypeDoesntMatch,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct JsonSample {
+	pub timestamp: Timestamp,
+	pub sensor: JsonSensor,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonBoard {
+	SamnV8,
+	SamnV9,
+	SamnDc,
+	SamnSwitch,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonResetReason {
+	PowerOn,
+	External,
+	Brownout,
+	Watchdog,
+	Software,
+	Panic,
+	Unknown,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct JsonLimb {
+	/// 0-15
+	pub id: LimbId,
+	#[serde(flatten)]
+	pub limb_type: JsonLimbType,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonLimbType {
+	Sensor {
+		/// Seconds
+		report_interval: u16,
+		data: Option<JsonSensor>,
+	},
+	Actuator {
+		actuator: JsonActuator,
+	},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonSensor {
+	Battery {
+		/// 0-100 %
+		percent: u8,
+	},
+	TempHum {
+		/// °C, 2 decimals
+		temperature: f64,
+		/// 0-100 %
+		humidity: u8,
+	},
+	Current {
+		/// mA
+		milliamps: u16,
+	},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonActuator {
+	Light { on: bool },
+}
+
+impl From<&Board> for JsonBoard {
+	fn from(board: &Board) -> Self {
+		match board {
+			Board::SamnV8 => JsonBoard::SamnV8,
+			Board::SamnV9 => JsonBoard::SamnV9,
+			Board::SamnDC => JsonBoard::SamnDc,
+			Board::SamnSwitch => JsonBoard::SamnSwitch,
+		}
+	}
+}
+impl From<JsonBoard> for Board {
+	fn from(board: JsonBoard) -> Self {
+		match board {
+			JsonBoard::SamnV8 => Board::SamnV8,
+			JsonBoard::SamnV9 => Board::SamnV9,
+			JsonBoard::SamnDc => Board::SamnDC,
+			JsonBoard::SamnSwitch => Board::SamnSwitch,
+		}
+	}
+}
+
+impl From<ResetReason> for JsonResetReason {
+	fn from(reset: ResetReason) -> Self {
+		match reset {
+			ResetReason::PowerOn => JsonResetReason::PowerOn,
+			ResetReason::External => JsonResetReason::External,
+			ResetReason::Brownout => JsonResetReason::Brownout,
+			ResetReason::Watchdog => JsonResetReason::Watchdog,
+			ResetReason::Software => JsonResetReason::Software,
+			ResetReason::Panic => JsonResetReason::Panic,
+			ResetReason::Unknown => JsonResetReason::Unknown,
+		}
+	}
+}
+impl From<JsonResetReason> for ResetReason {
+	fn from(reset: JsonResetReason) -> Self {
+		match reset {
+			JsonResetReason::PowerOn => ResetReason::PowerOn,
+			JsonResetReason::External => ResetReason::External,
+			JsonResetReason::Brownout => ResetReason::Brownout,
+			JsonResetReason::Watchdog => ResetReason::Watchdog,
+			JsonResetReason::Software => ResetReason::Software,
+			JsonResetReason::Panic => ResetReason::Panic,
+			JsonResetReason::Unknown => ResetReason::Unknown,
+		}
+	}
+}
+
+impl From<&Sensor> for JsonSensor {
+	fn from(sensor: &Sensor) -> Self {
+		match sensor {
+			Sensor::Battery(percent) => JsonSensor::Battery { percent: *percent },
+			Sensor::TempHum((temp, hum)) => JsonSensor::TempHum {
+				temperature: *temp as f64 / 100.,
+				humidity: *hum,
+			},
+			Sensor::Current(milliamps) => JsonSensor::Current {
+				milliamps: *milliamps,
+			},
+		}
+	}
+}
+impl TryFrom<JsonSensor> for Sensor {
+	type Error = JsonValueError;
+	fn try_from(sensor: JsonSensor) -> JsonResult<Self> {
+		Ok(match sensor {
+			JsonSensor::Battery { percent } => Sensor::Battery(percent),
+			JsonSensor::TempHum {
+				temperature,
+				humidity,
+			} => {
+				let temp = (temperature * 100.).round();
+				if !(i16::MIN as f64..=i16::MAX as f64).contains(&temp) {
+					return Err(JsonValueError("temperature"));
+				}
+				Sensor::TempHum((temp as i16, humidity))
+			}
+			JsonSensor::Current { milliamps } => Sensor::Current(milliamps),
+		})
+	}
+}
+
+impl From<&Actuator> for JsonActuator {
+	fn from(actuator: &Actuator) -> Self {
+		match actuator {
+			Actuator::Light(on) => JsonActuator::Light { on: *on },
+		}
+	}
+}
+impl From<JsonActuator> for Actuator {
+	fn from(actuator: JsonActuator) -> Self {
+		match actuator {
+			JsonActuator::Light { on } => Actuator::Light(on),
+		}
+	}
+}
+
+impl From<&LimbType> for JsonLimbType {
+	fn from(limb_type: &LimbType) -> Self {
+		match limb_type {
+			LimbType::Sensor {
+				report_interval,
+				data,
+			} => JsonLimbType::Sensor {
+				report_interval: *report_interval,
+				data: data.as_ref().map(Into::into),
+			},
+			LimbType::Actuator(actuator) => JsonLimbType::Actuator {
+				actuator: actuator.into(),
+			},
+		}
+	}
+}
+impl TryFrom<JsonLimbType> for LimbType {
+	type Error = JsonValueError;
+	fn try_from(limb_type: JsonLimbType) -> JsonResult<Self> {
+		Ok(match limb_type {
+			JsonLimbType::Sensor {
+				report_interval,
+				data,
+			} => LimbType::Sensor {
+				report_interval,
+				data: data.map(TryInto::try_into).transpose()?,
+			},
+			JsonLimbType::Actuator { actuator } => LimbType::Actuator(actuator.into()),
+		})
+	}
+}
+
+impl From<&Limb> for JsonLimb {
+	fn from(limb: &Limb) -> Self {
+		JsonLimb {
+			id: limb.0,
+			limb_type: (&limb.1).into(),
+		}
+	}
+}
+impl TryFrom<JsonLimb> for Limb {
+	type Error = JsonValueError;
+	fn try_from(limb: JsonLimb) -> JsonResult<Self> {
+		Ok(Limb(
+			check(limb.id, 15, "limb id")?,
+			limb.limb_type.try_into()?,
+		))
+	}
+}
+
+fn json_limbs(limbs: &Limbs) -> [Option<JsonLimb>; LIMBS_MAX] {
+	core::array::from_fn(|i| limbs[i].as_ref().map(Into::into))
+}
+fn limbs_from_json(limbs: [Option<JsonLimb>; LIMBS_MAX]) -> JsonResult<Limbs> {
+	let mut out: Limbs = Default::default();
+	for (slot, limb) in out.iter_mut().zip(limbs) {
+		*slot = limb.map(TryInto::try_into).transpose()?;
+	}
+	Ok(out)
+}
+
+impl From<&Command> for JsonCommand {
+	fn from(command: &Command) -> Self {
+		match command {
+			Command::Info => JsonCommand::Info,
+			Command::Limbs => JsonCommand::Limbs,
+			Command::SetLimb(limb) => JsonCommand::SetLimb { limb: limb.into() },
+			Command::ToggleLimb(limb_id) => JsonCommand::ToggleLimb { limb_id: *limb_id },
+			Command::SetLimbType(limb_type) => JsonCommand::SetLimbType {
+				limb_type: limb_type.into(),
+			},
+			Command::SyncTime(time) => JsonCommand::SyncTime {
+				seconds: time.seconds,
+				millis: time.millis,
+				latency: time.latency,
+			},
+			Command::FetchHistory { limb, since } => JsonCommand::FetchHistory {
+				limb_id: *limb,
+				since: *since,
+			},
+			Command::ChangeChannel { channel, at } => JsonCommand::ChangeChannel {
+				channel: *channel,
+				at: *at,
+			},
+			Command::JoinGroup(group) => JsonCommand::JoinGroup { group: *group },
+			Command::LeaveGroup(group) => JsonCommand::LeaveGroup { group: *group },
+		}
+	}
+}
+impl TryFrom<JsonCommand> for Command {
+	type Error = JsonValueError;
+	fn try_from(command: JsonCommand) -> JsonResult<Self> {
+		Ok(match command {
+			JsonCommand::Info => Command::Info,
+			JsonCommand::Limbs => Command::Limbs,
+			JsonCommand::SetLimb { limb } => Command::SetLimb(limb.try_into()?),
+			JsonCommand::ToggleLimb { limb_id } => {
+				Command::ToggleLimb(check(limb_id, 15, "limb id")?)
+			}
+			JsonCommand::SetLimbType { limb_type } => {
+				Command::SetLimbType(limb_type.try_into()?)
+			}
+			JsonCommand::SyncTime {
+				seconds,
+				millis,
+				latency,
+			} => Command::SyncTime(TimeSync {
+				seconds,
+				millis: check(millis, 999, "millis")?,
+				latency,
+			}),
+			JsonCommand::FetchHistory { limb_id, since } => Command::FetchHistory {
+				limb: check(limb_id, 15, "limb id")?,
+				since,
+			},
+			JsonCommand::ChangeChannel { channel, at } => {
+				Command::ChangeChannel { channel, at }
+			}
+			JsonCommand::JoinGroup { group } => Command::JoinGroup(group),
+			JsonCommand::LeaveGroup { group } => Command::LeaveGroup(group),
+		})
+	}
+}
+
+impl From<&Response> for JsonResponse {
+	fn from(response: &Response) -> Self {
+		match response {
+			Response::Ok => JsonResponse::Ok,
+			Response::Info(info) => JsonResponse::Info {
+				board: (&info.board).into(),
+				heartbeat_interval: info.heartbeat_interval,
+			},
+			Response::Limbs(limbs) => JsonResponse::Limbs {
+				limbs: json_limbs(limbs),
+			},
+			Response::Heartbeat(timestamp) => JsonResponse::Heartbeat {
+				timestamp: *timestamp,
+			},
+			Response::LimbsAt(timestamp, limbs) => JsonResponse::LimbsAt {
+				timestamp: *timestamp,
+				limbs: json_limbs(limbs),
+			},
+			Response::History(history) => JsonResponse::History {
+				limb_id: history.limb,
+				samples: history
+					.samples
+					.iter()
+					.flatten()
+					.map(|(timestamp, sensor)| JsonSample {
+						timestamp: *timestamp,
+						sensor: sensor.into(),
+					})
+					.collect(),
+			},
+			Response::ErrLimbNotFound => JsonResponse::ErrLimbNotFound,
+			Response::ErrLimbTypeDoesntMatch => JsonResponse::ErrLimbTypeDoesntMatch,
+		}
+	}
+}
+impl TryFrom<JsonResponse> for Response {
+	type Error = JsonValueError;
+	fn try_from(response: JsonResponse) -> JsonResult<Self> {
+		Ok(match response {
+			JsonResponse::Ok => Response::Ok,
+			JsonResponse::Info {
+				board,
+				heartbeat_interval,
+			} => Response::Info(NodeInfo {
+				board: board.into(),
+				heartbeat_interval,
+			}),
+			JsonResponse::Limbs { limbs } => Response::Limbs(limbs_from_json(limbs)?),
+			JsonResponse::Heartbeat { timestamp } => Response::Heartbeat(timestamp),
+			JsonResponse::LimbsAt { timestamp, limbs } => {
+				Response::LimbsAt(timestamp, limbs_from_json(limbs)?)
+			}
+			JsonResponse::History { limb_id, samples } => {
+				check(samples.len(), HISTORY_PAGE_MAX, "history samples")?;
+				let mut history = SensorHistory {
+					limb: check(limb_id, 15, "limb id")?,
+					samples: Default::default(),
+				};
+				for (slot, sample) in history.samples.iter_mut().zip(samples) {
+					*slot = Some((sample.timestamp, sample.sensor.try_into()?));
+				}
+				Response::History(history)
+			}
+			JsonResponse::ErrLimbNotFound => Response::ErrLimbNotFound,
+			JsonResponse::ErrLimbTypeDoesntMatch => Response::ErrLimbTypeDoesntMatch,
+		})
+	}
+}
+
+impl From<&MessageData> for JsonMessageData {
+	fn from(data: &MessageData) -> Self {
+		match data {
+			MessageData::Command { id, command } => JsonMessageData::Command {
+				id: *id,
+				command: command.into(),
+			},
+			MessageData::Response { id, response } => JsonMessageData::Response {
+				id: *id,
+				response: response.into(),
+			},
+		}
+	}
+}
+impl TryFrom<JsonMessageData> for MessageData {
+	type Error = JsonValueError;
+	fn try_from(data: JsonMessageData) -> JsonResult<Self> {
+		Ok(match data {
+			JsonMessageData::Command { id, command } => MessageData::Command {
+				id: check(id, COMMAND_ID_MAX - 1, "command id")?,
+				command: command.try_into()?,
+			},
+			JsonMessageData::Response { id, response } => MessageData::Response {
+				id: id
+					.map(|id| check(id, COMMAND_ID_MAX - 1, "command id"))
+					.transpose()?,
+				response: response.try_into()?,
+			},
+		})
+	}
+}
+
+impl From<&Message> for JsonMessage {
+	fn from(message: &Message) -> Self {
+		match message {
+			Message::Message(data) => JsonMessage::Message { data: data.into() },
+			Message::RelayMessage(node_id, data) => JsonMessage::RelayMessage {
+				node_id: *node_id,
+				data: data.into(),
+			},
+			Message::SearchingNetwork(node_id) => {
+				JsonMessage::SearchingNetwork { node_id: *node_id }
+			}
+			Message::Network(node_id, address) => JsonMessage::Network {
+				node_id: *node_id,
+				address: *address,
+			},
+			Message::DebugMessage(node_id, message) => {
+				let end = message
+					.iter()
+					.position(|b| *b == 0)
+					.unwrap_or(message.len());
+				JsonMessage::DebugMessage {
+					node_id: *node_id,
+					text: String::from_utf8_lossy(&message[..end]).into_owned(),
+				}
+			}
+			Message::FaultReport(node_id, fault) => JsonMessage::FaultReport {
+				node_id: *node_id,
+				code: fault.code,
+				count: fault.count,
+				uptime: fault.uptime,
+				reset: fault.reset.into(),
+			},
+			Message::Log(node_id, chunk) => JsonMessage::Log {
+				node_id: *node_id,
+				log: chunk.log,
+				part: chunk.part,
+				last: chunk.last,
+				data: chunk.data().to_vec(),
+			},
+		}
+	}
+}
+impl TryFrom<JsonMessage> for Message {
+	type Error = JsonValueError;
+	fn try_from(message: JsonMessage) -> JsonResult<Self> {
+		Ok(match message {
+			JsonMessage::Message { data } => Message::Message(data.try_into()?),
+			JsonMessage::RelayMessage { node_id, data } => {
+				Message::RelayMessage(node_id, data.try_into()?)
+			}
+			JsonMessage::SearchingNetwork { node_id } => Message::SearchingNetwork(node_id),
+			JsonMessage::Network { node_id, address } => Message::Network(node_id, address),
+			JsonMessage::DebugMessage { node_id, text } => {
+				let mut message = [0u8; 20];
+				let bytes = text.as_bytes();
+				check(bytes.len(), message.len(), "debug text")?;
+				message[..bytes.len()].copy_from_slice(bytes);
+				Message::DebugMessage(node_id, message)
+			}
+			JsonMessage::FaultReport {
+				node_id,
+				code,
+				count,
+				uptime,
+				reset,
+			} => Message::FaultReport(
+				node_id,
+				Fault {
+					code,
+					count,
+					uptime,
+					reset: reset.into(),
+				},
+			),
+			JsonMessage::Log {
+				node_id,
+				log,
+				part,
+				last,
+				data,
+			} => {
+				let mut chunk = LogChunk {
+					log: check(log, 15, "log")?,
+					part: check(part, 15, "log part")?,
+					last,
+					len: check(data.len(), LOG_CHUNK_MAX, "log data")? as u8,
+					data: [0; LOG_CHUNK_MAX],
+				};
+				chunk.data[..data.len()].copy_from_slice(&data);
+				Message::Log(node_id, chunk)
+			}
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+	Json(serde_json::Error),
+	Value(JsonValueError),
+}
+impl core::fmt::Display for JsonError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			JsonError::Json(err) => err.fmt(f),
+			JsonError::Value(err) => err.fmt(f),
+		}
+	}
+}
+impl std::error::Error for JsonError {}
+
+pub fn to_json(message: &Message) -> String {
+	// Mirror types only have string keys and finite numbers, this can't fail
+	serde_json::to_string(&JsonMessage::from(message)).unwrap_or_default()
+}
+pub fn from_json(json: &str) -> Result<Message, JsonError> {
+	let message: JsonMessage = serde_json::from_str(json).map_err(JsonError::Json)?;
+	message.try_into().map_err(JsonError::Value)
+}
+
+/// JSON Schema of [`JsonMessage`]
+pub fn schema() -> schemars::schema::RootSchema {
+	schemars::schema_for!(JsonMessage)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn messages() -> Vec<Message> {
+		let temp_hum = |id, temp| {
+			Some(Limb(
+				id,
+				LimbType::Sensor {
+					report_interval: 300,
+					data: Some(Sensor::TempHum((temp, 50))),
+				},
+			))
+		};
+		let mut debug = [0u8; 20];
+		debug[..5].copy_from_slice(b"hello");
+		vec![
+			Message::Message(MessageData::Response {
+				id: Some(3),
+				response: Response::LimbsAt(
+					1_700_000_000,
+					[temp_hum(0, 2150), None, temp_hum(2, -1)],
+				),
+			}),
+			Message::Message(MessageData::Command {
+				id: 55,
+				command: Command::SetLimb(Limb(1, LimbType::Actuator(Actuator::Light(true)))),
+			}),
+			Message::Message(MessageData::Command {
+				id: 1,
+				command: Command::SyncTime(TimeSync {
+					seconds: 1_700_000_000,
+					millis: 250,
+					latency: 4,
+				}),
+			}),
+			Message::RelayMessage(
+				7,
+				MessageData::Response {
+					id: None,
+					response: Response::Info(NodeInfo {
+						board: Board::SamnDC,
+						heartbeat_interval: 60,
+					}),
+				},
+			),
+			Message::Message(MessageData::Response {
+				id: Some(9),
+				response: Response::History(SensorHistory {
+					limb: 2,
+					samples: [
+						Some((1_700_000_000, Sensor::Current(120))),
+						Some((1_700_000_300, Sensor::Current(125))),
+						None,
+						None,
+						None,
+						None,
+					],
+				}),
+			}),
+			Message::Network(7, 0x1234),
+			Message::DebugMessage(7, debug),
+			Message::FaultReport(
+				7,
+				Fault {
+					code: 12,
+					count: 1,
+					uptime: 3600,
+					reset: ResetReason::Brownout,
+				},
+			),
+			Message::Log(
+				7,
+				LogChunk {
+					log: 3,
+					part: 1,
+					last: true,
+					len: 2,
+					data: core::array::from_fn(|i| if i < 2 { 0xAB } else { 0 }),
+				},
+			),
+		]
+	}
+
+	#[test]
+	fn json_round_trip() {
+		for message in messages() {
+			let json = to_json(&message);
+			assert_eq!(from_json(&json).unwrap(), message, "{json}");
+
+			// Bit format and postcard agree with JSON
+			let mut data = [0u8; 32];
+			message.serialize_to_bytes(&mut data).unwrap();
+			let from_bits = Message::deserialize_from_bytes(&data).unwrap().0;
+			assert_eq!(to_json(&from_bits), json);
+			#[cfg(feature = "postcard")]
+			{
+				let mut data = [0u8; 32];
+				postcard::to_slice(&message, &mut data).unwrap();
+				let from_postcard: Message = postcard::from_bytes(&data).unwrap();
+				assert_eq!(to_json(&from_postcard), json);
+			}
+		}
+	}
+
+	#[test]
+	fn json_shape() {
+		let json = to_json(&messages()[0]);
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+			serde_json::json!({
+				"type": "message",
+				"data": {"type": "response", "id": 3, "response": {
+					"type": "limbs_at",
+					"timestamp": 1_700_000_000,
+					"limbs": [
+						{"id": 0, "type": "sensor", "report_interval": 300,
+						 "data": {"type": "temp_hum", "temperature": 21.5, "humidity": 50}},
+						null,
+						{"id": 2, "type": "sensor", "report_interval": 300,
+						 "data": {"type": "temp_hum", "temperature": -0.01, "humidity": 50}},
+					]
+				}}
+			})
+		);
+		assert!(from_json(r#"{"type": "message", "data": {"type": "command", "id": 64, "command": {"type": "info"}}}"#).is_err());
+	}
+
+	#[test]
+	fn schema_is_checked_in() {
+		let schema = serde_json::to_string_pretty(&schema()).unwrap();
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/message.schema.json");
+		if std::env::var_os("SAMN_UPDATE_SCHEMA").is_some() {
+			std::fs::write(path, schema + "\n").unwrap();
+			return;
+		}
+		let checked_in = std::fs::read_to_string(path).unwrap_or_default();
+		assert_eq!(
+			checked_in.trim_end(),
+			schema,
+			"schema changed, rerun with SAMN_UPDATE_SCHEMA=1"
+		);
+	}
+}
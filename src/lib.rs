@@ -1,10 +1,24 @@
-#![cfg_attr(not(feature = "std"), no_std)]
+// json and ip only build where there's a standard library, without the rest of `std`
+#![cfg_attr(not(any(feature = "std", feature = "json", feature = "ip")), no_std)]
 
+pub mod channel;
+pub mod clock;
+pub mod dedup;
+pub mod fault;
+pub mod group;
+pub mod history;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod log;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod node;
-#[cfg(any(feature = "cc1101",feature = "nrf24"))]
+#[cfg(any(feature = "cc1101", feature = "nrf24", feature = "serial", feature = "ip"))]
 pub mod radio;
+pub mod schedule;
 #[cfg(feature = "sonnerie")]
 pub mod sonnerie;
+pub mod transport;
 
 #[cfg(feature = "cc1101")]
 pub extern crate cc1101;
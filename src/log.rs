@@ -0,0 +1,513 @@
+//! Logs that fit the radio: an interned format string id and bit-packed arguments
+//!
+//! The format string never leaves the node, [`samn_log!`](crate::samn_log) keeps it in
+//! the firmware's `.samn_log` section and sends its [`intern`] id instead. HQ reads the
+//! strings out of the firmware build into a [`LogTable`]:
+//! `objcopy -O binary --only-section=.samn_log firmware.elf samn_log.bin`.
+//!
+//! Every argument goes with a 3 bit tag saying what it is, so `{}` is all a format
+//! string needs. Logs longer than a [`LogChunk`] are sent in several, HQ puts them back
+//! together with [`LogAssembler`].
+//!
+//! With a linker script, keep the section out of flash (it's only read from the build):
+//! `SECTIONS { .samn_log (INFO) : { KEEP(*(.samn_log)) } } INSERT AFTER .text;`
+use crate::node::{LogChunk, Message, NodeAddress, NodeId, LOG_CHUNK_MAX};
+use crate::transport::Transport;
+
+/// Max bytes of a log, id included
+pub const LOG_MAX: usize = 4 * LOG_CHUNK_MAX;
+/// Longer `&str` arguments are cut (5 bits)
+pub const LOG_STR_MAX: usize = 31;
+
+/// Id of a format string (32 bit FNV-1a), what's sent instead of it
+pub const fn intern(format: &str) -> u32 {
+	let bytes = format.as_bytes();
+	let mut hash = 0x811C_9DC5u32;
+	let mut i = 0;
+	while i < bytes.len() {
+		hash ^= bytes[i] as u32;
+		hash = hash.wrapping_mul(0x0100_0193);
+		i += 1;
+	}
+	hash
+}
+
+/// How `format` is kept in `.samn_log`, its length then the string
+#[doc(hidden)]
+pub const fn table_entry<const L: usize>(format: &str) -> [u8; L] {
+	let bytes = format.as_bytes();
+	assert!(bytes.len() <= 255, "log format strings are up to 255 bytes");
+	let mut entry = [0u8; L];
+	entry[0] = bytes.len() as u8;
+	let mut i = 0;
+	while i < bytes.len() {
+		entry[1 + i] = bytes[i];
+		i += 1;
+	}
+	entry
+}
+
+/// A [`Log`] with an interned format string, `{}` for every argument
+///
+/// ```ignore
+/// let log = samn_log!("limb {} read {}", limb_id, temp);
+/// logger.send(&mut transport, address, node_id, &log)?;
+/// ```
+#[macro_export]
+macro_rules! samn_log {
+	($format:literal $(, $arg:expr)* $(,)?) => {{
+		const FORMAT: &str = $format;
+		const ID: u32 = $crate::log::intern(FORMAT);
+		#[cfg_attr(
+			not(any(target_vendor = "apple", windows)),
+			link_section = ".samn_log"
+		)]
+		#[used]
+		static ENTRY: [u8; FORMAT.len() + 1] = $crate::log::table_entry(FORMAT);
+		#[allow(unused_mut)]
+		let mut log = $crate::log::Log::new(ID);
+		$(log.arg(&$arg);)*
+		log
+	}};
+}
+
+/// An argument as it's sent
+///
+/// Tag 0 is left out so the padding at the end of a log isn't read as an argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogValue<'a> {
+	Bool(bool),
+	U8(u8),
+	U16(u16),
+	I16(i16),
+	U32(u32),
+	I32(i32),
+	Str(&'a str),
+}
+
+impl LogValue<'_> {
+	fn tag(&self) -> u8 {
+		match self {
+			LogValue::Bool(_) => 1,
+			LogValue::U8(_) => 2,
+			LogValue::U16(_) => 3,
+			LogValue::I16(_) => 4,
+			LogValue::U32(_) => 5,
+			LogValue::I32(_) => 6,
+			LogValue::Str(_) => 7,
+		}
+	}
+}
+
+/// Types that can be [`samn_log!`](crate::samn_log) arguments
+pub trait LogArg {
+	fn log_value(&self) -> LogValue<'_>;
+}
+
+macro_rules! log_arg {
+	($($ty:ty => $variant:ident),*) => {
+		$(impl LogArg for $ty {
+			fn log_value(&self) -> LogValue<'_> {
+				LogValue::$variant((*self).into())
+			}
+		})*
+	};
+}
+log_arg!(bool => Bool, u8 => U8, u16 => U16, i8 => I16, i16 => I16, u32 => U32, i32 => I32);
+
+impl LogArg for str {
+	fn log_value(&self) -> LogValue<'_> {
+		LogValue::Str(self)
+	}
+}
+impl<T: LogArg + ?Sized> LogArg for &T {
+	fn log_value(&self) -> LogValue<'_> {
+		(**self).log_value()
+	}
+}
+
+/// A format string id and its arguments, packed MSB first like the rest of the protocol
+///
+/// Arguments that don't fit in [`LOG_MAX`] are left out, HQ shows `{?}` for them.
+#[derive(Clone, Debug)]
+pub struct Log {
+	bytes: [u8; LOG_MAX],
+	bits: usize,
+	truncated: bool,
+}
+
+impl Log {
+	pub fn new(id: u32) -> Self {
+		let mut log = Self {
+			bytes: [0; LOG_MAX],
+			bits: 0,
+			truncated: false,
+		};
+		log.push(id, 32);
+		log
+	}
+
+	fn push(&mut self, value: u32, bits: u8) {
+		for i in (0..bits).rev() {
+			if (value >> i) & 1 != 0 {
+				self.bytes[self.bits / 8] |= 0x80 >> (self.bits % 8);
+			}
+			self.bits += 1;
+		}
+	}
+
+	pub fn arg<A: LogArg + ?Sized>(&mut self, arg: &A) -> &mut Self {
+		let value = arg.log_value();
+		let str_bytes = match value {
+			LogValue::Str(s) => &s.as_bytes()[..s.len().min(LOG_STR_MAX)],
+			_ => &[],
+		};
+		let bits = match value {
+			LogValue::Bool(_) => 1,
+			LogValue::U8(_) => 8,
+			LogValue::U16(_) | LogValue::I16(_) => 16,
+			LogValue::U32(_) | LogValue::I32(_) => 32,
+			LogValue::Str(_) => 5 + 8 * str_bytes.len(),
+		};
+		// Once one is left out the ones after it are too, so HQ doesn't show them in its place
+		if self.truncated || self.bits + 3 + bits > LOG_MAX * 8 {
+			self.truncated = true;
+			return self;
+		}
+		self.push(value.tag() as u32, 3);
+		match value {
+			LogValue::Bool(b) => self.push(b as u32, 1),
+			LogValue::U8(v) => self.push(v as u32, 8),
+			LogValue::U16(v) => self.push(v as u32, 16),
+			LogValue::I16(v) => self.push(v as u16 as u32, 16),
+			LogValue::U32(v) => self.push(v, 32),
+			LogValue::I32(v) => self.push(v as u32, 32),
+			LogValue::Str(_) => {
+				self.push(str_bytes.len() as u32, 5);
+				for byte in str_bytes {
+					self.push(*byte as u32, 8);
+				}
+			}
+		}
+		self
+	}
+
+	/// Whether arguments were left out
+	pub fn truncated(&self) -> bool {
+		self.truncated
+	}
+	pub fn bytes(&self) -> &[u8] {
+		&self.bytes[..self.bits.div_ceil(8)]
+	}
+
+	/// The log split in [`LogChunk`]s, `log` tells it apart from the node's other logs
+	pub fn chunks(&self, log: u8) -> impl Iterator<Item = LogChunk> + '_ {
+		let count = self.bytes().len().div_ceil(LOG_CHUNK_MAX);
+		self
+			.bytes()
+			.chunks(LOG_CHUNK_MAX)
+			.enumerate()
+			.map(move |(part, bytes)| {
+				let mut data = [0u8; LOG_CHUNK_MAX];
+				data[..bytes.len()].copy_from_slice(bytes);
+				LogChunk {
+					log: log % 16,
+					part: part as u8,
+					last: part + 1 == count,
+					len: bytes.len() as u8,
+					data,
+				}
+			})
+	}
+}
+
+/// Node side, numbers logs and sends them
+#[derive(Clone, Debug, Default)]
+pub struct Logger {
+	next: u8,
+}
+
+impl Logger {
+	pub const fn new() -> Self {
+		Self { next: 0 }
+	}
+
+	/// Sends every chunk of `log` with [`Message::Log`], `false` if one wasn't acked
+	///
+	/// Chunks after the one that wasn't acked aren't sent, HQ can't show the log anyway.
+	pub fn send<T: Transport>(
+		&mut self,
+		transport: &mut T,
+		address: NodeAddress,
+		node_id: NodeId,
+		log: &Log,
+	) -> Result<bool, T::Error> {
+		let number = self.next;
+		self.next = (self.next + 1) % 16;
+		for chunk in log.chunks(number) {
+			if !transport.send(address, &Message::Log(node_id, chunk))? {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(feature = "std")]
+pub use hq::*;
+
+#[cfg(feature = "std")]
+mod hq {
+	use std::collections::HashMap;
+	use std::fmt;
+
+	use bity::BitReader;
+
+	use super::intern;
+	use crate::node::{LogChunk, NodeId};
+
+	#[derive(Clone, Debug, PartialEq, Eq)]
+	pub enum LogTableError {
+		/// The section ends in the middle of an entry
+		Truncated,
+		NotUtf8,
+		/// Two format strings with the same id, change one of them
+		Collision(u32),
+	}
+
+	impl fmt::Display for LogTableError {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			match self {
+				LogTableError::Truncated => {
+					f.write_str("log table ends in the middle of an entry")
+				}
+				LogTableError::NotUtf8 => f.write_str("log format string isn't utf8"),
+				LogTableError::Collision(id) => {
+					write!(f, "two log format strings have the id {id:08x}")
+				}
+			}
+		}
+	}
+	impl std::error::Error for LogTableError {}
+
+	/// HQ side, the format strings of a firmware by id
+	#[derive(Clone, Debug, Default)]
+	pub struct LogTable {
+		formats: HashMap<u32, String>,
+	}
+
+	impl LogTable {
+		pub fn new() -> Self {
+			Self::default()
+		}
+
+		/// From the `.samn_log` section of a firmware build
+		///
+		/// Zero bytes between entries (alignment) are skipped.
+		pub fn from_section(mut bytes: &[u8]) -> Result<Self, LogTableError> {
+			let mut table = Self::new();
+			while let [len, rest @ ..] = bytes {
+				let len = *len as usize;
+				let format = rest.get(..len).ok_or(LogTableError::Truncated)?;
+				if len > 0 {
+					let format = std::str::from_utf8(format).map_err(|_| LogTableError::NotUtf8)?;
+					table.insert(format)?;
+				}
+				bytes = &rest[len..];
+			}
+			Ok(table)
+		}
+
+		/// Adds `format`, the same string twice is fine
+		pub fn insert(&mut self, format: &str) -> Result<u32, LogTableError> {
+			let id = intern(format);
+			match self.formats.get(&id) {
+				Some(known) if known != format => Err(LogTableError::Collision(id)),
+				Some(_) => Ok(id),
+				None => {
+					self.formats.insert(id, format.to_string());
+					Ok(id)
+				}
+			}
+		}
+
+		pub fn len(&self) -> usize {
+			self.formats.len()
+		}
+		pub fn is_empty(&self) -> bool {
+			self.formats.is_empty()
+		}
+
+		/// A whole log (see [`LogAssembler`]) as text
+		///
+		/// Arguments that didn't make it show as `{?}`, ids that aren't in the table as
+		/// `unknown log <id>`.
+		pub fn format(&self, log: &[u8]) -> String {
+			let mut reader = BitReader::new(log);
+			let Ok(id) = reader.read_bits(32) else {
+				return "empty log".to_string();
+			};
+			let Some(format) = self.formats.get(&id) else {
+				return format!("unknown log {id:08x}");
+			};
+			let mut text = String::new();
+			let mut chars = format.chars().peekable();
+			while let Some(c) = chars.next() {
+				match (c, chars.peek()) {
+					('{', Some('{')) | ('}', Some('}')) => {
+						chars.next();
+						text.push(c);
+					}
+					('{', _) => {
+						// Anything between the braces is ignored
+						for c in chars.by_ref() {
+							if c == '}' {
+								break;
+							}
+						}
+						match read_value(&mut reader) {
+							Some(value) => text.push_str(&value),
+							None => text.push_str("{?}"),
+						}
+					}
+					_ => text.push(c),
+				}
+			}
+			text
+		}
+	}
+
+	fn read_value(reader: &mut BitReader) -> Option<String> {
+		let mut read = |bits| reader.read_bits(bits).ok();
+		let value = match read(3)? {
+			1 => (read(1)? != 0).to_string(),
+			2 => read(8)?.to_string(),
+			3 => read(16)?.to_string(),
+			4 => (read(16)? as u16 as i16).to_string(),
+			5 => read(32)?.to_string(),
+			6 => (read(32)? as i32).to_string(),
+			7 => {
+				let len = read(5)?;
+				let bytes = (0..len)
+					.map(|_| read(8).map(|byte| byte as u8))
+					.collect::<Option<Vec<u8>>>()?;
+				String::from_utf8_lossy(&bytes).into_owned()
+			}
+			// Padding
+			_ => return None,
+		};
+		Some(value)
+	}
+
+	/// HQ side, puts the [`LogChunk`]s of each node's logs back together
+	///
+	/// A chunk of another log drops what was kept for the node, so a log that lost a
+	/// chunk doesn't stay around.
+	#[derive(Debug, Default)]
+	pub struct LogAssembler {
+		partial: HashMap<NodeId, Partial>,
+	}
+
+	#[derive(Debug)]
+	struct Partial {
+		log: u8,
+		parts: Vec<Option<Vec<u8>>>,
+		last: Option<usize>,
+	}
+
+	impl LogAssembler {
+		pub fn new() -> Self {
+			Self::default()
+		}
+
+		/// The whole log, once `chunk` was the last one missing
+		pub fn push(&mut self, node_id: NodeId, chunk: &LogChunk) -> Option<Vec<u8>> {
+			let partial = self.partial.entry(node_id).or_insert_with(|| Partial {
+				log: chunk.log,
+				parts: vec![],
+				last: None,
+			});
+			if partial.log != chunk.log {
+				*partial = Partial {
+					log: chunk.log,
+					parts: vec![],
+					last: None,
+				};
+			}
+			let part = chunk.part as usize;
+			if partial.parts.len() <= part {
+				partial.parts.resize(part + 1, None);
+			}
+			partial.parts[part] = Some(chunk.data().to_vec());
+			if chunk.last {
+				partial.last = Some(part);
+			}
+			let last = partial.last?;
+			if partial.parts.len() <= last || partial.parts[..=last].iter().any(Option::is_none)
+			{
+				return None;
+			}
+			let partial = self.partial.remove(&node_id)?;
+			Some(
+				partial
+					.parts
+					.into_iter()
+					.take(last + 1)
+					.flatten()
+					.flatten()
+					.collect(),
+			)
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+	use super::*;
+
+	#[test]
+	fn logs_go_through() {
+		const FORMAT: &str = "limb {} read {} ({}) {{x}}";
+		let mut section = table_entry::<{ 1 + 5 }>("hello").to_vec();
+		section.push(0);
+		section.extend(table_entry::<{ FORMAT.len() + 1 }>(FORMAT));
+		let mut table = LogTable::from_section(&section).unwrap();
+		assert_eq!(table.len(), 2);
+		assert_eq!(table.insert("hello"), Ok(intern("hello")));
+		assert_eq!(
+			LogTable::from_section(&section[..section.len() - 1]).unwrap_err(),
+			LogTableError::Truncated
+		);
+
+		let long = "a string longer than what's kept";
+		let mut log = crate::samn_log!("limb {} read {} ({}) {{x}}", 2u8, -1050i16, long);
+		assert!(!log.truncated());
+		let chunks: Vec<_> = log.chunks(17).collect();
+		assert_eq!(chunks.len(), 2);
+		assert!(chunks.iter().all(|chunk| chunk.log == 1));
+
+		let mut assembler = LogAssembler::new();
+		assert_eq!(assembler.push(7, &chunks[1]), None);
+		let bytes = assembler.push(7, &chunks[0]).unwrap();
+		assert_eq!(
+			table.format(&bytes),
+			format!("limb 2 read -1050 ({}) {{x}}", &long[..LOG_STR_MAX])
+		);
+
+		// Doesn't fit anymore
+		for _ in 0..3 {
+			log.arg(long);
+		}
+		log.arg(&true);
+		assert!(log.truncated());
+		let bytes: Vec<u8> = log
+			.chunks(0)
+			.flat_map(|chunk| chunk.data().to_vec())
+			.collect();
+		assert!(table.format(&bytes).ends_with("{x}"));
+		let mut short = Log::new(intern(FORMAT));
+		short.arg(&true);
+		assert_eq!(table.format(short.bytes()), "limb true read {?} ({?}) {x}");
+		assert_eq!(table.format(&[0, 0, 0, 1]), "unknown log 00000001");
+	}
+}
@@ -0,0 +1,212 @@
+//! Home Assistant MQTT discovery configs for a node's limbs
+//!
+//! Published retained to `<discovery_prefix>/<component>/samn_<node_id>_<limb_id>_<field>/config`,
+//! they point Home Assistant at the topics [`MqttBridge`](super::MqttBridge) publishes.
+use serde_json::{json, Value};
+
+use super::{bridge_availability_topic, limb_topic, node_topic, Publish};
+use crate::node::{Actuator, Board, LimbType, Limbs, NodeId, NodeInfo, Sensor};
+
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// An entity a limb shows up as
+struct Entity {
+	component: &'static str,
+	field: &'static str,
+	config: Value,
+}
+
+fn sensor_entity(
+	field: &'static str,
+	device_class: &'static str,
+	unit: &'static str,
+	value_template: &str,
+) -> Entity {
+	Entity {
+		component: "sensor",
+		field,
+		config: json!({
+			"device_class": device_class,
+			"unit_of_measurement": unit,
+			"state_class": "measurement",
+			"value_template": value_template,
+		}),
+	}
+}
+
+fn sensor_entities(sensor: &Sensor) -> Vec<Entity> {
+	match sensor {
+		Sensor::Battery(_) => vec![sensor_entity(
+			"battery",
+			"battery",
+			"%",
+			"{{ value_json.data.percent }}",
+		)],
+		Sensor::TempHum(_) => vec![
+			sensor_entity(
+				"temperature",
+				"temperature",
+				"°C",
+				"{{ value_json.data.temperature }}",
+			),
+			sensor_entity(
+				"humidity",
+				"humidity",
+				"%",
+				"{{ value_json.data.humidity }}",
+			),
+		],
+		Sensor::Current(_) => vec![sensor_entity(
+			"current",
+			"current",
+			"mA",
+			"{{ value_json.data.milliamps }}",
+		)],
+	}
+}
+
+fn actuator_entities(actuator: &Actuator) -> Vec<Entity> {
+	match actuator {
+		Actuator::Light(_) => vec![Entity {
+			component: "light",
+			field: "light",
+			config: json!({
+				"payload_on": "ON",
+				"payload_off": "OFF",
+				"state_value_template": "{{ 'ON' if value_json.actuator.on else 'OFF' }}",
+			}),
+		}],
+	}
+}
+
+fn board_model(board: &Board) -> &'static str {
+	match board {
+		Board::SamnV8 => "Samn v8",
+		Board::SamnV9 => "Samn v9",
+		Board::SamnDC => "Samn DC",
+		Board::SamnSwitch => "Samn Switch",
+	}
+}
+
+/// Discovery configs for every limb of a node
+///
+/// Which entities a sensor limb gets depends on its `data`, so a sensor that hasn't
+/// reported yet is left out until it does. Sensors expire after missing two reports,
+/// the whole node goes unavailable when HQ marks it offline after missed heartbeats
+/// or when the bridge goes offline.
+pub fn discovery_configs(
+	prefix: &str,
+	discovery_prefix: &str,
+	node_id: NodeId,
+	info: &NodeInfo,
+	limbs: &Limbs,
+) -> Vec<Publish> {
+	let device = json!({
+		"identifiers": [format!("samn_{node_id}")],
+		"name": format!("Samn {node_id}"),
+		"model": board_model(&info.board),
+		"manufacturer": "samn",
+	});
+	let availability = json!([
+		{ "topic": bridge_availability_topic(prefix) },
+		{ "topic": node_topic(prefix, node_id, "availability") },
+	]);
+
+	let mut publishes = vec![];
+	for limb in limbs.iter().flatten() {
+		let (entities, expire_after) = match &limb.1 {
+			LimbType::Sensor {
+				data: Some(sensor),
+				report_interval,
+			} => (sensor_entities(sensor), Some(*report_interval as u32 * 2)),
+			LimbType::Sensor { data: None, .. } => continue,
+			LimbType::Actuator(actuator) => (actuator_entities(actuator), None),
+		};
+		for Entity {
+			component,
+			field,
+			mut config,
+		} in entities
+		{
+			let object_id = format!("samn_{node_id}_{}_{field}", limb.0);
+			let config_map = config.as_object_mut().expect("entity configs are objects");
+			config_map.insert("name".into(), json!(format!("{field} {}", limb.0)));
+			config_map.insert("unique_id".into(), json!(object_id));
+			config_map.insert("device".into(), device.clone());
+			config_map.insert("availability".into(), availability.clone());
+			config_map.insert("availability_mode".into(), json!("all"));
+			config_map.insert(
+				"state_topic".into(),
+				json!(limb_topic(prefix, node_id, limb.0, "state")),
+			);
+			if component == "light" {
+				config_map.insert(
+					"command_topic".into(),
+					json!(limb_topic(prefix, node_id, limb.0, "set")),
+				);
+			}
+			if let Some(expire_after) = expire_after {
+				config_map.insert("expire_after".into(), json!(expire_after));
+			}
+			publishes.push(Publish::new(
+				format!("{discovery_prefix}/{component}/{object_id}/config"),
+				config.to_string(),
+				true,
+			));
+		}
+	}
+	publishes
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::node::Limb;
+
+	#[test]
+	fn configs_for_limbs() {
+		let info = NodeInfo {
+			board: Board::SamnSwitch,
+			heartbeat_interval: 60,
+		};
+		let limbs = [
+			Some(Limb(
+				0,
+				LimbType::Sensor {
+					report_interval: 300,
+					data: Some(Sensor::TempHum((2150, 40))),
+				},
+			)),
+			Some(Limb(
+				1,
+				LimbType::Sensor {
+					report_interval: 300,
+					data: None,
+				},
+			)),
+			Some(Limb(2, LimbType::Actuator(Actuator::Light(false)))),
+		];
+		let publishes = discovery_configs("samn", "homeassistant", 7, &info, &limbs);
+		let topics: Vec<_> = publishes.iter().map(|p| p.topic.as_str()).collect();
+		assert_eq!(
+			topics,
+			[
+				"homeassistant/sensor/samn_7_0_temperature/config",
+				"homeassistant/sensor/samn_7_0_humidity/config",
+				"homeassistant/light/samn_7_2_light/config",
+			]
+		);
+		assert!(publishes.iter().all(|p| p.retain));
+
+		let light: Value = serde_json::from_slice(&publishes[2].payload).unwrap();
+		assert_eq!(light["command_topic"], "samn/7/2/set");
+		assert_eq!(light["state_topic"], "samn/7/2/state");
+		assert_eq!(light["availability"][0]["topic"], "samn/availability");
+		assert_eq!(light["availability"][1]["topic"], "samn/7/availability");
+		assert_eq!(light["availability_mode"], "all");
+		assert_eq!(light["device"]["model"], "Samn Switch");
+		let temperature: Value = serde_json::from_slice(&publishes[0].payload).unwrap();
+		assert_eq!(temperature["unit_of_measurement"], "°C");
+		assert_eq!(temperature["expire_after"], 600);
+	}
+}
@@ -0,0 +1,580 @@
+//! Bridges decoded radio messages to an MQTT broker
+//!
+//! Topics, with the default `samn` prefix:
+//! - `samn/availability` `online`/`offline` for the bridge itself, retained, `offline` is
+//!   its last will
+//! - `samn/<node_id>/<limb_id>/state` current limb state as a [`JsonLimb`], retained,
+//!   also the sensors of a [`Response::LimbsAt`] since that's how nodes report
+//! - `samn/<node_id>/<limb_id>/reading` `{"timestamp": <sample time>, "data": <JsonSensor>}`
+//!   for readings that carry their own time ([`Response::LimbsAt`], [`Response::History`]),
+//!   not retained since they can be old
+//! - `samn/<node_id>/info` [`JsonResponse::Info`], retained
+//! - `samn/<node_id>/heartbeat` `{"timestamp": <node clock>}`
+//! - `samn/<node_id>/availability` `online`, retained, refreshed with every message from the node
+//! - `samn/<node_id>/<limb_id>/set` subscribed, `ON`/`OFF`/`TOGGLE` or a [`JsonLimbType`]
+//!
+//! The topic mapping is plain functions ([`publishes_for`], [`command_for`]),
+//! [`MqttBridge`] only moves their results to and from the broker.
+//! [`discovery`] has the Home Assistant discovery configs pointing at these topics.
+pub mod discovery;
+
+use rumqttc::{
+	AsyncClient, ClientError, ConnectionError, Event, EventLoop, LastWill, MqttOptions,
+	Packet, QoS,
+};
+
+use crate::json::{JsonLimb, JsonLimbType, JsonResponse, JsonSensor};
+use crate::node::{
+	Actuator, Command, Limb, LimbId, LimbType, Limbs, Message, MessageData, NodeId,
+	NodeInfo, Response, Sensor, Timestamp,
+};
+
+pub const DEFAULT_PREFIX: &str = "samn";
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Publish {
+	pub topic: String,
+	pub payload: Vec<u8>,
+	pub retain: bool,
+}
+
+impl Publish {
+	fn new(topic: String, payload: impl Into<Vec<u8>>, retain: bool) -> Self {
+		Self {
+			topic,
+			payload: payload.into(),
+			retain,
+		}
+	}
+}
+
+pub fn limb_topic(prefix: &str, node_id: NodeId, limb_id: LimbId, leaf: &str) -> String {
+	format!("{prefix}/{node_id}/{limb_id}/{leaf}")
+}
+pub fn node_topic(prefix: &str, node_id: NodeId, leaf: &str) -> String {
+	format!("{prefix}/{node_id}/{leaf}")
+}
+/// The bridge's own `online`/`offline`, nodes can't be reached while it's offline
+pub fn bridge_availability_topic(prefix: &str) -> String {
+	format!("{prefix}/availability")
+}
+
+fn limb_state_publish(prefix: &str, node_id: NodeId, limb: &Limb) -> Publish {
+	let json = serde_json::to_vec(&JsonLimb::from(limb)).unwrap_or_default();
+	Publish::new(limb_topic(prefix, node_id, limb.0, "state"), json, true)
+}
+
+fn limbs_publishes(prefix: &str, node_id: NodeId, limbs: &Limbs) -> Vec<Publish> {
+	limbs
+		.iter()
+		.flatten()
+		.map(|limb| limb_state_publish(prefix, node_id, limb))
+		.collect()
+}
+
+fn reading_publish(
+	prefix: &str,
+	node_id: NodeId,
+	limb_id: LimbId,
+	timestamp: Timestamp,
+	sensor: &Sensor,
+) -> Publish {
+	let json =
+		serde_json::json!({ "timestamp": timestamp, "data": JsonSensor::from(sensor) });
+	Publish::new(
+		limb_topic(prefix, node_id, limb_id, "reading"),
+		json.to_string(),
+		false,
+	)
+}
+
+/// What to publish for a message received from `node_id`
+///
+/// Relayed messages are published under the node they were relayed for.
+pub fn publishes_for(prefix: &str, node_id: NodeId, message: &Message) -> Vec<Publish> {
+	let (node_id, data) = match message {
+		Message::Message(data) => (node_id, data),
+		Message::RelayMessage(node_id, data) => (*node_id, data),
+		_ => return vec![],
+	};
+	let MessageData::Response { response, .. } = data else {
+		return vec![];
+	};
+
+	let mut publishes = match response {
+		Response::Limbs(limbs) => limbs_publishes(prefix, node_id, limbs),
+		// Nodes report with these, their sensors go on the state topics too so Home
+		// Assistant's entities keep updating. A buffered one leaves an older value there
+		// until the next report. Actuator states only matter when current.
+		Response::LimbsAt(timestamp, limbs) => limbs
+			.iter()
+			.flatten()
+			.filter_map(|limb| match &limb.1 {
+				LimbType::Sensor {
+					data: Some(sensor), ..
+				} => Some([
+					reading_publish(prefix, node_id, limb.0, *timestamp, sensor),
+					limb_state_publish(prefix, node_id, limb),
+				]),
+				_ => None,
+			})
+			.flatten()
+			.collect(),
+		// Pages of old readings, never the state
+		Response::History(history) => history
+			.samples
+			.iter()
+			.flatten()
+			.map(|(timestamp, sensor)| {
+				reading_publish(prefix, node_id, history.limb, *timestamp, sensor)
+			})
+			.collect(),
+		Response::Info(_) => vec![Publish::new(
+			node_topic(prefix, node_id, "info"),
+			serde_json::to_vec(&JsonResponse::from(response)).unwrap_or_default(),
+			true,
+		)],
+		Response::Heartbeat(timestamp) => vec![Publish::new(
+			node_topic(prefix, node_id, "heartbeat"),
+			serde_json::json!({ "timestamp": timestamp }).to_string(),
+			false,
+		)],
+		Response::Ok | Response::ErrLimbNotFound | Response::ErrLimbTypeDoesntMatch => vec![],
+	};
+	publishes.push(Publish::new(
+		node_topic(prefix, node_id, "availability"),
+		ONLINE,
+		true,
+	));
+	publishes
+}
+
+/// Parses a `<prefix>/<node_id>/<limb_id>/set` publish into a command for that node
+pub fn command_for(
+	prefix: &str,
+	topic: &str,
+	payload: &[u8],
+) -> Option<(NodeId, Command)> {
+	let mut parts = topic.strip_prefix(prefix)?.strip_prefix('/')?.split('/');
+	let node_id: NodeId = parts.next()?.parse().ok()?;
+	let limb_id: LimbId = parts.next()?.parse().ok().filter(|id| *id < 16)?;
+	if parts.next()? != "set" || parts.next().is_some() {
+		return None;
+	}
+
+	let payload = core::str::from_utf8(payload).ok()?.trim();
+	let light =
+		|on| Command::SetLimb(Limb(limb_id, LimbType::Actuator(Actuator::Light(on))));
+	let command = match payload.to_ascii_lowercase().as_str() {
+		"on" | "true" | "1" => light(true),
+		"off" | "false" | "0" => light(false),
+		"toggle" => Command::ToggleLimb(limb_id),
+		_ => {
+			let limb_type: JsonLimbType = serde_json::from_str(payload).ok()?;
+			Command::SetLimb(Limb(limb_id, limb_type.try_into().ok()?))
+		}
+	};
+	Some((node_id, command))
+}
+
+/// MQTT side of HQ
+///
+/// ```ignore
+/// let (bridge, mut eventloop) = MqttBridge::new(MqttOptions::new("samn-hq", "localhost", 1883), "samn");
+/// loop {
+///   tokio::select! {
+///     command = bridge.next_command(&mut eventloop) => { /* send command to node */ }
+///     (node_id, message) = radio_rx.recv() => bridge.publish_message(node_id, &message).await?,
+///   }
+/// }
+/// ```
+pub struct MqttBridge {
+	client: AsyncClient,
+	prefix: String,
+}
+
+impl MqttBridge {
+	/// Sets `offline` on [`bridge_availability_topic`] as the last will of `options`
+	pub fn new(mut options: MqttOptions, prefix: &str) -> (Self, EventLoop) {
+		options.set_last_will(LastWill::new(
+			bridge_availability_topic(prefix),
+			OFFLINE,
+			QoS::AtLeastOnce,
+			true,
+		));
+		let (client, eventloop) = AsyncClient::new(options, 32);
+		(
+			Self {
+				client,
+				prefix: prefix.to_string(),
+			},
+			eventloop,
+		)
+	}
+	pub fn prefix(&self) -> &str {
+		&self.prefix
+	}
+	pub fn client(&self) -> &AsyncClient {
+		&self.client
+	}
+
+	fn set_filter(&self) -> String {
+		format!("{}/+/+/set", self.prefix)
+	}
+
+	/// Subscribes to every limb's `set` topic
+	///
+	/// [`MqttBridge::next_command`] already does on every connect the broker didn't keep
+	/// our session for.
+	pub async fn subscribe(&self) -> Result<(), ClientError> {
+		self
+			.client
+			.subscribe(self.set_filter(), QoS::AtLeastOnce)
+			.await
+	}
+
+	pub async fn publish(&self, publish: Publish) -> Result<(), ClientError> {
+		self
+			.client
+			.publish(
+				publish.topic,
+				QoS::AtLeastOnce,
+				publish.retain,
+				publish.payload,
+			)
+			.await
+	}
+
+	/// Publishes everything a message from `node_id` carries
+	pub async fn publish_message(
+		&self,
+		node_id: NodeId,
+		message: &Message,
+	) -> Result<(), ClientError> {
+		for publish in publishes_for(&self.prefix, node_id, message) {
+			self.publish(publish).await?;
+		}
+		Ok(())
+	}
+
+	/// Announces a node's limbs to Home Assistant, call again when its limbs change
+	pub async fn publish_discovery(
+		&self,
+		discovery_prefix: &str,
+		node_id: NodeId,
+		info: &NodeInfo,
+		limbs: &Limbs,
+	) -> Result<(), ClientError> {
+		let publishes =
+			discovery::discovery_configs(&self.prefix, discovery_prefix, node_id, info, limbs);
+		for publish in publishes {
+			self.publish(publish).await?;
+		}
+		Ok(())
+	}
+
+	/// Marks a node offline, for when HQ missed its heartbeats
+	pub async fn publish_offline(&self, node_id: NodeId) -> Result<(), ClientError> {
+		let topic = node_topic(&self.prefix, node_id, "availability");
+		self.publish(Publish::new(topic, OFFLINE, true)).await
+	}
+
+	/// Drives the connection until a valid `set` publish comes in
+	///
+	/// Must be polled continuously, it's also what sends our publishes out.
+	/// Sets the bridge `online` every time it (re)connects. With a clean session (rumqttc's
+	/// default) the broker forgets our subscription, so it subscribes to the `set` topics
+	/// again first.
+	pub async fn next_command(
+		&self,
+		eventloop: &mut EventLoop,
+	) -> Result<(NodeId, Command), ConnectionError> {
+		loop {
+			match eventloop.poll().await? {
+				Event::Incoming(Packet::ConnAck(connack)) => {
+					// Can't await here, we're what sends them. Only fails when the request
+					// queue is full, which won't be the case right after connecting.
+					if !connack.session_present {
+						let _ = self
+							.client
+							.try_subscribe(self.set_filter(), QoS::AtLeastOnce);
+					}
+					let _ = self.client.try_publish(
+						bridge_availability_topic(&self.prefix),
+						QoS::AtLeastOnce,
+						true,
+						ONLINE,
+					);
+				}
+				Event::Incoming(Packet::Publish(publish)) => {
+					if let Some(command) =
+						command_for(&self.prefix, &publish.topic, &publish.payload)
+					{
+						return Ok(command);
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::node::SensorHistory;
+
+	#[test]
+	fn publishes_limbs() {
+		let message = Message::Message(MessageData::Response {
+			id: None,
+			response: Response::Limbs([
+				Some(Limb(
+					0,
+					LimbType::Sensor {
+						report_interval: 300,
+						data: Some(Sensor::TempHum((2150, 40))),
+					},
+				)),
+				Some(Limb(1, LimbType::Actuator(Actuator::Light(true)))),
+				None,
+			]),
+		});
+		let publishes = publishes_for("samn", 7, &message);
+		let topics: Vec<_> = publishes.iter().map(|p| p.topic.as_str()).collect();
+		assert_eq!(
+			topics,
+			["samn/7/0/state", "samn/7/1/state", "samn/7/availability"]
+		);
+		assert_eq!(
+			serde_json::from_slice::<serde_json::Value>(&publishes[1].payload).unwrap(),
+			serde_json::json!({"id": 1, "type": "actuator", "actuator": {"type": "light", "on": true}})
+		);
+
+		let command = Message::Message(MessageData::Command {
+			id: 1,
+			command: Command::Info,
+		});
+		assert!(publishes_for("samn", 7, &command).is_empty());
+	}
+
+	#[test]
+	fn commands_from_set_topics() {
+		assert_eq!(
+			command_for("samn", "samn/7/1/set", b"ON"),
+			Some((
+				7,
+				Command::SetLimb(Limb(1, LimbType::Actuator(Actuator::Light(true))))
+			))
+		);
+		assert_eq!(
+			command_for("samn", "samn/7/1/set", b"toggle"),
+			Some((7, Command::ToggleLimb(1)))
+		);
+		assert_eq!(
+			command_for(
+				"samn",
+				"samn/7/2/set",
+				br#"{"type": "sensor", "report_interval": 60, "data": null}"#
+			),
+			Some((
+				7,
+				Command::SetLimb(Limb(
+					2,
+					LimbType::Sensor {
+						report_interval: 60,
+						data: None
+					}
+				))
+			))
+		);
+		assert_eq!(command_for("samn", "samn/7/1/state", b"ON"), None);
+		assert_eq!(command_for("samn", "samn/7/16/set", b"ON"), None);
+		assert_eq!(command_for("samn", "other/7/1/set", b"ON"), None);
+		assert_eq!(command_for("samn", "samn/7/1/set", b"dim"), None);
+	}
+
+	#[test]
+	fn timestamped_readings() {
+		let t = 1_700_000_000;
+		let message = Message::Message(MessageData::Response {
+			id: None,
+			response: Response::LimbsAt(
+				t,
+				[
+					Some(Limb(
+						0,
+						LimbType::Sensor {
+							report_interval: 300,
+							data: Some(Sensor::Battery(80)),
+						},
+					)),
+					Some(Limb(1, LimbType::Actuator(Actuator::Light(true)))),
+					None,
+				],
+			),
+		});
+		let publishes = publishes_for("samn", 7, &message);
+		assert_eq!(
+			publishes[0],
+			Publish::new(
+				"samn/7/0/reading".into(),
+				serde_json::json!({"timestamp": t, "data": {"type": "battery", "percent": 80}})
+					.to_string(),
+				false
+			)
+		);
+		// Still the sensor's state, the light isn't
+		assert_eq!(publishes[1].topic, "samn/7/0/state");
+		assert!(publishes[1].retain);
+		assert_eq!(
+			serde_json::from_slice::<serde_json::Value>(&publishes[1].payload).unwrap()["data"],
+			serde_json::json!({"type": "battery", "percent": 80})
+		);
+		assert_eq!(publishes[2].topic, "samn/7/availability");
+		assert_eq!(publishes.len(), 3);
+
+		let message = Message::Message(MessageData::Response {
+			id: Some(2),
+			response: Response::History(SensorHistory {
+				limb: 3,
+				samples: [
+					Some((t, Sensor::Current(100))),
+					Some((t + 60, Sensor::Current(120))),
+					None,
+					None,
+					None,
+					None,
+				],
+			}),
+		});
+		let publishes = publishes_for("samn", 7, &message);
+		assert!(!publishes.iter().any(|p| p.topic.ends_with("/state")));
+		let readings: Vec<_> = publishes
+			.iter()
+			.filter(|p| p.topic == "samn/7/3/reading" && !p.retain)
+			.map(|p| serde_json::from_slice::<serde_json::Value>(&p.payload).unwrap())
+			.collect();
+		assert_eq!(readings.len(), 2);
+		assert_eq!(readings[1]["timestamp"], t + 60);
+		assert_eq!(readings[1]["data"]["milliamps"], 120);
+	}
+
+	/// Broker on localhost in a thread of its own
+	fn start_broker() -> u16 {
+		let port = std::net::TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.port();
+		let config: rumqttd::Config = serde_json::from_value(serde_json::json!({
+			"id": 0,
+			"router": {
+				"max_connections": 10,
+				"max_outgoing_packet_count": 200,
+				"max_segment_size": 104857600,
+				"max_segment_count": 10,
+			},
+			"v4": {
+				"1": {
+					"name": "v4-1",
+					"listen": format!("127.0.0.1:{port}"),
+					"next_connection_delay_ms": 1,
+					"connections": {
+						"connection_timeout_ms": 5000,
+						"max_payload_size": 20480,
+						"max_inflight_count": 100,
+						"dynamic_filters": true,
+					},
+				},
+			},
+		}))
+		.unwrap();
+		std::thread::spawn(move || rumqttd::Broker::new(config).start().unwrap());
+		// Wait for it to listen
+		for _ in 0..100 {
+			if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+				return port;
+			}
+			std::thread::sleep(std::time::Duration::from_millis(20));
+		}
+		panic!("broker didn't start");
+	}
+
+	#[tokio::test]
+	async fn bridge_against_broker() {
+		let port = start_broker();
+		let timeout = |secs| std::time::Duration::from_secs(secs);
+
+		// Another client that watches the bridge and plays Home Assistant
+		let (observer, mut observer_events) =
+			AsyncClient::new(MqttOptions::new("samn-observer", "127.0.0.1", port), 10);
+		observer
+			.subscribe("samn-test/availability", QoS::AtLeastOnce)
+			.await
+			.unwrap();
+		let (published, mut observed) = tokio::sync::mpsc::unbounded_channel();
+		tokio::spawn(async move {
+			while let Ok(event) = observer_events.poll().await {
+				if let Event::Incoming(Packet::Publish(publish)) = event {
+					let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+					published.send((publish.topic, payload)).ok();
+				}
+			}
+		});
+		// Subscribed before the bridge connects, so we see its will
+		tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+		let options = MqttOptions::new("samn-bridge-test", "127.0.0.1", port);
+		let (bridge, mut eventloop) = MqttBridge::new(options, "samn-test");
+		let (command, ()) = tokio::join!(
+			tokio::time::timeout(timeout(5), bridge.next_command(&mut eventloop)),
+			async {
+				// Online goes out after the subscription, so set publishes reach the bridge now
+				let online = observed.recv().await.unwrap();
+				assert_eq!(online, ("samn-test/availability".into(), ONLINE.into()));
+				observer
+					.publish("samn-test/3/1/set", QoS::AtLeastOnce, false, "TOGGLE")
+					.await
+					.unwrap();
+			}
+		);
+		assert_eq!(
+			command.expect("no command from broker").unwrap(),
+			(3, Command::ToggleLimb(1))
+		);
+
+		// Connection lost, the broker drops our subscription and rumqttc reconnects
+		eventloop.clean();
+		let (command, ()) = tokio::join!(
+			tokio::time::timeout(timeout(5), bridge.next_command(&mut eventloop)),
+			async {
+				let offline = observed.recv().await.unwrap();
+				assert_eq!(offline, ("samn-test/availability".into(), OFFLINE.into()));
+				let online = observed.recv().await.unwrap();
+				assert_eq!(online, ("samn-test/availability".into(), ONLINE.into()));
+				observer
+					.publish("samn-test/4/2/set", QoS::AtLeastOnce, false, "ON")
+					.await
+					.unwrap();
+			}
+		);
+		assert_eq!(
+			command.expect("no command after reconnecting").unwrap(),
+			(
+				4,
+				Command::SetLimb(Limb(2, LimbType::Actuator(Actuator::Light(true))))
+			)
+		);
+
+		// Going away without a disconnect, the broker publishes our will
+		drop(eventloop);
+		let offline = tokio::time::timeout(timeout(5), observed.recv())
+			.await
+			.expect("no last will")
+			.unwrap();
+		assert_eq!(offline, ("samn-test/availability".into(), OFFLINE.into()));
+	}
+}
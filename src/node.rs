@@ -1,15 +1,17 @@
 use bity::{BitReader, BitWriter};
-use errors::Discriminant;
+use errors::{Discriminant, FromDiscriminant};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 pub const COMMAND_ID_MAX:u8 = 2u8.pow(6);
 
 
-#[derive(Clone, Debug)]
-#[repr(u8)]
+/// Keeps the codes firmware has been reporting, add variants at the end
+#[derive(Clone, Debug, Discriminant, FromDiscriminant)]
+#[discriminant(size = 20)]
 pub enum NodeSerializeError {
 	BitError(bity::Error),
+	#[discriminant(at = 3)]
 	InvalidBoardCode,
 	InvalidSensorCode,
 	InvalidActuatorCode,
@@ -17,8 +19,10 @@ pub enum NodeSerializeError {
 	InvalidResponseCode,
 	InvalidMessageCode,
 	InvalidMessageVersion,
+	InvalidLogChunkLength,
+	/// A [`SensorHistory`] with more samples than [`HISTORY_PAGE_MAX`]
+	InvalidHistoryCount,
 }
-const ERROR_MAX: u8 = 20;
 pub type NodeBitsResult<T> = Result<T, NodeSerializeError>;
 
 impl From<bity::Error> for NodeSerializeError {
@@ -27,25 +31,31 @@ impl From<bity::Error> for NodeSerializeError {
 	}
 }
 
-impl Discriminant for NodeSerializeError {
-	fn discriminant(&self) -> u8 {
-		// SAFETY: Because `Self` is marked `repr(u8)`, its layout is a `repr(C)` `union`
-		// between `repr(C)` structs, each of which has the `u8` discriminant as its first
-		// field, so we can read the discriminant without offsetting the pointer.
-		match self {
-			Self::BitError(err) => err.discriminant(),
-			_ => {
-				(unsafe { *<*const _>::from(self).cast::<u8>() }) + bity::Error::discriminant_max()
-			}
-		}
-	}
-	fn discriminant_max() -> u8 {
-		ERROR_MAX
+impl core::fmt::Display for NodeSerializeError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		Self::describe(self.discriminant(), f)
 	}
 }
 
 pub type NodeId = u32;
 pub type NodeAddress = u16;
+/// Every node receives messages sent to this address
+pub const BROADCAST_ADDRESS: NodeAddress = 0xFFFF;
+/// Addresses from here up are groups, HQ never gives them to a node
+pub const GROUP_ADDRESS_MIN: NodeAddress = 0xFF00;
+/// The address messages to `group` are sent to, group 255 is [`BROADCAST_ADDRESS`]
+pub const fn group_address(group: u8) -> NodeAddress {
+	GROUP_ADDRESS_MIN + group as NodeAddress
+}
+/// Whether `address` is a group (or the broadcast) address, messages to it aren't acked
+pub const fn is_group_address(address: NodeAddress) -> bool {
+	address >= GROUP_ADDRESS_MIN
+}
+/// Seconds since the unix epoch (UTC)
+///
+/// This is the epoch HQ uses when syncing nodes with [`Command::SyncTime`],
+/// so every timestamp a node sends back is in this same epoch.
+pub type Timestamp = u32;
 /// max 16 (4 bits)
 pub type LimbId = u8;
 
@@ -111,7 +121,7 @@ impl NodeInfo {
 		})
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self.board {
 			Board::SamnV8 => 0,
 			Board::SamnV9 => 1,
@@ -147,7 +157,7 @@ impl Sensor {
 			}
 			Sensor::TempHum((temp, hum)) => {
 				// Write temperature (16 bits)
-				writer.write_bits(*temp as u32, 16)?;
+				writer.write_bits(*temp as u16 as u32, 16)?;
 				// Write humidity (8 bits)
 				writer.write_bits(*hum as u32, 8)?;
 			}
@@ -184,7 +194,7 @@ impl Sensor {
 		}
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self {
 			Sensor::Battery(_) => 0,
 			Sensor::TempHum(_) => 1,
@@ -192,6 +202,105 @@ impl Sensor {
 			// Add other variants and codes here, up to 16
 		}
 	}
+
+	/// Short snake_case name, used in storage keys and topics
+	pub fn name(&self) -> &'static str {
+		match self {
+			Sensor::Battery(_) => "battery",
+			Sensor::TempHum(_) => "temp_hum",
+			Sensor::Current(_) => "current",
+		}
+	}
+
+	/// Numeric fields in the order they appear in the variant, unused ones are 0
+	pub fn fields(&self) -> [i32; 2] {
+		match self {
+			Sensor::Battery(level) => [*level as i32, 0],
+			Sensor::TempHum((temp, hum)) => [*temp as i32, *hum as i32],
+			Sensor::Current(current) => [*current as i32, 0],
+		}
+	}
+	/// Same sensor as this one with other `fields`, see [`Sensor::fields`]
+	pub fn with_fields(&self, fields: [i32; 2]) -> Sensor {
+		match self {
+			Sensor::Battery(_) => Sensor::Battery(fields[0] as u8),
+			Sensor::TempHum(_) => Sensor::TempHum((fields[0] as i16, fields[1] as u8)),
+			Sensor::Current(_) => Sensor::Current(fields[0] as u16),
+		}
+	}
+	pub fn is_same_sensor(&self, other: &Sensor) -> bool {
+		core::mem::discriminant(self) == core::mem::discriminant(other)
+	}
+
+	/// Writes this reading relative to `prev`, used to pack several samples in one payload
+	///
+	/// A 0 bit followed by small zigzag deltas when they fit, otherwise a 1 bit
+	/// followed by the full reading.
+	fn serialize_delta_to_bits(&self, prev: &Sensor, writer: &mut BitWriter) -> NodeBitsResult<()> {
+		let deltas = match (prev, self) {
+			(Sensor::Battery(prev), Sensor::Battery(level)) => {
+				fits_delta(*prev as i32, *level as i32, 4).map(|level| (level, 4, 0, 0))
+			}
+			(Sensor::TempHum((prev_temp, prev_hum)), Sensor::TempHum((temp, hum))) => {
+				match (
+					fits_delta(*prev_temp as i32, *temp as i32, 8),
+					fits_delta(*prev_hum as i32, *hum as i32, 5),
+				) {
+					(Some(temp), Some(hum)) => Some((temp, 8, hum, 5)),
+					_ => None,
+				}
+			}
+			(Sensor::Current(prev), Sensor::Current(current)) => {
+				fits_delta(*prev as i32, *current as i32, 8).map(|current| (current, 8, 0, 0))
+			}
+			_ => None,
+		};
+
+		if let Some((a, a_bits, b, b_bits)) = deltas {
+			writer.write_bits(0, 1)?;
+			writer.write_bits(a, a_bits)?;
+			writer.write_bits(b, b_bits)?;
+		} else {
+			writer.write_bits(1, 1)?;
+			self.serialize_to_bits(writer)?;
+		}
+		Ok(())
+	}
+
+	fn deserialize_delta_from_bits(prev: &Sensor, reader: &mut BitReader) -> NodeBitsResult<Self> {
+		// Read is_full bit (1 bit)
+		if reader.read_bits(1)? == 1 {
+			return Sensor::deserialize_from_bits(reader);
+		}
+		match prev {
+			Sensor::Battery(prev) => {
+				let level = unzigzag(reader.read_bits(4)?);
+				Ok(Sensor::Battery((*prev as i32 + level) as u8))
+			}
+			Sensor::TempHum((prev_temp, prev_hum)) => {
+				let temp = unzigzag(reader.read_bits(8)?);
+				let hum = unzigzag(reader.read_bits(5)?);
+				Ok(Sensor::TempHum((
+					(*prev_temp as i32 + temp) as i16,
+					(*prev_hum as i32 + hum) as u8,
+				)))
+			}
+			Sensor::Current(prev) => {
+				let current = unzigzag(reader.read_bits(8)?);
+				Ok(Sensor::Current((*prev as i32 + current) as u16))
+			}
+		}
+	}
+}
+
+/// Zigzag encodes `value - prev` if it fits in `bits`
+fn fits_delta(prev: i32, value: i32, bits: u8) -> Option<u32> {
+	let delta = value - prev;
+	let zigzag = ((delta << 1) ^ (delta >> 31)) as u32;
+	(zigzag < 1 << bits).then_some(zigzag)
+}
+fn unzigzag(value: u32) -> i32 {
+	(value >> 1) as i32 ^ -((value & 1) as i32)
 }
 
 /// Max 16 Variants
@@ -232,12 +341,19 @@ impl Actuator {
 		}
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self {
 			Actuator::Light(_) => 0,
 			// Add other variants and codes here, up to 16
 		}
 	}
+
+	/// Short snake_case name, used in storage keys and topics
+	pub fn name(&self) -> &'static str {
+		match self {
+			Actuator::Light(_) => "light",
+		}
+	}
 }
 
 /// Max 2 Variants
@@ -332,6 +448,291 @@ impl Limb {
 	}
 }
 
+/// Wall clock time sent from HQ to a node
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(PartialEq, Eq))]
+#[derive(Clone, Debug)]
+pub struct TimeSync {
+	/// Seconds since the unix epoch, taken when HQ started transmitting
+	pub seconds: Timestamp,
+	/// Milliseconds within that second (0-999, 10 bits)
+	pub millis: u16,
+	/// Estimated ms between HQ taking the time and the node receiving it (8 bits)
+	///
+	/// Covers HQ's transmit path (spi/serial, retries, air time), the node adds it on sync.
+	pub latency: u8,
+}
+
+impl TimeSync {
+	/// Takes the current system time, `latency` is the expected transmit latency in ms
+	#[cfg(feature = "std")]
+	pub fn now(latency: u8) -> Self {
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap_or_default();
+		Self {
+			seconds: now.as_secs() as Timestamp,
+			millis: now.subsec_millis() as u16,
+			latency,
+		}
+	}
+
+	/// Epoch time in ms this sync represents, latency corrected
+	pub fn epoch_ms(&self) -> u64 {
+		self.seconds as u64 * 1000 + self.millis as u64 + self.latency as u64
+	}
+
+	fn serialize_to_bits(&self, writer: &mut BitWriter) -> NodeBitsResult<()> {
+		// Write seconds (32 bits)
+		writer.write_bits(self.seconds, 32)?;
+		// Write millis (10 bits)
+		writer.write_bits(self.millis as u32, 10)?;
+		// Write latency (8 bits)
+		writer.write_bits(self.latency as u32, 8)?;
+		Ok(())
+	}
+
+	fn deserialize_from_bits(reader: &mut BitReader) -> NodeBitsResult<Self> {
+		let seconds = reader.read_bits(32)?;
+		let millis = reader.read_bits(10)? as u16;
+		let latency = reader.read_bits(8)? as u8;
+		Ok(TimeSync {
+			seconds,
+			millis,
+			latency,
+		})
+	}
+}
+
+/// Why a node last (re)started, as far as its chip can tell (3 bits)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(PartialEq, Eq))]
+#[derive(Clone, Copy, Debug)]
+pub enum ResetReason {
+	PowerOn,
+	/// Reset pin
+	External,
+	Brownout,
+	Watchdog,
+	/// The firmware asked for it
+	Software,
+	Panic,
+	Unknown,
+}
+
+impl ResetReason {
+	pub(crate) fn code(&self) -> u8 {
+		match self {
+			ResetReason::PowerOn => 0,
+			ResetReason::External => 1,
+			ResetReason::Brownout => 2,
+			ResetReason::Watchdog => 3,
+			ResetReason::Software => 4,
+			ResetReason::Panic => 5,
+			ResetReason::Unknown => 7,
+		}
+	}
+	/// Codes we don't know are [`ResetReason::Unknown`], so a newer node still gets through
+	pub fn from_code(code: u8) -> Self {
+		match code {
+			0 => ResetReason::PowerOn,
+			1 => ResetReason::External,
+			2 => ResetReason::Brownout,
+			3 => ResetReason::Watchdog,
+			4 => ResetReason::Software,
+			5 => ResetReason::Panic,
+			_ => ResetReason::Unknown,
+		}
+	}
+}
+
+/// An error a node ran into, sent with [`Message::FaultReport`]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(PartialEq, Eq))]
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+	/// The error's [`Discriminant`], HQ turns it back into text with
+	/// [`errors::describe`]
+	pub code: u8,
+	/// How many times it happened since it was last reported
+	pub count: u16,
+	/// Seconds since boot, the last time it happened
+	pub uptime: u32,
+	/// Why the node started the run it happened in
+	pub reset: ResetReason,
+}
+
+impl Fault {
+	fn serialize_to_bits(&self, writer: &mut BitWriter) -> NodeBitsResult<()> {
+		// Write code (8 bits)
+		writer.write_bits(self.code as u32, 8)?;
+		// Write count (16 bits)
+		writer.write_bits(self.count as u32, 16)?;
+		// Write uptime (32 bits)
+		writer.write_bits(self.uptime, 32)?;
+		// Write reset reason (3 bits)
+		writer.write_bits(self.reset.code() as u32, 3)?;
+		Ok(())
+	}
+
+	fn deserialize_from_bits(reader: &mut BitReader) -> NodeBitsResult<Self> {
+		let code = reader.read_bits(8)? as u8;
+		let count = reader.read_bits(16)? as u16;
+		let uptime = reader.read_bits(32)?;
+		let reset = ResetReason::from_code(reader.read_bits(3)? as u8);
+		Ok(Fault {
+			code,
+			count,
+			uptime,
+			reset,
+		})
+	}
+}
+
+/// Max bytes in a [`LogChunk`] (5 bits), as much as fits in a payload
+pub const LOG_CHUNK_MAX: usize = 20;
+
+/// A piece of a [`Log`](crate::log::Log), sent with [`Message::Log`]
+///
+/// HQ puts the chunks of a log back together with
+/// [`LogAssembler`](crate::log::LogAssembler).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(PartialEq, Eq))]
+#[derive(Clone, Debug)]
+pub struct LogChunk {
+	/// Which log this is a part of, wraps around (4 bits)
+	pub log: u8,
+	/// Position in the log (4 bits)
+	pub part: u8,
+	/// Whether it's the log's last chunk
+	pub last: bool,
+	/// Only the first `len` bytes are used
+	pub len: u8,
+	pub data: [u8; LOG_CHUNK_MAX],
+}
+
+impl LogChunk {
+	pub fn data(&self) -> &[u8] {
+		&self.data[..self.len as usize]
+	}
+
+	fn serialize_to_bits(&self, writer: &mut BitWriter) -> NodeBitsResult<()> {
+		// Write log (4 bits)
+		writer.write_bits(self.log as u32, 4)?;
+		// Write part (4 bits)
+		writer.write_bits(self.part as u32, 4)?;
+		// Write last (1 bit)
+		writer.write_bits(self.last as u32, 1)?;
+		// Write len (5 bits)
+		writer.write_bits(self.len as u32, 5)?;
+		// Write data (len bytes)
+		for byte in self.data() {
+			writer.write_bits(*byte as u32, 8)?;
+		}
+		Ok(())
+	}
+
+	fn deserialize_from_bits(reader: &mut BitReader) -> NodeBitsResult<Self> {
+		let log = reader.read_bits(4)? as u8;
+		let part = reader.read_bits(4)? as u8;
+		let last = reader.read_bits(1)? != 0;
+		let len = reader.read_bits(5)? as u8;
+		if len as usize > LOG_CHUNK_MAX {
+			return Err(NodeSerializeError::InvalidLogChunkLength);
+		}
+		let mut data = [0u8; LOG_CHUNK_MAX];
+		for byte in data[..len as usize].iter_mut() {
+			*byte = reader.read_bits(8)? as u8;
+		}
+		Ok(LogChunk {
+			log,
+			part,
+			last,
+			len,
+			data,
+		})
+	}
+}
+
+/// Max samples in a [`SensorHistory`] (3 bits)
+///
+/// Around 6 TempHum samples taken minutes apart fit in one payload,
+/// fewer if the readings jump around.
+pub const HISTORY_PAGE_MAX: usize = 6;
+
+/// Buffered readings of a single limb, sent with [`Response::History`]
+///
+/// First sample is sent in full, the following ones as time + value deltas from
+/// the previous one, so a page is much smaller than the same readings sent one by one.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(PartialEq, Eq))]
+#[derive(Clone, Debug)]
+pub struct SensorHistory {
+	pub limb: LimbId,
+	/// (sample time, reading), oldest first
+	pub samples: [Option<(Timestamp, Sensor)>; HISTORY_PAGE_MAX],
+}
+
+impl SensorHistory {
+	fn serialize_to_bits(&self, writer: &mut BitWriter) -> NodeBitsResult<()> {
+		// Write limb id (4 bits)
+		writer.write_bits(self.limb as u32, 4)?;
+		// Write sample count (3 bits)
+		let count = self.samples.iter().flatten().count();
+		writer.write_bits(count as u32, 3)?;
+
+		let mut prev: Option<&(Timestamp, Sensor)> = None;
+		for sample in self.samples.iter().flatten() {
+			let (timestamp, sensor) = sample;
+			if let Some((prev_timestamp, prev_sensor)) = prev {
+				// Write time delta, 1 bit + 10 bits if under ~17 min, otherwise full 32 bits
+				let delta = timestamp.wrapping_sub(*prev_timestamp);
+				if delta < 1 << 10 {
+					writer.write_bits(0, 1)?;
+					writer.write_bits(delta, 10)?;
+				} else {
+					writer.write_bits(1, 1)?;
+					writer.write_bits(*timestamp, 32)?;
+				}
+				sensor.serialize_delta_to_bits(prev_sensor, writer)?;
+			} else {
+				// Write timestamp (32 bits)
+				writer.write_bits(*timestamp, 32)?;
+				sensor.serialize_to_bits(writer)?;
+			}
+			prev = Some(sample);
+		}
+		Ok(())
+	}
+
+	fn deserialize_from_bits(reader: &mut BitReader) -> NodeBitsResult<Self> {
+		let limb = reader.read_bits(4)? as u8;
+		let count = reader.read_bits(3)? as usize;
+		if count > HISTORY_PAGE_MAX {
+			return Err(NodeSerializeError::InvalidHistoryCount);
+		}
+
+		let mut samples: [Option<(Timestamp, Sensor)>; HISTORY_PAGE_MAX] = Default::default();
+		let mut prev: Option<(Timestamp, Sensor)> = None;
+		for sample in samples.iter_mut().take(count) {
+			let next = if let Some((prev_timestamp, prev_sensor)) = &prev {
+				let timestamp = if reader.read_bits(1)? == 0 {
+					prev_timestamp.wrapping_add(reader.read_bits(10)?)
+				} else {
+					reader.read_bits(32)?
+				};
+				(timestamp, Sensor::deserialize_delta_from_bits(prev_sensor, reader)?)
+			} else {
+				let timestamp = reader.read_bits(32)?;
+				(timestamp, Sensor::deserialize_from_bits(reader)?)
+			};
+			*sample = Some(next.clone());
+			prev = Some(next);
+		}
+		Ok(SensorHistory { limb, samples })
+	}
+}
+
 /// Max 16 Variants
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "std", derive(PartialEq, Eq))]
@@ -347,9 +748,31 @@ pub enum Command {
 	ToggleLimb(LimbId),
 	/// Set a limb
 	SetLimbType(LimbType),
+	/// Set the node's clock, can be sent to a single node or all of them
+	SyncTime(TimeSync),
+	/// Get buffered readings of `limb` taken at or after `since`
+	///
+	/// Node answers with [`Response::History`] pages, HQ asks again from the
+	/// last timestamp it got + 1 until a page comes back empty.
+	FetchHistory { limb: LimbId, since: Timestamp },
+	/// Move to another radio channel once the node's clock reaches `at`
+	///
+	/// Sent to every node ahead of time, so they all switch together.
+	ChangeChannel { channel: u8, at: Timestamp },
+	/// Also receive messages sent to [`group_address`]`(group)`
+	JoinGroup(u8),
+	LeaveGroup(u8),
 }
 
 impl Command {
+	/// Whether running it twice is the same as running it once
+	///
+	/// Nodes should only run the others once per command id, see
+	/// [`CommandDedup`](crate::dedup::CommandDedup).
+	pub fn is_idempotent(&self) -> bool {
+		!matches!(self, Command::ToggleLimb(_))
+	}
+
 	fn serialize_to_bits(&self, writer: &mut BitWriter) -> NodeBitsResult<()> {
 		// Write command code (4 bits)
 		writer.write_bits(self.code() as u32, 4)?;
@@ -370,6 +793,29 @@ impl Command {
 				limb_type.serialize_to_bits(writer)?;
 				Ok(())
 			}
+			Command::SyncTime(time) => {
+				time.serialize_to_bits(writer)?;
+				Ok(())
+			}
+			Command::FetchHistory { limb, since } => {
+				// Write limb (4 bits)
+				writer.write_bits(*limb as u32, 4)?;
+				// Write since (32 bits)
+				writer.write_bits(*since, 32)?;
+				Ok(())
+			}
+			Command::ChangeChannel { channel, at } => {
+				// Write channel (8 bits)
+				writer.write_bits(*channel as u32, 8)?;
+				// Write at (32 bits)
+				writer.write_bits(*at, 32)?;
+				Ok(())
+			}
+			Command::JoinGroup(group) | Command::LeaveGroup(group) => {
+				// Write group (8 bits)
+				writer.write_bits(*group as u32, 8)?;
+				Ok(())
+			}
 		}
 	}
 
@@ -395,17 +841,49 @@ impl Command {
 				let limb_type = LimbType::deserialize_from_bits(reader)?;
 				Ok(Command::SetLimbType(limb_type))
 			}
+			5 => {
+				// SyncTime
+				let time = TimeSync::deserialize_from_bits(reader)?;
+				Ok(Command::SyncTime(time))
+			}
+			6 => {
+				// FetchHistory
+				let limb = reader.read_bits(4)? as u8;
+				let since = reader.read_bits(32)?;
+				Ok(Command::FetchHistory { limb, since })
+			}
+			7 => {
+				// ChangeChannel
+				let channel = reader.read_bits(8)? as u8;
+				let at = reader.read_bits(32)?;
+				Ok(Command::ChangeChannel { channel, at })
+			}
+			8 => {
+				// JoinGroup
+				let group = reader.read_bits(8)? as u8;
+				Ok(Command::JoinGroup(group))
+			}
+			9 => {
+				// LeaveGroup
+				let group = reader.read_bits(8)? as u8;
+				Ok(Command::LeaveGroup(group))
+			}
 			_ => Err(NodeSerializeError::InvalidCommandCode),
 		}
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self {
 			Command::Info => 0,
 			Command::Limbs => 1,
 			Command::SetLimb(_) => 2,
 			Command::ToggleLimb(_) => 3,
 			Command::SetLimbType(_) => 4,
+			Command::SyncTime(_) => 5,
+			Command::FetchHistory { .. } => 6,
+			Command::ChangeChannel { .. } => 7,
+			Command::JoinGroup(_) => 8,
+			Command::LeaveGroup(_) => 9,
 			// Add other variants and codes here, up to 16
 		}
 	}
@@ -420,7 +898,14 @@ pub enum Response {
 	Ok,
 	Info(NodeInfo),
 	Limbs(Limbs),
-	Heartbeat(u32),
+	/// Node's clock as a [`Timestamp`], 0 if it was never synced
+	Heartbeat(Timestamp),
+	/// Limb states sampled at a [`Timestamp`]
+	///
+	/// Lets nodes that buffer readings (sleeping, out of range) keep the real sample time.
+	LimbsAt(Timestamp, Limbs),
+	/// A page of buffered readings, answer to [`Command::FetchHistory`]
+	History(SensorHistory),
 	ErrLimbNotFound = 200,
 	ErrLimbTypeDoesntMatch,
 }
@@ -437,27 +922,18 @@ impl Response {
 				node_info.serialize_to_bits(writer)?;
 				Ok(())
 			}
-			Response::Limbs(limbs) => {
-				// let limbs_n = limbs.len();
-				// writer.write_bits(limbs_n as u32, 4)?;
-				// Serialize limbs array
-				for limb_option in limbs.iter() {
-					// limb.serialize_to_bits(writer)?;
-					// Write presence bit
-					if let Some(limb) = limb_option {
-						writer.write_bits(1, 1)?;
-						limb.serialize_to_bits(writer)?;
-					} else {
-						writer.write_bits(0, 1)?;
-					}
-				}
-				Ok(())
-			}
+			Response::Limbs(limbs) => serialize_limbs(limbs, writer),
 			Response::Heartbeat(timestamp) => {
 				// Write timestamp (32 bits)
 				writer.write_bits(*timestamp , 32)?;
 				Ok(())
 			}
+			Response::LimbsAt(timestamp, limbs) => {
+				// Write timestamp (32 bits)
+				writer.write_bits(*timestamp, 32)?;
+				serialize_limbs(limbs, writer)
+			}
+			Response::History(history) => history.serialize_to_bits(writer),
 			Response::ErrLimbNotFound => Ok(()),
 			Response::ErrLimbTypeDoesntMatch => Ok(()),
 		}
@@ -476,44 +952,68 @@ impl Response {
 			}
 			2 => {
 				// Limbs
-				let mut limbs: Limbs = Default::default();
-				// let limbs_n = reader.read_bits(4)? as u8;
-				// for _ in 0..limbs_n {
-				//     limbs.push(Limb::deserialize_from_bits(reader)?).ok();
-				// }
-				for limb_option in limbs.iter_mut() {
-					// Read presence bit
-					let has_limb = reader.read_bits(1)? == 1;
-					if has_limb {
-						let limb = Limb::deserialize_from_bits(reader)?;
-						*limb_option = Some(limb);
-					}
-				}
-				Ok(Response::Limbs(limbs))
+				Ok(Response::Limbs(deserialize_limbs(reader)?))
 			}
 			3 => {
 				// Heartbeat
 				let timestamp = reader.read_bits(32)?;
 				Ok(Response::Heartbeat(timestamp))
 			}
+			4 => {
+				// LimbsAt
+				let timestamp = reader.read_bits(32)?;
+				Ok(Response::LimbsAt(timestamp, deserialize_limbs(reader)?))
+			}
+			5 => {
+				// History
+				Ok(Response::History(SensorHistory::deserialize_from_bits(reader)?))
+			}
 			200 => Ok(Response::ErrLimbNotFound),
 			201 => Ok(Response::ErrLimbTypeDoesntMatch),
 			_ => Err(NodeSerializeError::InvalidResponseCode),
 		}
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self {
 			Response::Ok => 0,
 			Response::Info(_) => 1,
 			Response::Limbs(_) => 2,
 			Response::Heartbeat(_) => 3,
+			Response::LimbsAt(_, _) => 4,
+			Response::History(_) => 5,
 			Response::ErrLimbNotFound => 200,
 			Response::ErrLimbTypeDoesntMatch => 201,
 		}
 	}
 }
 
+fn serialize_limbs(limbs: &Limbs, writer: &mut BitWriter) -> NodeBitsResult<()> {
+	for limb_option in limbs.iter() {
+		// Write presence bit
+		if let Some(limb) = limb_option {
+			writer.write_bits(1, 1)?;
+			limb.serialize_to_bits(writer)?;
+		} else {
+			writer.write_bits(0, 1)?;
+		}
+	}
+	Ok(())
+}
+
+fn deserialize_limbs(reader: &mut BitReader) -> NodeBitsResult<Limbs> {
+	let mut limbs: Limbs = Default::default();
+	for limb_option in limbs.iter_mut() {
+		// Read presence bit
+		let has_limb = reader.read_bits(1)? == 1;
+		if has_limb {
+			let limb = Limb::deserialize_from_bits(reader)?;
+			*limb_option = Some(limb);
+		}
+	}
+	Ok(limbs)
+}
+
 /// Max 2 Variants
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "std", derive(PartialEq, Eq))]
@@ -604,6 +1104,16 @@ pub enum Message {
 	///
 	/// (node_id, message)
 	DebugMessage(NodeId, [u8; 20]),
+
+	/// An error the node ran into, see [`FaultLog`](crate::fault::FaultLog)
+	///
+	/// (node_id, fault)
+	FaultReport(NodeId, Fault),
+
+	/// Part of a log with an interned format string, see [`log`](crate::log)
+	///
+	/// (node_id, chunk)
+	Log(NodeId, LogChunk),
 	// Add other variants here, up to 16
 }
 
@@ -650,6 +1160,16 @@ impl Message {
 					writer.write_bits(*byte as u32, 8)?;
 				}
 			}
+			Message::FaultReport(node_id, fault) => {
+				// Serialize node_id (32 bits)
+				writer.write_bits(*node_id, 32)?;
+				fault.serialize_to_bits(&mut writer)?;
+			}
+			Message::Log(node_id, chunk) => {
+				// Serialize node_id (32 bits)
+				writer.write_bits(*node_id, 32)?;
+				chunk.serialize_to_bits(&mut writer)?;
+			}
 		}
 
 		Ok(writer.finalize())
@@ -700,6 +1220,18 @@ impl Message {
 				}
 				Message::DebugMessage(node_id, msg)
 			}
+			5 => {
+				// Message::FaultReport
+				let node_id = reader.read_bits(32)?;
+				let fault = Fault::deserialize_from_bits(&mut reader)?;
+				Message::FaultReport(node_id, fault)
+			}
+			6 => {
+				// Message::Log
+				let node_id = reader.read_bits(32)?;
+				let chunk = LogChunk::deserialize_from_bits(&mut reader)?;
+				Message::Log(node_id, chunk)
+			}
 			_ => {
 				return Err(NodeSerializeError::InvalidMessageCode);
 			}
@@ -708,13 +1240,15 @@ impl Message {
 		Ok((message, reader.finalize()))
 	}
 
-	fn code(&self) -> u8 {
+	pub(crate) fn code(&self) -> u8 {
 		match self {
 			Message::Message(_) => 0,
 			Message::RelayMessage(_, _) => 1,
 			Message::SearchingNetwork(_) => 2,
 			Message::Network(_, _) => 3,
 			Message::DebugMessage(_, _) => 4,
+			Message::FaultReport(_, _) => 5,
+			Message::Log(_, _) => 6,
 			// Add other variants and codes here, up to 16
 		}
 	}
@@ -722,19 +1256,16 @@ impl Message {
 
 impl core::ops::Add for Sensor {
 	type Output = Sensor;
+	/// Averages each field, both have to be the same sensor
 	fn add(self, rhs: Self) -> Self::Output {
-		match (self, rhs) {
-			(Self::Battery(level), Self::Battery(level_in)) => {
-				Self::Battery((level + level_in) / 2)
-			}
-			(Self::TempHum((temp, hum)), Self::TempHum((temp_in, hum_in))) => {
-				Self::TempHum(((temp + temp_in) / 2, (hum + hum_in) / 2))
-			}
-			(Self::Current(i), Self::Current(i_in)) => {
-				Self::Current(((i as u32 + i_in as u32) / 2) as u16)
-			}
-			_ => panic!("Can't add two different sensors"),
+		if !self.is_same_sensor(&rhs) {
+			panic!("Can't add two different sensors");
 		}
+		let (fields, fields_in) = (self.fields(), rhs.fields());
+		self.with_fields([
+			(fields[0] + fields_in[0]) / 2,
+			(fields[1] + fields_in[1]) / 2,
+		])
 	}
 }
 
@@ -834,4 +1365,138 @@ fn serialize_limbs_bits() {
 		id: 20,
 		command: Command::Limbs,
 	}));
+	check(Message::Message(MessageData::Command {
+		id: 21,
+		command: Command::SyncTime(TimeSync {
+			seconds: 1_700_000_000,
+			millis: 999,
+			latency: 12,
+		}),
+	}));
+	check(Message::Message(MessageData::Command {
+		id: 25,
+		command: Command::FetchHistory {
+			limb: 2,
+			since: 1_700_000_000,
+		},
+	}));
+	check(Message::Message(MessageData::Command {
+		id: 22,
+		command: Command::ChangeChannel {
+			channel: 110,
+			at: 1_700_000_060,
+		},
+	}));
+	check(Message::Message(MessageData::Command {
+		id: 23,
+		command: Command::JoinGroup(3),
+	}));
+	check(Message::Message(MessageData::Command {
+		id: 24,
+		command: Command::LeaveGroup(255),
+	}));
+	check(Message::FaultReport(
+		0xDEAD_BEEF,
+		Fault {
+			code: 200,
+			count: 3,
+			uptime: 86_400,
+			reset: ResetReason::Watchdog,
+		},
+	));
+	let mut data = [0u8; LOG_CHUNK_MAX];
+	data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+	check(Message::Log(
+		0xDEAD_BEEF,
+		LogChunk {
+			log: 15,
+			part: 3,
+			last: true,
+			len: LOG_CHUNK_MAX as u8,
+			data,
+		},
+	));
+	let temp_hum = |id| {
+		Some(Limb(
+			id,
+			LimbType::Sensor {
+				report_interval: 300,
+				data: Some(Sensor::TempHum((-1000, 50))),
+			},
+		))
+	};
+	check(Message::Message(MessageData::Response {
+		id: None,
+		response: Response::LimbsAt(1_700_000_000, [temp_hum(0), None, temp_hum(2)]),
+	}));
+}
+
+#[test]
+fn serialize_history_bits() {
+	fn check(history: SensorHistory) -> usize {
+		let message = Message::Message(MessageData::Response {
+			id: Some(3),
+			response: Response::History(history),
+		});
+		let mut data = [0u8; 28];
+		let data_l = message.serialize_to_bytes(&mut data).unwrap();
+		let message_out = Message::deserialize_from_bytes(&data).unwrap().0;
+		assert_eq!(message, message_out);
+		data_l
+	}
+	let t = 1_700_000_000;
+	// Full page of TempHum 5 min apart, small deltas
+	let data_l = check(SensorHistory {
+		limb: 2,
+		samples: [
+			Some((t, Sensor::TempHum((2150, 50)))),
+			Some((t + 300, Sensor::TempHum((2161, 51)))),
+			Some((t + 600, Sensor::TempHum((2140, 49)))),
+			Some((t + 900, Sensor::TempHum((2100, 45)))),
+			Some((t + 1200, Sensor::TempHum((2050, 40)))),
+			Some((t + 1500, Sensor::TempHum((2000, 38)))),
+		],
+	});
+	assert!(data_l <= 28);
+	// Big jumps in time and value, and a sensor change fall back to full samples
+	check(SensorHistory {
+		limb: 0,
+		samples: [
+			Some((t, Sensor::TempHum((-500, 90)))),
+			Some((t + 86_400, Sensor::TempHum((3000, 10)))),
+			Some((t + 86_401, Sensor::Battery(80))),
+			None,
+			None,
+			None,
+		],
+	});
+	check(SensorHistory {
+		limb: 1,
+		samples: Default::default(),
+	});
+
+	// 7 samples is more than a page holds
+	let mut data = [0u8; 4];
+	let mut writer = BitWriter::new(&mut data);
+	writer.write_bits(1, 4).unwrap();
+	writer.write_bits(7, 3).unwrap();
+	assert!(matches!(
+		SensorHistory::deserialize_from_bits(&mut BitReader::new(&data)),
+		Err(NodeSerializeError::InvalidHistoryCount)
+	));
+}
+
+#[test]
+fn sensors_average() {
+	assert_eq!(Sensor::Battery(200) + Sensor::Battery(101), Sensor::Battery(150));
+	assert_eq!(
+		Sensor::TempHum((-2000, 40)) + Sensor::TempHum((2101, 61)),
+		Sensor::TempHum((50, 50))
+	);
+	assert_eq!(
+		Sensor::Current(60_000) + Sensor::Current(60_002),
+		Sensor::Current(60_001)
+	);
+	assert!(Sensor::Battery(1).is_same_sensor(&Sensor::Battery(2)));
+	assert!(!Sensor::Battery(1).is_same_sensor(&Sensor::Current(1)));
 }
@@ -3,50 +3,46 @@
  * 
  */
 
-use crate::node::{Message, NodeSerializeError};
+use crate::node::{Message, NodeAddress, NodeId, NodeSerializeError};
 use crate::radio::*;
+#[cfg(feature = "nrf24")]
 use embedded_hal::digital::OutputPin;
+#[cfg(feature = "nrf24")]
 use embedded_hal::spi::SpiDevice;
 use embedded_hal::{delay::DelayNs, digital::InputPin};
-use errors::Discriminant;
-use nrf24::NRF24L01;
-
-use super::addr_to_nrf24_hq_pipe;
+use errors::{Discriminant, FromDiscriminant};
 
 // No debug for this, to prevent accidentally unwrapping it.
 // #[derive(Debug)]
-#[repr(u8)]
+/// The radio's error goes last, its range depends on the radio and would move the others
+///
+/// Codes are the ones firmware has been reporting, new variants go in the gaps.
+#[derive(Discriminant, FromDiscriminant)]
 pub enum Error<E> {
-	RadioError(E) = 0,
-	SerializationError(NodeSerializeError),
+	#[discriminant(at = 2)]
 	SendingTimedOut,
+	#[discriminant(at = 10)]
+	SerializationError(NodeSerializeError),
+	#[discriminant(at = 30)]
+	RadioError(E),
 }
-const ERROR_MAX: u8 = 10;
 
 impl<E> From<E> for Error<E> {
 	fn from(value: E) -> Self {
 		Self::RadioError(value)
 	}
 }
-impl<E: Discriminant> Discriminant for Error<E> {
-	fn discriminant(&self) -> u8 {
-		// SAFETY: Because `Self` is marked `repr(u8)`, its layout is a `repr(C)` `union`
-		// between `repr(C)` structs, each of which has the `u8` discriminant as its first
-		// field, so we can read the discriminant without offsetting the pointer.
+impl<E: core::fmt::Display> core::fmt::Display for Error<E> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
-			Self::SerializationError(err) => ERROR_MAX + err.discriminant(),
-			Self::RadioError(err) => {
-				ERROR_MAX + NodeSerializeError::discriminant_max() + err.discriminant()
-			}
-			_ => unsafe { *<*const _>::from(self).cast::<u8>() },
+			Self::RadioError(err) => write!(f, "radio error: {err}"),
+			Self::SerializationError(err) => write!(f, "serialization error: {err}"),
+			Self::SendingTimedOut => write!(f, "sending timed out"),
 		}
 	}
-	fn discriminant_max() -> u8 {
-		ERROR_MAX + E::discriminant_max() + NodeSerializeError::discriminant_max()
-	}
 }
 
-type SendResult<E> = Result<bool, Error<E>>;
+pub type SendResult<E> = Result<bool, Error<E>>;
 // pub fn send_looking_for_network<E, R: Radio<E>, D: DelayNs>(
 // 	radio: &mut R,
 // 	node_id: NodeId,
@@ -57,24 +53,17 @@ type SendResult<E> = Result<bool, Error<E>>;
 // 	send_message(radio, Message::SearchingNetwork(node_id), node_addr, delay)
 // }
 
-/// For NRF24 we need to enable first pipe and disable it afterwards
-/// 
-/// This is a very specific method just for nodes with nrf24.
-/// We enable first pipe cause its needed for an ACK from HQ.
-/// But disable it afterwards because we don't want to receive 
-/// messages from other nodes that are headed to HQ.
-pub fn send_message_nrf24<SPI : SpiDevice,CE:OutputPin, D: DelayNs>(
-	radio: &mut NRF24L01<SPI, CE>,
+/// Send a message from a node with an nrf24
+///
+/// See [`SamnNrf24::send_message`], nodes wrap their `NRF24L01` in a [`SamnNrf24`] to use it.
+#[cfg(feature = "nrf24")]
+pub fn send_message_nrf24<SPI: SpiDevice, CE: OutputPin, D: DelayNs>(
+	radio: &mut SamnNrf24<SPI, CE>,
 	message: Message,
 	node_addr: u16,
 	delay: &mut D,
 ) -> SendResult<nrf24::Error<SPI::Error, CE::Error>> {
-	// Enable first pipe
-	radio.set_rx_enabled_pipes(&[true,true,false,false,false,false])?;
-	let result = send_message_(radio, message, node_addr, delay)?;
-	// Disable first pipe
-	radio.set_rx_enabled_pipes(&[false,true,false,false,false,false])?;
-	Ok(result)
+	radio.send_message(message, node_addr, delay)
 }
 
 
@@ -95,12 +84,7 @@ pub fn send_message_<E, R: Radio<E>, D: DelayNs>(
 
 	send_payload(
 		radio,
-		&Payload::new_with_addr_from_array(
-			data,
-			data_l,
-			node_addr,
-			addr_to_nrf24_hq_pipe(node_addr),
-		),
+		&Payload::new_with_addr_from_array(data, data_l, node_addr, radio.hq_pipe(node_addr)),
 		delay,
 	)
 }
@@ -112,7 +96,13 @@ pub fn send_payload<E, R: Radio<E>, D: DelayNs>(
 	delay: &mut D,
 ) -> SendResult<E> {
 	radio.transmit_start(payload, delay)?;
+	wait_for_transmission(radio, delay)
+}
 
+fn wait_for_transmission<E, R: Radio<E>, D: DelayNs>(
+	radio: &mut R,
+	delay: &mut D,
+) -> SendResult<E> {
 	// 64 ms max wait
 	for _ in 0..u8::MAX {
 		match radio.transmit_poll() {
@@ -127,6 +117,40 @@ pub fn send_payload<E, R: Radio<E>, D: DelayNs>(
 	Err(Error::SendingTimedOut)
 }
 
+/// Sends `message` to a group or to everyone, without waiting for acks
+///
+/// Nobody acks group messages, so it goes out `1 + repeats` times, `gap_ms` apart,
+/// to make up for the ones that get lost. Receivers have to cope with getting it more than once.
+/// `address` is the [`BROADCAST_ADDRESS`](crate::node::BROADCAST_ADDRESS) or a
+/// [`group_address`](crate::node::group_address), it goes out on [`BROADCAST_PIPE`].
+pub fn send_broadcast<E, R: Radio<E>, D: DelayNs>(
+	radio: &mut R,
+	message: Message,
+	address: NodeAddress,
+	repeats: u8,
+	gap_ms: u32,
+	delay: &mut D,
+) -> Result<(), Error<E>> {
+	let mut data = [0u8; 32];
+	let data_l = message
+		.serialize_to_bytes(&mut data)
+		.map_err(Error::SerializationError)?;
+	let payload = Payload::new_with_addr_from_array(data, data_l, address, BROADCAST_PIPE);
+
+	for i in 0..=repeats {
+		if i > 0 {
+			delay.delay_ms(gap_ms);
+		}
+		radio.transmit_start_no_ack(&payload, delay)?;
+		// No ack is expected, only the radio failing matters
+		match wait_for_transmission(radio, delay) {
+			Ok(_) | Err(Error::SendingTimedOut) => {}
+			Err(err) => return Err(err),
+		}
+	}
+	Ok(())
+}
+
 pub fn check_for_messages_for_a_bit<E, R: Radio<E>, P: InputPin, D: DelayNs>(
 	radio: &mut R,
 	irq: &mut P,
@@ -162,3 +186,233 @@ pub fn check_for_payloads_for_a_bit<E, R: Radio<E>, P: InputPin, D: DelayNs>(
 	}
 	Ok(None)
 }
+
+/// Looks for HQ on up to `attempts` of `channels`, when joining or after losing HQ
+///
+/// Sends [`Message::SearchingNetwork`] on a channel and listens a bit for HQ's
+/// [`Message::Network`], giving back the channel and our address once HQ answers.
+/// Pass a [`ChannelSearch`](crate::channel::ChannelSearch) to try the last known channel
+/// first, it goes round forever so `attempts` is what ends the search.
+/// When HQ isn't found the radio is put back on the first channel.
+pub fn search_network<E, R: Radio<E>, P: InputPin, D: DelayNs>(
+	radio: &mut R,
+	irq: &mut P,
+	delay: &mut D,
+	node_id: NodeId,
+	node_addr: u16,
+	channels: impl IntoIterator<Item = u8>,
+	attempts: usize,
+) -> Result<Option<(u8, NodeAddress)>, Error<E>> {
+	let mut channels = channels.into_iter().take(attempts);
+	let Some(first) = channels.next() else {
+		return Ok(None);
+	};
+	for channel in core::iter::once(first).chain(channels) {
+		radio.set_channel(channel)?;
+		// No ack or timing out just means HQ isn't on this channel
+		match send_message_(radio, Message::SearchingNetwork(node_id), node_addr, delay) {
+			Ok(_) | Err(Error::SendingTimedOut) => {}
+			Err(err) => return Err(err),
+		}
+		if let Some(Message::Network(id, address)) =
+			check_for_messages_for_a_bit(radio, irq, delay)?
+		{
+			if id == node_id {
+				return Ok(Some((channel, address)));
+			}
+		}
+	}
+	radio.set_channel(first)?;
+	Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	#[cfg(feature = "std")]
+	use crate::radio::mock::{MockRadio, NoDelay};
+
+	#[derive(Debug, PartialEq)]
+	enum MockError {
+		Spi,
+		Busy,
+	}
+	impl Discriminant for MockError {
+		fn discriminant(&self) -> u8 {
+			match self {
+				MockError::Spi => 0,
+				MockError::Busy => 1,
+			}
+		}
+		fn discriminant_max() -> u8 {
+			2
+		}
+		fn describe(discriminant: u8, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+			errors::describe_from::<Self>(discriminant, f)
+		}
+	}
+	impl FromDiscriminant for MockError {
+		fn from_discriminant(discriminant: u8) -> Option<Self> {
+			match discriminant {
+				0 => Some(MockError::Spi),
+				1 => Some(MockError::Busy),
+				_ => None,
+			}
+		}
+	}
+	impl core::fmt::Display for MockError {
+		fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+			write!(f, "{self:?}")
+		}
+	}
+
+	#[test]
+	fn discriminants_dont_overlap() {
+		let bit_errors = [bity::Error::BufferOverflow, bity::Error::BufferUnderflow];
+		let node_errors = bit_errors
+			.iter()
+			.cloned()
+			.map(NodeSerializeError::BitError)
+			.chain([
+				NodeSerializeError::InvalidBoardCode,
+				NodeSerializeError::InvalidSensorCode,
+				NodeSerializeError::InvalidActuatorCode,
+				NodeSerializeError::InvalidCommandCode,
+				NodeSerializeError::InvalidResponseCode,
+				NodeSerializeError::InvalidMessageCode,
+				NodeSerializeError::InvalidMessageVersion,
+				NodeSerializeError::InvalidLogChunkLength,
+				NodeSerializeError::InvalidHistoryCount,
+			]);
+		let errors: Vec<Error<MockError>> = [
+			Error::RadioError(MockError::Spi),
+			Error::RadioError(MockError::Busy),
+			Error::SendingTimedOut,
+		]
+		.into_iter()
+		.chain(node_errors.map(Error::SerializationError))
+		.collect();
+
+		let mut seen = Vec::new();
+		for err in &errors {
+			let discriminant = err.discriminant();
+			assert!(discriminant < Error::<MockError>::discriminant_max(), "{err}");
+			assert!(!seen.contains(&discriminant), "{err} overlaps");
+			seen.push(discriminant);
+
+			// Back to the same error, described the same way
+			let back = Error::<MockError>::from_discriminant(discriminant).unwrap();
+			assert_eq!(back.discriminant(), discriminant);
+			let described = errors::describe::<Error<MockError>>(discriminant).to_string();
+			assert_eq!(described, err.to_string());
+		}
+		// Nothing else decodes
+		assert_eq!(
+			(0..=u8::MAX)
+				.filter(|d| Error::<MockError>::from_discriminant(*d).is_some())
+				.count(),
+			errors.len()
+		);
+
+		assert_eq!(
+			errors::describe::<Error<MockError>>(
+				Error::RadioError(MockError::Busy).discriminant()
+			).to_string(),
+			"radio error: Busy"
+		);
+		assert_eq!(
+			errors::describe::<Error<MockError>>(
+				Error::<MockError>::SerializationError(NodeSerializeError::InvalidSensorCode)
+					.discriminant()
+			)
+			.to_string(),
+			"serialization error: invalid sensor code"
+		);
+		assert_eq!(
+			errors::describe::<Error<MockError>>(10).to_string(),
+			"serialization error: bit error: buffer overflow"
+		);
+		// Same codes as before the derive, new variants didn't move them
+		assert_eq!(Error::<MockError>::SendingTimedOut.discriminant(), 2);
+		assert_eq!(
+			Error::<MockError>::SerializationError(NodeSerializeError::InvalidBoardCode)
+				.discriminant(),
+			13
+		);
+		assert_eq!(Error::RadioError(MockError::Busy).discriminant(), 31);
+		assert_eq!(Error::<MockError>::discriminant_max(), 32);
+		assert_eq!(NodeSerializeError::DISCRIMINANT_COUNT, Some(20));
+		assert_eq!(errors::describe::<Error<MockError>>(1).to_string(), "unknown error 1");
+		assert_eq!(
+			errors::describe::<Error<MockError>>(200).to_string(),
+			"unknown error 200"
+		);
+	}
+	#[cfg(feature = "std")]
+	#[test]
+	fn messages_go_to_the_radios_hq_pipe() {
+		let mut radio = MockRadio::default();
+		let message = Message::SearchingNetwork(5);
+		let sent = send_message_(&mut radio, message, 0x0204, &mut NoDelay);
+		assert!(matches!(sent, Ok(true)));
+		// Not one of the nrf24 HQ's other pipes, HQ's cc1101 only hears this one
+		assert_eq!(radio.tx[0].pipe(), DEFAULT_PIPE);
+		assert_eq!(radio.tx[0].address(), Some(0x0204));
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn broadcasts_dont_ask_for_acks() {
+		// Nobody acks
+		let mut radio = MockRadio {
+			acked: false,
+			..Default::default()
+		};
+		let message = Message::SearchingNetwork(5);
+		let sent = send_broadcast(&mut radio, message, BROADCAST_ADDRESS, 2, 0, &mut NoDelay);
+		assert!(sent.is_ok());
+		assert_eq!(radio.tx.len(), 3);
+		assert_eq!(radio.no_ack, [true; 3]);
+		assert!(radio.tx.iter().all(|payload| payload.pipe() == BROADCAST_PIPE));
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn garbled_payloads_arent_messages() {
+		use crate::radio::{NoIrq, DEFAULT_PIPE};
+
+		let mut radio = MockRadio::default();
+		// Says it has 60 bytes of data
+		radio.push_rx(&[DEFAULT_PIPE, 0x80 | 60, 4, 2]);
+		let received = check_for_messages_for_a_bit(&mut radio, &mut NoIrq, &mut NoDelay);
+		assert!(matches!(received, Ok(None)));
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn network_search_ends() {
+		use crate::channel::ChannelSearch;
+		use crate::radio::{addr_to_rx_pipe, NoIrq};
+
+		let mut radio = MockRadio::default();
+		let search = ChannelSearch::new(110, &[76, 90]);
+		let found = search_network(&mut radio, &mut NoIrq, &mut NoDelay, 5, 0, search, 4);
+		assert!(matches!(found, Ok(None)));
+		assert_eq!(radio.tx.len(), 4);
+		assert_eq!(radio.channel, 110);
+
+		// Another node's answer doesn't count
+		for node_id in [6, 5] {
+			let mut data = [0u8; 32];
+			let len = Message::Network(node_id, 0x0204)
+				.serialize_to_bytes(&mut data)
+				.unwrap();
+			let payload = Payload::new_with_addr(&data[..len], 0, addr_to_rx_pipe(0));
+			radio.push_rx(payload.payload());
+		}
+		let search = ChannelSearch::new(110, &[76, 90]);
+		let found = search_network(&mut radio, &mut NoIrq, &mut NoDelay, 5, 0, search, 4);
+		assert!(matches!(found, Ok(Some((76, 0x0204)))));
+		assert_eq!(radio.channel, 76);
+	}
+}
@@ -0,0 +1,577 @@
+//! Carries payloads over UDP or TCP, so nodes on WiFi/ethernet join the same network
+//!
+//! Both implement [`Radio`], so HQ handles IP nodes with the same code as radio nodes.
+//! What goes over the wire is the payload as it would go over the air ([`Payload::payload`]),
+//! TCP prefixes each one with its length.
+//!
+//! There's no hardware filtering, so like the cc1101:
+//! - [`Radio::set_rx_filter`] drops payloads sent to other pipes
+//! - `rx_addresses` in [`Radio::receive`] drops payloads for other addresses
+//!
+//! Peers are discovered from what they send. The address on a payload we receive is
+//! remembered along with where it came from, and payloads for that address go back there.
+//! Payloads for an address we haven't heard from go everywhere we can reach
+//! (the broadcast address for UDP, every connection for TCP).
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+
+use embedded_hal::{delay::DelayNs, digital::InputPin};
+
+use super::{address_accepted, Capabilities, Payload, Radio};
+use crate::node::NodeAddress;
+
+pub const DEFAULT_PORT: u16 = 9797;
+
+/// UDP doesn't ack, TCP does it for us
+const fn ip_capabilities(auto_ack: bool) -> Capabilities {
+	Capabilities {
+		tx_power_min: 0,
+		tx_power_max: 0,
+		data_rate_min: 0,
+		data_rate_max: u32::MAX,
+		channel_max: 0,
+		auto_ack,
+	}
+}
+
+fn accepts(rx_pipes: &[u8], payload: &Payload, rx_addresses: Option<&[u16]>) -> bool {
+	let pipe_ok = rx_pipes.is_empty() || rx_pipes.contains(&payload.pipe());
+	let address_ok = match (payload.address(), rx_addresses) {
+		(Some(address), Some(addresses)) => address_accepted(address, addresses),
+		_ => true,
+	};
+	pipe_ok && address_ok
+}
+
+fn would_block<T>(result: io::Result<T>) -> nb::Result<T, io::Error> {
+	result.map_err(|err| match err.kind() {
+		ErrorKind::WouldBlock => nb::Error::WouldBlock,
+		_ => nb::Error::Other(err),
+	})
+}
+
+pub struct UdpRadio {
+	socket: UdpSocket,
+	/// Where payloads for addresses we haven't heard from go
+	broadcast: SocketAddr,
+	peers: BTreeMap<NodeAddress, SocketAddr>,
+	rx_pipes: Vec<u8>,
+}
+
+impl UdpRadio {
+	/// `broadcast` is usually `255.255.255.255:DEFAULT_PORT` on HQ,
+	/// nodes that know where HQ is can use its address instead
+	pub fn bind(addr: impl ToSocketAddrs, broadcast: SocketAddr) -> io::Result<Self> {
+		Self::new(UdpSocket::bind(addr)?, broadcast)
+	}
+	pub fn new(socket: UdpSocket, broadcast: SocketAddr) -> io::Result<Self> {
+		socket.set_nonblocking(true)?;
+		socket.set_broadcast(true)?;
+		Ok(Self {
+			socket,
+			broadcast,
+			peers: BTreeMap::new(),
+			rx_pipes: vec![],
+		})
+	}
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.socket.local_addr()
+	}
+	/// Addresses we've heard from and where they were
+	pub fn peers(&self) -> &BTreeMap<NodeAddress, SocketAddr> {
+		&self.peers
+	}
+}
+
+impl Radio<io::Error> for UdpRadio {
+	fn init<D: DelayNs>(&mut self, _: &mut D) -> Result<(), io::Error> {
+		self.peers.clear();
+		Ok(())
+	}
+	/// Sends straight away, there's nothing to wait for with UDP
+	fn transmit_start<D: DelayNs>(
+		&mut self,
+		payload: &Payload,
+		_: &mut D,
+	) -> Result<(), io::Error> {
+		let to = payload
+			.address()
+			.and_then(|address| self.peers.get(&address))
+			.copied()
+			.unwrap_or(self.broadcast);
+		self.socket.send_to(payload.payload(), to)?;
+		Ok(())
+	}
+	fn transmit_poll(&mut self) -> nb::Result<bool, io::Error> {
+		Ok(true)
+	}
+	fn receive<P: InputPin>(
+		&mut self,
+		_: &mut P,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<Payload, io::Error> {
+		let mut buf = [0u8; 64];
+		loop {
+			let (len, from) = would_block(self.socket.recv_from(&mut buf))?;
+			// Anything that isn't a payload for us is dropped
+			let Some(payload) = Payload::from_bytes(&buf[..len]) else {
+				continue;
+			};
+			if !accepts(&self.rx_pipes, &payload, rx_addresses) {
+				continue;
+			}
+			if let Some(address) = payload.address() {
+				self.peers.insert(address, from);
+			}
+			return Ok(payload);
+		}
+	}
+	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), io::Error> {
+		self.rx_pipes = rx_pipes.to_vec();
+		Ok(())
+	}
+	/// There's only the one network
+	fn set_channel(&mut self, _: u8) -> Result<(), io::Error> {
+		Ok(())
+	}
+	/// Always quiet
+	fn energy_scan<D: DelayNs>(&mut self, _: u8, _: &mut D) -> Result<u8, io::Error> {
+		Ok(0)
+	}
+	/// Nothing to turn up or down
+	fn set_tx_power(&mut self, _: i8) -> Result<i8, io::Error> {
+		Ok(0)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, io::Error> {
+		Ok(bps)
+	}
+	fn capabilities(&self) -> Capabilities {
+		ip_capabilities(false)
+	}
+	fn to_rx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn to_tx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn to_idle(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn flush_rx(&mut self) -> Result<(), io::Error> {
+		let mut buf = [0u8; 64];
+		loop {
+			match self.socket.recv_from(&mut buf) {
+				Ok(_) => {}
+				Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+				Err(err) => return Err(err),
+			}
+		}
+	}
+	fn flush_tx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+
+	#[cfg(feature = "tokio")]
+	async fn to_rx_async(&mut self) -> Result<(), io::Error> {
+		self.to_rx()
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_idle_async(&mut self) -> Result<(), io::Error> {
+		self.to_idle()
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_tx_async(&mut self) -> Result<(), io::Error> {
+		self.to_tx()
+	}
+}
+
+/// A peer that stops reading is dropped once this much is waiting to be sent
+const TX_PENDING_MAX: usize = 1 << 20;
+
+struct Connection<S = TcpStream> {
+	stream: S,
+	/// Bytes read that don't make up a whole frame yet
+	rx: Vec<u8>,
+	/// Bytes the socket wasn't ready to take yet
+	tx: Vec<u8>,
+}
+
+impl<S: Read + Write> Connection<S> {
+	/// Queues `frame` and writes what the socket takes, `false` once the connection is gone
+	fn send(&mut self, frame: &[u8]) -> bool {
+		if self.tx.len() + frame.len() > TX_PENDING_MAX {
+			return false;
+		}
+		self.tx.extend_from_slice(frame);
+		self.write_pending()
+	}
+	/// Writes out what's queued, partial writes keep the rest for next time
+	fn write_pending(&mut self) -> bool {
+		while !self.tx.is_empty() {
+			match self.stream.write(&self.tx) {
+				Ok(0) => return false,
+				Ok(len) => {
+					self.tx.drain(..len);
+				}
+				Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+				Err(err) if err.kind() == ErrorKind::Interrupted => {}
+				Err(_) => return false,
+			}
+		}
+		true
+	}
+	/// Reads what's available, `false` once the connection is gone
+	fn fill(&mut self) -> bool {
+		let mut buf = [0u8; 256];
+		loop {
+			match self.stream.read(&mut buf) {
+				Ok(0) => return false,
+				Ok(len) => self.rx.extend_from_slice(&buf[..len]),
+				Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+				Err(_) => return false,
+			}
+		}
+	}
+	/// Next frame's bytes, `Err` if the stream is garbage
+	fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ()> {
+		let Some(len) = self.rx.first().map(|len| *len as usize) else {
+			return Ok(None);
+		};
+		if !(2..=32).contains(&len) {
+			return Err(());
+		}
+		if self.rx.len() <= len {
+			return Ok(None);
+		}
+		let frame = self.rx[1..=len].to_vec();
+		self.rx.drain(..=len);
+		Ok(Some(frame))
+	}
+}
+
+/// TCP for nodes that would rather have a connection, HQ listens and nodes connect
+pub struct TcpRadio {
+	listener: Option<TcpListener>,
+	connections: BTreeMap<u32, Connection>,
+	next_connection: u32,
+	peers: BTreeMap<NodeAddress, u32>,
+	rx_pipes: Vec<u8>,
+	/// Whether the last transmit reached anyone
+	sent: bool,
+}
+
+impl TcpRadio {
+	fn new(listener: Option<TcpListener>) -> Self {
+		Self {
+			listener,
+			connections: BTreeMap::new(),
+			next_connection: 0,
+			peers: BTreeMap::new(),
+			rx_pipes: vec![],
+			sent: false,
+		}
+	}
+	/// HQ side, nodes connecting to `listener` are accepted while receiving
+	pub fn listen(listener: TcpListener) -> io::Result<Self> {
+		listener.set_nonblocking(true)?;
+		Ok(Self::new(Some(listener)))
+	}
+	/// Node side, one connection to HQ
+	pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+		let mut radio = Self::new(None);
+		radio.add_connection(TcpStream::connect(addr)?)?;
+		Ok(radio)
+	}
+	pub fn local_addr(&self) -> Option<io::Result<SocketAddr>> {
+		self.listener.as_ref().map(TcpListener::local_addr)
+	}
+	pub fn connections(&self) -> usize {
+		self.connections.len()
+	}
+
+	fn add_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+		stream.set_nonblocking(true)?;
+		stream.set_nodelay(true)?;
+		self.connections.insert(
+			self.next_connection,
+			Connection {
+				stream,
+				rx: vec![],
+				tx: vec![],
+			},
+		);
+		self.next_connection = self.next_connection.wrapping_add(1);
+		Ok(())
+	}
+	fn remove_connection(&mut self, id: u32) {
+		self.connections.remove(&id);
+		self.peers.retain(|_, connection| *connection != id);
+	}
+	fn accept(&mut self) -> io::Result<()> {
+		while let Some(listener) = &self.listener {
+			match listener.accept() {
+				Ok((stream, _)) => self.add_connection(stream)?,
+				Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Radio<io::Error> for TcpRadio {
+	fn init<D: DelayNs>(&mut self, _: &mut D) -> Result<(), io::Error> {
+		self.peers.clear();
+		Ok(())
+	}
+	/// Connections that fail to write are dropped,
+	/// [`transmit_poll`](Radio::transmit_poll) is `false` if no one got the payload
+	///
+	/// Whatever a socket can't take right away is written on later calls to
+	/// `transmit_start` and [`receive`](Radio::receive).
+	fn transmit_start<D: DelayNs>(
+		&mut self,
+		payload: &Payload,
+		_: &mut D,
+	) -> Result<(), io::Error> {
+		let mut frame = vec![payload.len_total() as u8];
+		frame.extend_from_slice(payload.payload());
+
+		let targets: Vec<u32> = match payload
+			.address()
+			.and_then(|address| self.peers.get(&address))
+		{
+			Some(id) => vec![*id],
+			None => self.connections.keys().copied().collect(),
+		};
+		self.sent = false;
+		for id in targets {
+			let Some(connection) = self.connections.get_mut(&id) else {
+				continue;
+			};
+			if connection.send(&frame) {
+				self.sent = true;
+			} else {
+				self.remove_connection(id);
+			}
+		}
+		Ok(())
+	}
+	fn transmit_poll(&mut self) -> nb::Result<bool, io::Error> {
+		Ok(self.sent)
+	}
+	fn receive<P: InputPin>(
+		&mut self,
+		_: &mut P,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<Payload, io::Error> {
+		self.accept()?;
+
+		let ids: Vec<u32> = self.connections.keys().copied().collect();
+		for id in ids {
+			let connection = self.connections.get_mut(&id).expect("id was just listed");
+			let open = connection.write_pending() && connection.fill();
+			loop {
+				let frame = match connection.next_frame() {
+					Ok(Some(frame)) => frame,
+					Ok(None) => break,
+					// Out of sync, there's no finding the next frame in a stream
+					Err(()) => {
+						self.remove_connection(id);
+						break;
+					}
+				};
+				let Some(payload) = Payload::from_bytes(&frame) else {
+					continue;
+				};
+				if !accepts(&self.rx_pipes, &payload, rx_addresses) {
+					continue;
+				}
+				if let Some(address) = payload.address() {
+					self.peers.insert(address, id);
+				}
+				return Ok(payload);
+			}
+			// Frames already read are handed out before dropping a closed connection
+			if !open {
+				self.remove_connection(id);
+			}
+		}
+		Err(nb::Error::WouldBlock)
+	}
+	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), io::Error> {
+		self.rx_pipes = rx_pipes.to_vec();
+		Ok(())
+	}
+	/// There's only the one network
+	fn set_channel(&mut self, _: u8) -> Result<(), io::Error> {
+		Ok(())
+	}
+	/// Always quiet
+	fn energy_scan<D: DelayNs>(&mut self, _: u8, _: &mut D) -> Result<u8, io::Error> {
+		Ok(0)
+	}
+	/// Nothing to turn up or down
+	fn set_tx_power(&mut self, _: i8) -> Result<i8, io::Error> {
+		Ok(0)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, io::Error> {
+		Ok(bps)
+	}
+	fn capabilities(&self) -> Capabilities {
+		ip_capabilities(true)
+	}
+	fn to_rx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn to_tx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn to_idle(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	fn flush_rx(&mut self) -> Result<(), io::Error> {
+		for connection in self.connections.values_mut() {
+			connection.fill();
+			connection.rx.clear();
+		}
+		Ok(())
+	}
+	fn flush_tx(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+
+	#[cfg(feature = "tokio")]
+	async fn to_rx_async(&mut self) -> Result<(), io::Error> {
+		self.to_rx()
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_idle_async(&mut self) -> Result<(), io::Error> {
+		self.to_idle()
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_tx_async(&mut self) -> Result<(), io::Error> {
+		self.to_tx()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::{Duration, Instant};
+
+	use super::*;
+	use crate::radio::mock::NoDelay;
+	use crate::radio::{addr_to_rx_pipe, NoIrq, DEFAULT_PIPE};
+
+	fn receive_for_a_bit<R: Radio<io::Error>>(
+		radio: &mut R,
+		rx_addresses: Option<&[u16]>,
+	) -> Option<Payload> {
+		let start = Instant::now();
+		while start.elapsed() < Duration::from_secs(2) {
+			match radio.receive(&mut NoIrq, rx_addresses) {
+				Ok(payload) => return Some(payload),
+				Err(nb::Error::WouldBlock) => std::thread::sleep(Duration::from_millis(5)),
+				Err(nb::Error::Other(err)) => panic!("{err}"),
+			}
+		}
+		None
+	}
+
+	/// Node says hi to HQ, HQ learns where it is and answers
+	fn hq_and_node<R: Radio<io::Error>>(hq: &mut R, node: &mut R) {
+		let node_addr = 0x0102;
+		hq.set_rx_filter(&[DEFAULT_PIPE]).unwrap();
+		node.set_rx_filter(&[addr_to_rx_pipe(node_addr)]).unwrap();
+
+		let hello = Payload::new_with_addr(&[1, 2, 3], node_addr, DEFAULT_PIPE);
+		node.transmit_start(&hello, &mut NoDelay).unwrap();
+		assert!(matches!(node.transmit_poll(), Ok(true)));
+		let received = receive_for_a_bit(hq, None).expect("hq got nothing");
+		assert_eq!(received.data(), [1, 2, 3]);
+		assert_eq!(received.address(), Some(node_addr));
+
+		// Wrong pipe gets dropped, the right one gets through
+		let other = Payload::new_with_addr(&[9], node_addr, DEFAULT_PIPE);
+		hq.transmit_start(&other, &mut NoDelay).unwrap();
+		let answer = Payload::new_with_addr(&[4, 5], node_addr, addr_to_rx_pipe(node_addr));
+		hq.transmit_start(&answer, &mut NoDelay).unwrap();
+		let received = receive_for_a_bit(node, Some(&[node_addr])).expect("node got nothing");
+		assert_eq!(received.data(), [4, 5]);
+	}
+
+	#[test]
+	fn udp_on_localhost() {
+		let mut hq = UdpRadio::bind("127.0.0.1:0", "127.0.0.1:9".parse().unwrap()).unwrap();
+		let hq_addr = hq.local_addr().unwrap();
+		let mut node = UdpRadio::bind("127.0.0.1:0", hq_addr).unwrap();
+		hq_and_node(&mut hq, &mut node);
+		assert_eq!(hq.peers().get(&0x0102), Some(&node.local_addr().unwrap()));
+	}
+
+	#[test]
+	fn tcp_on_localhost() {
+		let mut hq = TcpRadio::listen(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+		let hq_addr = hq.local_addr().unwrap().unwrap();
+		let mut node = TcpRadio::connect(hq_addr).unwrap();
+		hq_and_node(&mut hq, &mut node);
+		assert_eq!(hq.connections(), 1);
+
+		drop(node);
+		assert!(receive_for_a_bit(&mut hq, None).is_none());
+		assert_eq!(hq.connections(), 0);
+	}
+
+	/// Takes a few bytes per write, like a socket with a full buffer
+	struct Trickle {
+		written: Vec<u8>,
+		space: usize,
+	}
+	impl Write for Trickle {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if self.space == 0 {
+				return Err(ErrorKind::WouldBlock.into());
+			}
+			let len = buf.len().min(self.space);
+			self.written.extend_from_slice(&buf[..len]);
+			self.space -= len;
+			Ok(len)
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+	impl Read for Trickle {
+		fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+			Err(ErrorKind::WouldBlock.into())
+		}
+	}
+
+	#[test]
+	fn partial_writes_are_kept() {
+		let mut connection = Connection {
+			stream: Trickle {
+				written: vec![],
+				space: 5,
+			},
+			rx: vec![],
+			tx: vec![],
+		};
+		assert!(connection.send(&[3, 1, 2, 3]));
+		assert!(connection.send(&[2, 4, 5]));
+		assert_eq!(connection.stream.written, [3, 1, 2, 3, 2]);
+		assert_eq!(connection.tx, [4, 5]);
+
+		connection.stream.space = 10;
+		assert!(connection.write_pending());
+		assert_eq!(connection.stream.written, [3, 1, 2, 3, 2, 4, 5]);
+		assert!(connection.tx.is_empty());
+
+		// Never reads, so it's dropped
+		connection.stream.space = 0;
+		let frame = [32u8; 33];
+		while connection.send(&frame) {}
+		assert!(connection.tx.len() + frame.len() > TX_PENDING_MAX);
+	}
+}
@@ -0,0 +1,188 @@
+//! A [`Radio`] for tests, it keeps what's sent and hands out what it's given
+use std::collections::VecDeque;
+
+use embedded_hal::{delay::DelayNs, digital::InputPin};
+use errors::Discriminant;
+
+use super::{address_accepted, Capabilities, Payload, Radio};
+
+/// Doesn't wait at all
+pub struct NoDelay;
+impl DelayNs for NoDelay {
+	fn delay_ns(&mut self, _: u32) {}
+}
+
+/// What [`MockRadio`] fails with
+#[derive(Debug, PartialEq, Eq)]
+pub struct MockError;
+impl Discriminant for MockError {
+	fn discriminant(&self) -> u8 {
+		0
+	}
+	fn discriminant_max() -> u8 {
+		1
+	}
+}
+
+/// `rx` is handed out front first and every transmit lands in `tx`
+///
+/// Only fails where it's told to.
+pub struct MockRadio {
+	pub rx: VecDeque<Payload>,
+	pub tx: Vec<Payload>,
+	/// Whether each of `tx` went out with [`Radio::transmit_start_no_ack`]
+	pub no_ack: Vec<bool>,
+	/// What [`Radio::transmit_poll`] answers
+	pub acked: bool,
+	/// Handed out by [`Radio::rssi`] front first, the last one sticks
+	pub rssi: VecDeque<i8>,
+	pub rx_filter: Vec<u8>,
+	pub channel: u8,
+	pub auto_ack: bool,
+	/// Transmits also go back into `rx`, like another radio answering with the same
+	pub loopback: bool,
+	/// What [`Radio::energy_scan`] finds on every channel
+	pub energy: u8,
+	/// [`Radio::flush_tx`] fails with [`MockError`]
+	pub fail_flush_tx: bool,
+}
+
+impl Default for MockRadio {
+	fn default() -> Self {
+		Self {
+			rx: VecDeque::new(),
+			tx: vec![],
+			no_ack: vec![],
+			acked: true,
+			rssi: VecDeque::new(),
+			rx_filter: vec![],
+			channel: 0,
+			auto_ack: true,
+			loopback: false,
+			energy: 0,
+			fail_flush_tx: false,
+		}
+	}
+}
+
+impl MockRadio {
+	/// `bytes` as they would come off the air, they don't have to make sense
+	pub fn push_rx(&mut self, bytes: &[u8]) {
+		let mut payload = Payload::default();
+		payload.0[..bytes.len()].copy_from_slice(bytes);
+		self.rx.push_back(payload);
+	}
+
+	fn transmit(&mut self, payload: &Payload, no_ack: bool) {
+		self.tx.push(Payload(payload.0));
+		self.no_ack.push(no_ack);
+		if self.loopback {
+			self.rx.push_back(Payload(payload.0));
+		}
+	}
+}
+
+impl Radio<MockError> for MockRadio {
+	fn init<D: DelayNs>(&mut self, _: &mut D) -> Result<(), MockError> {
+		Ok(())
+	}
+	fn transmit_start<D: DelayNs>(
+		&mut self,
+		payload: &Payload,
+		_: &mut D,
+	) -> Result<(), MockError> {
+		self.transmit(payload, false);
+		Ok(())
+	}
+	fn transmit_start_no_ack<D: DelayNs>(
+		&mut self,
+		payload: &Payload,
+		_: &mut D,
+	) -> Result<(), MockError> {
+		self.transmit(payload, true);
+		Ok(())
+	}
+	fn transmit_poll(&mut self) -> nb::Result<bool, MockError> {
+		Ok(self.acked)
+	}
+	/// Filters like the cc1101 does, in software
+	fn receive<P: InputPin>(
+		&mut self,
+		_: &mut P,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<Payload, MockError> {
+		let payload = self.rx.pop_front().ok_or(nb::Error::WouldBlock)?;
+		match (payload.address(), rx_addresses) {
+			(Some(address), Some(addresses)) if !address_accepted(address, addresses) => {
+				Err(nb::Error::WouldBlock)
+			}
+			_ => Ok(payload),
+		}
+	}
+	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), MockError> {
+		self.rx_filter = rx_pipes.to_vec();
+		Ok(())
+	}
+	fn set_channel(&mut self, channel: u8) -> Result<(), MockError> {
+		self.channel = channel;
+		Ok(())
+	}
+	fn energy_scan<D: DelayNs>(&mut self, channel: u8, _: &mut D) -> Result<u8, MockError> {
+		self.channel = channel;
+		Ok(self.energy)
+	}
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, MockError> {
+		Ok(dbm)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, MockError> {
+		Ok(bps)
+	}
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			tx_power_min: -30,
+			tx_power_max: 10,
+			data_rate_min: 1200,
+			data_rate_max: 2_000_000,
+			channel_max: 125,
+			auto_ack: self.auto_ack,
+		}
+	}
+	fn rssi(&mut self) -> Option<i8> {
+		if self.rssi.len() > 1 {
+			self.rssi.pop_front()
+		} else {
+			self.rssi.front().copied()
+		}
+	}
+	fn to_rx(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+	fn to_tx(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+	fn to_idle(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+	fn flush_rx(&mut self) -> Result<(), MockError> {
+		self.rx.clear();
+		Ok(())
+	}
+	fn flush_tx(&mut self) -> Result<(), MockError> {
+		match self.fail_flush_tx {
+			true => Err(MockError),
+			false => Ok(()),
+		}
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_rx_async(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_idle_async(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_tx_async(&mut self) -> Result<(), MockError> {
+		Ok(())
+	}
+}
@@ -1,24 +1,118 @@
 pub mod helper;
 /// Provides a trait for Radios to implement, so that we only use 1 API
+#[cfg(any(feature = "cc1101", feature = "nrf24"))]
 mod radios;
+#[cfg(feature = "nrf24")]
+pub use radios::SamnNrf24;
+#[cfg(feature = "cc1101")]
+pub use radios::{CollisionStats, Csma, SamnCc1101};
+#[cfg(feature = "ip")]
+pub mod ip;
+#[cfg(all(test, any(feature = "std", feature = "ip")))]
+pub(crate) mod mock;
+#[cfg(any(feature = "nrf24", test))]
+mod nrf24_pipes;
+#[cfg(feature = "std")]
+pub mod pcap;
+pub mod power;
+#[cfg(feature = "serial")]
+pub mod serial;
+
+use crate::node::{is_group_address, BROADCAST_ADDRESS, GROUP_ADDRESS_MIN};
 
 pub const DEFAULT_PIPE: u8 = 0x97u8;
+/// Pipes HQ's nrf24 receives on, on its data pipes 1 to 5
+///
+/// Nodes are split in groups by address, one group per pipe, so acks from HQ
+/// to nodes in different groups don't collide. The first one is [`DEFAULT_PIPE`],
+/// nodes that haven't joined yet (and older nodes) still get through on it.
+pub const NRF24_HQ_PIPES: [u8; 5] = [DEFAULT_PIPE, 0x98, 0x99, 0x9A, 0x9B];
+/// Pipe group and broadcast messages go out on, nodes add it to their rx filter
+pub const BROADCAST_PIPE: u8 = 0x96;
+/// What a node passes to [`Radio::set_rx_filter`]
+///
+/// Its own pipe twice, the nrf24 takes pipe 0 over for acks while sending and keeps
+/// receiving on pipe 1, then [`BROADCAST_PIPE`]. The cc1101 only filters on the first.
+pub fn node_rx_filter(addr: u16) -> [u8; 3] {
+	let pipe = addr_to_rx_pipe(addr);
+	[pipe, pipe, BROADCAST_PIPE]
+}
 /// Gives the pipe this node addr will receive on
 /// - Used by node to set rx_filter
 /// - Used by hq to send message to node
 pub fn addr_to_rx_pipe(addr: u16) -> u8 {
+	if is_group_address(addr) {
+		return BROADCAST_PIPE;
+	}
 	// The - 128 is to make sure we are out of HQ's pipe range
 	((addr.wrapping_sub(128)) % 256) as u8
 }
-/// - Used by node to send a message to HQ
-pub fn addr_to_nrf24_hq_pipe(_: u16) -> u8 {
-	// Because nrf24 on HQ not working with multiple pipes :(
-	DEFAULT_PIPE
+/// Whether HQ can give this address to a node
+///
+/// Its rx pipe can't be one of HQ's or the broadcast one, and groups are off limits.
+pub fn addr_is_usable(addr: u16) -> bool {
+	let pipe = addr_to_rx_pipe(addr);
+	!is_group_address(addr) && pipe != BROADCAST_PIPE && !NRF24_HQ_PIPES.contains(&pipe)
+}
+/// Whether a payload for `address` gets through an `rx_addresses` filter
+///
+/// Broadcasts always do, groups only when they're in the filter,
+/// see [`Groups`](crate::group::Groups).
+pub fn address_accepted(address: u16, rx_addresses: &[u16]) -> bool {
+	address == BROADCAST_ADDRESS || rx_addresses.contains(&address)
+}
+/// Which of [`NRF24_HQ_PIPES`] a node is in, HQ picks it in [`assign_address`]
+pub fn addr_to_nrf24_hq_group(addr: u16) -> usize {
+	addr as usize % NRF24_HQ_PIPES.len()
+}
+/// The address HQ gives a joining node, sent back in `Message::Network`
+///
+/// Only [usable](addr_is_usable) addresses that aren't `taken` by other nodes,
+/// in the group with the fewest nodes so they're spread over HQ's pipes.
+/// `None` once every address is taken.
+pub fn assign_address(taken: &[u16]) -> Option<u16> {
+	let mut per_group = [0usize; NRF24_HQ_PIPES.len()];
+	for addr in taken.iter().filter(|addr| addr_is_usable(**addr)) {
+		per_group[addr_to_nrf24_hq_group(*addr)] += 1;
+	}
+	let mut groups: [usize; NRF24_HQ_PIPES.len()] = core::array::from_fn(|group| group);
+	groups.sort_unstable_by_key(|group| (per_group[*group], *group));
+	// A full group moves on to the next emptiest one
+	groups.into_iter().find_map(|group| {
+		(1..GROUP_ADDRESS_MIN).find(|addr| {
+			addr_to_nrf24_hq_group(*addr) == group
+				&& addr_is_usable(*addr)
+				&& !taken.contains(addr)
+		})
+	})
 }
+/// - Used by node to send a message to HQ, see [`Radio::hq_pipe`]
+pub fn addr_to_nrf24_hq_pipe(addr: u16) -> u8 {
+	NRF24_HQ_PIPES[addr_to_nrf24_hq_group(addr)]
+}
+/// The cc1101 only filters on one byte, so every node shares the one pipe
 pub fn addr_to_cc1101_hq_pipe(_: u16) -> u8 {
 	DEFAULT_PIPE
 }
 
+/// What a radio can be set to
+///
+/// The default is a radio we don't know anything about, it can't be set to anything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+	/// Lowest tx power, dBm
+	pub tx_power_min: i8,
+	/// Highest tx power, dBm
+	pub tx_power_max: i8,
+	/// Bits per second
+	pub data_rate_min: u32,
+	/// Bits per second
+	pub data_rate_max: u32,
+	pub channel_max: u8,
+	/// Whether the radio acks and retransmits on its own
+	pub auto_ack: bool,
+}
+
 pub trait Radio<E> {
 	/// Resets the device (if possible) and configures with default settings
 	fn init<D: embedded_hal::delay::DelayNs>(&mut self, delay: &mut D) -> Result<(), E>;
@@ -30,6 +124,17 @@ pub trait Radio<E> {
 		payload: &Payload,
 		delay: &mut D,
 	) -> Result<(), E>;
+	/// [`Radio::transmit_start`] without asking the other end for an ack
+	///
+	/// For broadcasts, every node acking at once would only collide.
+	/// Radios that don't ack on their own send it like any other payload.
+	fn transmit_start_no_ack<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), E> {
+		self.transmit_start(payload, delay)
+	}
 	/// Polls the trasmission. Returning when tranmission was done.
 	fn transmit_poll(&mut self) -> nb::Result<bool, E>;
 	/// Implemented on nrf24 + cc1101
@@ -43,6 +148,34 @@ pub trait Radio<E> {
 	/// For the nrf24 this will set the 6 data pipe addresses
 	/// For the cc1101 this will set the 1 address filter (to the least significant byte on the first address)
 	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), E>;
+	/// Channels are the radio's own, 0-125 on the nrf24 and 0-255 on the cc1101
+	fn set_channel(&mut self, channel: u8) -> Result<(), E>;
+	/// How busy `channel` is, from 0 (quiet) to 255, leaves the radio listening on `channel`
+	fn energy_scan<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		channel: u8,
+		delay: &mut D,
+	) -> Result<u8, E>;
+	/// Sets the closest tx power at or below `dbm` (or the lowest), gives back what was set
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, E>;
+	/// Sets the closest data rate the radio can do, gives back what was set
+	///
+	/// Both ends of a link have to be on the same data rate.
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, E>;
+	fn capabilities(&self) -> Capabilities;
+	/// Pipe a node sends to HQ on
+	///
+	/// HQ's nrf24 listens on all of [`NRF24_HQ_PIPES`] and spreads nodes over them,
+	/// other radios filter on a single byte and only get [`DEFAULT_PIPE`].
+	fn hq_pipe(&self, node_addr: u16) -> u8 {
+		addr_to_cc1101_hq_pipe(node_addr)
+	}
+	/// Signal strength in dBm right now, `None` when the radio can't tell
+	///
+	/// Read right after [`Radio::receive`] it's close to what the payload came in with.
+	fn rssi(&mut self) -> Option<i8> {
+		None
+	}
 	fn to_rx(&mut self) -> Result<(), E>;
 	fn to_tx(&mut self) -> Result<(), E>;
 	fn to_idle(&mut self) -> Result<(), E>;
@@ -57,6 +190,20 @@ pub trait Radio<E> {
 	fn to_idle_async(&mut self) -> impl std::future::Future<Output = Result<(), E>>;
 }
 
+/// Packet ready pin for radios that don't have one (serial, ip), always low
+pub struct NoIrq;
+impl embedded_hal::digital::ErrorType for NoIrq {
+	type Error = core::convert::Infallible;
+}
+impl embedded_hal::digital::InputPin for NoIrq {
+	fn is_high(&mut self) -> Result<bool, Self::Error> {
+		Ok(false)
+	}
+	fn is_low(&mut self) -> Result<bool, Self::Error> {
+		Ok(true)
+	}
+}
+
 /// Payload is (pipe, len, addr1, addr0, ...data)
 #[derive(Default)]
 pub struct Payload([u8; 32]);
@@ -113,9 +260,43 @@ impl Payload {
 		s
 	}
 
+	/// A payload from exactly its [`Payload::payload`] bytes, like off the air or a capture
+	pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		let mut buf = [0u8; 32];
+		buf.get_mut(..bytes.len())?.copy_from_slice(bytes);
+		let payload = Self(buf);
+		(payload.len_is_valid() && payload.len_total() == bytes.len()).then_some(payload)
+	}
+
 	fn has_address(&self) -> bool {
 		((self.0[1] >> 7) & 1) == 1
 	}
+	fn has_sequence(&self) -> bool {
+		self.has_address() && ((self.0[1] >> 6) & 1) == 1
+	}
+	/// Adds a sequence number after the address, so the other end can drop repeats
+	///
+	/// Takes a byte away from the data, `false` when the data doesn't leave room for it
+	/// or there's no address. Receivers older than this read the flag as part of the
+	/// length and go out of bounds, only send one to those that know about it
+	/// (see [`RadioTransport`](crate::transport::RadioTransport)).
+	pub fn set_sequence(&mut self, sequence: u8) -> bool {
+		if self.has_sequence() {
+			self.0[4] = sequence;
+			return true;
+		}
+		if !self.has_address() || self.len_total() >= self.0.len() {
+			return false;
+		}
+		let len_total = self.len_total();
+		self.0.copy_within(4..len_total, 5);
+		self.0[4] = sequence;
+		self.0[1] |= 1 << 6;
+		true
+	}
+	pub fn sequence(&self) -> Option<u8> {
+		self.has_sequence().then_some(self.0[4])
+	}
 	pub fn address(&self) -> Option<u16> {
 		if self.has_address() {
 			Some(u16::from_le_bytes(self.0[2..4].try_into().unwrap()))
@@ -125,7 +306,9 @@ impl Payload {
 	}
 
 	fn header_length(&self) -> usize {
-		if self.has_address() {
+		if self.has_sequence() {
+			5
+		} else if self.has_address() {
 			4
 		} else {
 			2
@@ -133,7 +316,11 @@ impl Payload {
 	}
 	/// Get the length of the data
 	pub fn len(&self) -> usize {
-		(self.0[1] & !(1 << 7)).into()
+		if self.has_address() {
+			(self.0[1] & !(0b11 << 6)).into()
+		} else {
+			(self.0[1] & !(1 << 7)).into()
+		}
 	}
 	pub fn is_empty(&self) -> bool {
 		self.len() == 0
@@ -150,17 +337,63 @@ impl Payload {
 		!self.is_empty() && self.len() <= self.0.len() - self.header_length()
 	}
 	/// Get the data section of the payload
+	///
+	/// Empty when the length byte got garbled on the air (see [`Payload::len_is_valid`]).
 	pub fn data(&self) -> &[u8] {
-		&self.0[self.header_length()..self.len_total()]
+		self.0.get(self.header_length()..self.len_total()).unwrap_or(&[])
 	}
-	/// Get the entire payload
+	/// Get the entire payload, only the header when the length is garbled
 	pub fn payload(&self) -> &[u8] {
-		&self.0[..self.len_total()]
+		self.0.get(..self.len_total()).unwrap_or(&self.0[..self.header_length()])
 	}
 }
 
 mod test {
 
+	#[test]
+	fn hq_pipes_for_addresses() {
+		use crate::radio::*;
+
+		// Joining nodes without an address use the default pipe
+		assert_eq!(addr_to_nrf24_hq_pipe(0), DEFAULT_PIPE);
+		let mut per_pipe = [0; 5];
+		for addr in (1..=500).filter(|addr| addr_is_usable(*addr)) {
+			let pipe = addr_to_nrf24_hq_pipe(addr);
+			per_pipe[NRF24_HQ_PIPES.iter().position(|p| *p == pipe).unwrap()] += 1;
+			assert_ne!(addr_to_rx_pipe(addr), pipe);
+		}
+		assert!(per_pipe.iter().all(|count| *count >= 95), "{per_pipe:?}");
+		assert!(!addr_is_usable(0x0117));
+		assert!(!addr_is_usable(0x0116));
+		assert!(!addr_is_usable(crate::node::group_address(3)));
+
+		// Joining nodes fill the emptiest group
+		let mut taken = vec![];
+		for _ in 0..NRF24_HQ_PIPES.len() * 2 {
+			let addr = assign_address(&taken).unwrap();
+			assert!(addr_is_usable(addr));
+			taken.push(addr);
+		}
+		let mut per_group = [0; 5];
+		for addr in &taken {
+			per_group[addr_to_nrf24_hq_group(*addr)] += 1;
+		}
+		assert_eq!(per_group, [2; 5]);
+		taken.push(assign_address(&taken).unwrap());
+		taken.push(assign_address(&taken).unwrap());
+		assert_ne!(
+			addr_to_nrf24_hq_group(taken[10]),
+			addr_to_nrf24_hq_group(taken[11])
+		);
+		// Unusable addresses nodes already have don't count
+		assert_eq!(assign_address(&[0x0116]), assign_address(&[]));
+		assert_eq!(addr_to_rx_pipe(BROADCAST_ADDRESS), BROADCAST_PIPE);
+
+		assert!(address_accepted(BROADCAST_ADDRESS, &[0x0204]));
+		assert!(address_accepted(0xFF03, &[0x0204, 0xFF03]));
+		assert!(!address_accepted(0xFF04, &[0x0204, 0xFF03]));
+	}
+
 	#[test]
 	fn try_payload() {
 		use crate::radio::Payload;
@@ -181,6 +414,10 @@ mod test {
 		assert_eq!(payload.len_is_valid(), true);
 		payload.0[1] = 29 | (1 << 7);
 		assert_eq!(payload.len_is_valid(), false);
+		// Too long, doesn't panic
+		payload.0[1] = 0x3F | (1 << 7);
+		assert!(payload.data().is_empty());
+		assert_eq!(payload.payload(), &payload.0[..4]);
 	}
 
 	#[test]
@@ -208,4 +445,26 @@ mod test {
 		payload.0[1] = 29 | (1 << 7);
 		assert_eq!(payload.len_is_valid(), false);
 	}
+
+	#[test]
+	fn payload_sequence() {
+		use crate::radio::Payload;
+
+		let mut payload = Payload::new_with_addr(&[1, 2, 3], 0x5555, 0x22);
+		assert_eq!(payload.sequence(), None);
+		assert!(payload.set_sequence(9));
+		assert_eq!(payload.sequence(), Some(9));
+		assert_eq!(payload.data(), [1, 2, 3]);
+		assert_eq!(payload.address(), Some(0x5555));
+		assert_eq!(payload.len_total(), 8);
+		assert!(payload.set_sequence(10));
+		assert_eq!(payload.sequence(), Some(10));
+		assert_eq!(payload.data(), [1, 2, 3]);
+
+		// No room left
+		let mut full = Payload::new_with_addr(&[7; 28], 0x5555, 0x22);
+		assert!(!full.set_sequence(1));
+		assert_eq!(full.sequence(), None);
+		assert!(Payload::new_with_addr(&[7; 27], 0x5555, 0x22).set_sequence(1));
+	}
 }
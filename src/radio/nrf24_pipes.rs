@@ -0,0 +1,143 @@
+//! What [`SamnNrf24`](super::SamnNrf24) has set its pipes to, kept apart from the
+//! radio so it can be tested without one
+
+/// Pipes enabled on init, 0 is needed for acks when sending and 1 is the one we receive on
+pub(crate) const NRF24_PIPES: [bool; 6] = [true, true, false, false, false, false];
+
+/// Only what changed has to be written to the radio
+///
+/// Each `*_changed` gives back `Some` with what to write, call the matching setter
+/// once the write went through so a failed one is tried again next time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Nrf24Pipes {
+	/// Pipe the tx address (and rx pipe 0, for acks) is set to
+	tx_pipe: Option<u8>,
+	/// Pipes currently enabled for receiving
+	rx_enabled: [bool; 6],
+}
+
+impl Nrf24Pipes {
+	pub const fn new() -> Self {
+		Self {
+			tx_pipe: None,
+			rx_enabled: NRF24_PIPES,
+		}
+	}
+
+	/// The radio was reset, addresses are unknown and pipes are back to [`NRF24_PIPES`]
+	pub fn reset(&mut self) {
+		*self = Self::new();
+	}
+
+	/// Tx address to write before sending on `pipe`
+	pub fn tx_pipe_changed(&self, pipe: u8) -> Option<[u8; 5]> {
+		(self.tx_pipe != Some(pipe)).then_some(address(pipe))
+	}
+	pub fn set_tx_pipe(&mut self, pipe: u8) {
+		self.tx_pipe = Some(pipe);
+	}
+	/// Rx pipe 0 got another address, it has to be set again before sending
+	pub fn rx_pipe_0_changed(&mut self) {
+		self.tx_pipe = None;
+	}
+
+	pub fn rx_enabled(&self) -> [bool; 6] {
+		self.rx_enabled
+	}
+	pub fn rx_enabled_changed(&self, pipes: [bool; 6]) -> Option<[bool; 6]> {
+		(self.rx_enabled != pipes).then_some(pipes)
+	}
+	pub fn set_rx_enabled(&mut self, pipes: [bool; 6]) {
+		self.rx_enabled = pipes;
+	}
+
+	/// Enabled pipes once the rx filter has `count` addresses, one per pipe
+	///
+	/// Pipes 0 and 1 stay as they are, 0 is for acks and 1 is always on.
+	pub fn rx_filter_enabled(&self, count: usize) -> [bool; 6] {
+		let mut pipes = self.rx_enabled;
+		for (i, enabled) in pipes.iter_mut().enumerate().skip(2) {
+			*enabled = i < count;
+		}
+		pipes
+	}
+
+	/// Enabled pipes while sending a message to HQ and after
+	///
+	/// Pipe 0 is on while sending because the ack from HQ comes in on it,
+	/// and off afterwards so we don't receive messages from other nodes headed to HQ.
+	pub fn sending_to_hq(&self) -> ([bool; 6], [bool; 6]) {
+		let mut sending = self.rx_enabled;
+		sending[0] = true;
+		let mut after = self.rx_enabled;
+		after[0] = false;
+		(sending, after)
+	}
+}
+
+/// Full 5 byte address for `pipe`, the other bytes are [`DEFAULT_PIPE`](super::DEFAULT_PIPE)
+pub(crate) fn address(pipe: u8) -> [u8; 5] {
+	let d = super::DEFAULT_PIPE;
+	[pipe, d, d, d, d]
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn only_changes_are_written() {
+		let mut pipes = Nrf24Pipes::new();
+		assert_eq!(
+			pipes.tx_pipe_changed(0x98),
+			Some([0x98, 0x97, 0x97, 0x97, 0x97])
+		);
+		// The write failed, it's still needed
+		assert!(pipes.tx_pipe_changed(0x98).is_some());
+		pipes.set_tx_pipe(0x98);
+		assert_eq!(pipes.tx_pipe_changed(0x98), None);
+		assert!(pipes.tx_pipe_changed(0x99).is_some());
+
+		pipes.rx_pipe_0_changed();
+		assert!(pipes.tx_pipe_changed(0x98).is_some());
+		pipes.set_tx_pipe(0x98);
+		pipes.reset();
+		assert!(pipes.tx_pipe_changed(0x98).is_some());
+
+		assert_eq!(pipes.rx_enabled_changed(NRF24_PIPES), None);
+		let all = [true; 6];
+		assert_eq!(pipes.rx_enabled_changed(all), Some(all));
+		pipes.set_rx_enabled(all);
+		assert_eq!(pipes.rx_enabled(), all);
+		assert_eq!(pipes.rx_enabled_changed(all), None);
+	}
+
+	#[test]
+	fn rx_filter_enables_its_pipes() {
+		let pipes = Nrf24Pipes::new();
+		// A node's filter, its own pipe and the broadcast one
+		let enabled = pipes.rx_filter_enabled(3);
+		assert_eq!(enabled, [true, true, true, false, false, false]);
+		assert_eq!(pipes.rx_filter_enabled(1), NRF24_PIPES);
+
+		let mut pipes = Nrf24Pipes::new();
+		pipes.set_rx_enabled([false, true, true, true, true, true]);
+		let enabled = pipes.rx_filter_enabled(2);
+		assert_eq!(enabled, [false, true, false, false, false, false]);
+	}
+
+	#[test]
+	fn pipe_0_only_while_sending() {
+		let mut pipes = Nrf24Pipes::new();
+		let (sending, after) = pipes.sending_to_hq();
+		assert_eq!(sending, NRF24_PIPES);
+		assert_eq!(after, [false, true, false, false, false, false]);
+		// Done sending, next time pipe 0 goes on and off again
+		pipes.set_rx_enabled(after);
+		assert_eq!(
+			pipes.rx_enabled_changed(pipes.sending_to_hq().0),
+			Some(sending)
+		);
+		assert_eq!(pipes.rx_enabled_changed(pipes.sending_to_hq().1), None);
+	}
+}
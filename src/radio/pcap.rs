@@ -0,0 +1,279 @@
+//! Capturing raw payloads to a pcap file, to look at what went over the air afterwards
+//!
+//! Records use [`LINKTYPE_SAMN`] (`LINKTYPE_USER0`), each one is
+//! `[capture version, rssi (i8, 0x80 when unknown)]` followed by [`Payload::payload`],
+//! pipe first. `tools/samn.lua` is the Wireshark dissector for it:
+//! `wireshark -X lua_script:tools/samn.lua capture.pcap`.
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Capabilities, Payload, Radio};
+
+/// `LINKTYPE_USER0`, the dissector registers itself on it
+pub const LINKTYPE_SAMN: u32 = 147;
+/// First byte of every record, bump it when the record layout changes
+pub const CAPTURE_VERSION: u8 = 1;
+/// Rssi byte when the radio couldn't tell
+pub const RSSI_UNKNOWN: u8 = 0x80;
+
+/// Writes payloads to a classic (not ng) pcap file, microsecond timestamps
+pub struct PcapWriter<W> {
+	writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+	/// Writes the file header right away
+	pub fn new(mut writer: W) -> io::Result<Self> {
+		let mut header = [0u8; 24];
+		header[0..4].copy_from_slice(&0xA1B2_C3D4u32.to_le_bytes());
+		// Version 2.4
+		header[4..6].copy_from_slice(&2u16.to_le_bytes());
+		header[6..8].copy_from_slice(&4u16.to_le_bytes());
+		// Timezone and accuracy stay 0, snaplen
+		header[16..20].copy_from_slice(&(2 + 32u32).to_le_bytes());
+		header[20..24].copy_from_slice(&LINKTYPE_SAMN.to_le_bytes());
+		writer.write_all(&header)?;
+		Ok(Self { writer })
+	}
+
+	pub fn write(
+		&mut self,
+		payload: &Payload,
+		rssi: Option<i8>,
+		at: SystemTime,
+	) -> io::Result<()> {
+		let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+		let bytes = payload.payload();
+		let len = (2 + bytes.len()) as u32;
+		let mut record = [0u8; 16 + 2];
+		record[0..4].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+		record[4..8].copy_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+		record[8..12].copy_from_slice(&len.to_le_bytes());
+		record[12..16].copy_from_slice(&len.to_le_bytes());
+		record[16] = CAPTURE_VERSION;
+		record[17] = rssi.map(|rssi| rssi as u8).unwrap_or(RSSI_UNKNOWN);
+		self.writer.write_all(&record)?;
+		self.writer.write_all(bytes)
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.writer.flush()
+	}
+	pub fn into_inner(self) -> W {
+		self.writer
+	}
+}
+
+/// A [`Radio`] that writes every payload it receives to a [`PcapWriter`], when there is one
+///
+/// Payloads are captured before the `rx_addresses` check, so the capture also has
+/// what was dropped. A write error stops the capture, it's kept for [`Captured::take_error`].
+pub struct Captured<R, W> {
+	radio: R,
+	pcap: Option<PcapWriter<W>>,
+	error: Option<io::Error>,
+}
+
+impl<R, W: Write> Captured<R, W> {
+	pub fn new(radio: R, pcap: Option<PcapWriter<W>>) -> Self {
+		Self {
+			radio,
+			pcap,
+			error: None,
+		}
+	}
+	/// Starts, switches or stops (`None`) capturing, gives back the last writer
+	pub fn set_capture(&mut self, pcap: Option<PcapWriter<W>>) -> Option<PcapWriter<W>> {
+		core::mem::replace(&mut self.pcap, pcap)
+	}
+	/// Why capturing stopped, if it did
+	pub fn take_error(&mut self) -> Option<io::Error> {
+		self.error.take()
+	}
+	pub fn inner(&mut self) -> &mut R {
+		&mut self.radio
+	}
+	pub fn into_inner(self) -> R {
+		self.radio
+	}
+}
+
+impl<E, R: Radio<E>, W: Write> Radio<E> for Captured<R, W> {
+	fn init<D: embedded_hal::delay::DelayNs>(&mut self, delay: &mut D) -> Result<(), E> {
+		self.radio.init(delay)
+	}
+	fn transmit_start<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), E> {
+		self.radio.transmit_start(payload, delay)
+	}
+	fn transmit_start_no_ack<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), E> {
+		self.radio.transmit_start_no_ack(payload, delay)
+	}
+	fn transmit_poll(&mut self) -> nb::Result<bool, E> {
+		self.radio.transmit_poll()
+	}
+	fn receive<P: embedded_hal::digital::InputPin>(
+		&mut self,
+		packet_ready_pin: &mut P,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<Payload, E> {
+		let payload = self.radio.receive(packet_ready_pin, None)?;
+		if let Some(pcap) = &mut self.pcap {
+			let rssi = self.radio.rssi();
+			let written = pcap
+				.write(&payload, rssi, SystemTime::now())
+				.and_then(|_| pcap.flush());
+			if let Err(err) = written {
+				self.pcap = None;
+				self.error = Some(err);
+			}
+		}
+		match (payload.address(), rx_addresses) {
+			(Some(address), Some(addresses))
+				if !super::address_accepted(address, addresses) =>
+			{
+				Err(nb::Error::WouldBlock)
+			}
+			_ => Ok(payload),
+		}
+	}
+	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), E> {
+		self.radio.set_rx_filter(rx_pipes)
+	}
+	fn set_channel(&mut self, channel: u8) -> Result<(), E> {
+		self.radio.set_channel(channel)
+	}
+	fn energy_scan<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		channel: u8,
+		delay: &mut D,
+	) -> Result<u8, E> {
+		self.radio.energy_scan(channel, delay)
+	}
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, E> {
+		self.radio.set_tx_power(dbm)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, E> {
+		self.radio.set_data_rate(bps)
+	}
+	fn capabilities(&self) -> Capabilities {
+		self.radio.capabilities()
+	}
+	fn hq_pipe(&self, node_addr: u16) -> u8 {
+		self.radio.hq_pipe(node_addr)
+	}
+	fn rssi(&mut self) -> Option<i8> {
+		self.radio.rssi()
+	}
+	fn to_rx(&mut self) -> Result<(), E> {
+		self.radio.to_rx()
+	}
+	fn to_tx(&mut self) -> Result<(), E> {
+		self.radio.to_tx()
+	}
+	fn to_idle(&mut self) -> Result<(), E> {
+		self.radio.to_idle()
+	}
+	fn flush_rx(&mut self) -> Result<(), E> {
+		self.radio.flush_rx()
+	}
+	fn flush_tx(&mut self) -> Result<(), E> {
+		self.radio.flush_tx()
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_tx_async(&mut self) -> Result<(), E> {
+		self.radio.to_tx_async().await
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_rx_async(&mut self) -> Result<(), E> {
+		self.radio.to_rx_async().await
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_idle_async(&mut self) -> Result<(), E> {
+		self.radio.to_idle_async().await
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn pcap_records() {
+		let payload = Payload::new_with_addr(&[0x40, 0x12], 0x0204, 0x97);
+		let at = UNIX_EPOCH + std::time::Duration::from_micros(1_700_000_000_000_250);
+		let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+		pcap.write(&payload, Some(-60), at).unwrap();
+		pcap.write(&payload, None, at).unwrap();
+		let bytes = pcap.into_inner();
+
+		assert_eq!(bytes[..4], [0xD4, 0xC3, 0xB2, 0xA1]);
+		assert_eq!(bytes[20..24], 147u32.to_le_bytes());
+		let record = &bytes[24..];
+		assert_eq!(record[0..4], 1_700_000_000u32.to_le_bytes());
+		assert_eq!(record[4..8], 250u32.to_le_bytes());
+		assert_eq!(record[8..12], 8u32.to_le_bytes());
+		assert_eq!(
+			record[16..24],
+			[1, (-60i8) as u8, 0x97, 0x82, 0x04, 0x02, 0x40, 0x12]
+		);
+		assert_eq!(record[24 + 17], RSSI_UNKNOWN);
+		assert_eq!(bytes.len(), 24 + 2 * (16 + 8));
+	}
+
+	#[test]
+	fn dissector_in_sync() {
+		let lua = include_str!("../../tools/samn.lua");
+		assert!(lua.contains(&format!("CAPTURE_VERSION = {CAPTURE_VERSION}\n")));
+		assert!(lua.contains(&format!("RSSI_UNKNOWN = 0x{RSSI_UNKNOWN:02X}\n")));
+		assert!(lua.contains(&format!("LIMBS_MAX = {}\n", crate::node::LIMBS_MAX)));
+
+		// The `Enum::Variant.. => code,` arms of node.rs' `code()`s
+		let node = include_str!("../node.rs");
+		let codes = |name: &str| -> Vec<(u8, String)> {
+			let prefix = format!("{name}::");
+			node
+				.lines()
+				.filter_map(|line| {
+					let (variant, code) = line.trim().strip_prefix(&prefix)?.split_once(" => ")?;
+					let code = code.strip_suffix(',')?.parse().ok()?;
+					let end = variant.find(['(', ' ']).unwrap_or(variant.len());
+					Some((code, variant[..end].to_string()))
+				})
+				// Codes are 4 bits on the air, the error responses don't fit
+				.filter(|(code, _)| *code < 16)
+				.collect()
+		};
+		// `[code] = "Variant",` lines of a lua table
+		let table = |name: &str| -> Vec<(u8, String)> {
+			let start = lua.find(&format!("local {name} = {{\n")).unwrap();
+			let end = start + lua[start..].find("\n}").unwrap();
+			lua[start..end]
+				.lines()
+				.skip(1)
+				.map(|line| {
+					let (code, variant) = line.trim().split_once("] = ").unwrap();
+					let code = code.trim_start_matches('[').parse().unwrap();
+					let variant = variant.trim_end_matches(',').trim_matches('"');
+					(code, variant.to_string())
+				})
+				.collect()
+		};
+		for (enum_name, table_name) in [
+			("Message", "messages"),
+			("Command", "commands"),
+			("Response", "responses"),
+		] {
+			let codes = codes(enum_name);
+			assert!(!codes.is_empty(), "no codes for {enum_name} in node.rs");
+			assert_eq!(codes, table(table_name), "{table_name} in tools/samn.lua");
+		}
+	}
+}
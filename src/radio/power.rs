@@ -0,0 +1,165 @@
+//! Adaptive tx power, as low as it goes while messages still get through
+//!
+//! Feed [`PowerControl::record`] the outcome of every send. Each window of sends where
+//! delivery stayed at or above the target (and the link had margin) steps power down,
+//! as soon as losses make the target unreachable it steps back up by twice as much.
+//! ```ignore
+//! let mut power = PowerControl::new(&radio.capabilities(), 6, 90, 20);
+//! power.apply(&mut radio)?;
+//! loop {
+//!   let acked = send_message(&mut radio, ...)?;
+//!   if power.record(acked, None) {
+//!     power.apply(&mut radio)?;
+//!   }
+//! }
+//! ```
+use super::{Capabilities, Radio};
+
+#[derive(Clone, Debug)]
+pub struct PowerControl {
+	min: i8,
+	max: i8,
+	step: i8,
+	/// What the radio is set to
+	current: i8,
+	/// What we want it set to
+	wanted: i8,
+	/// Delivery target in percent
+	target: u8,
+	window: u8,
+	sent: u8,
+	lost: u8,
+	/// Only step down when every RSSI in the window is at or above this
+	rssi_floor: Option<i8>,
+	rssi_ok: bool,
+}
+
+impl PowerControl {
+	/// Starts at full power
+	///
+	/// `step` is in dB and should be at least the radio's own step (6 for the nrf24),
+	/// otherwise asking for a bit more power can give back the same level.
+	pub fn new(capabilities: &Capabilities, step: i8, target: u8, window: u8) -> Self {
+		Self {
+			min: capabilities.tx_power_min,
+			max: capabilities.tx_power_max,
+			step: step.max(1),
+			current: capabilities.tx_power_max,
+			wanted: capabilities.tx_power_max,
+			target: target.min(100),
+			window: window.max(1),
+			sent: 0,
+			lost: 0,
+			rssi_floor: None,
+			rssi_ok: true,
+		}
+	}
+	/// Also require the other end to hear us at `dbm` or better before stepping down
+	pub fn with_rssi_floor(mut self, dbm: i8) -> Self {
+		self.rssi_floor = Some(dbm);
+		self
+	}
+
+	/// What the radio is set to, as far as we know
+	pub fn current(&self) -> i8 {
+		self.current
+	}
+
+	/// Losses we can have in a window and still meet the target
+	fn allowed_losses(&self) -> u8 {
+		let needed = (self.window as u16 * self.target as u16).div_ceil(100);
+		self.window - needed as u8
+	}
+
+	fn reset_window(&mut self) {
+		self.sent = 0;
+		self.lost = 0;
+		self.rssi_ok = true;
+	}
+
+	/// Records a send, `true` when power should change, see [`PowerControl::apply`]
+	///
+	/// `rssi` is how well the other end heard us, if it tells us.
+	pub fn record(&mut self, delivered: bool, rssi: Option<i8>) -> bool {
+		self.sent += 1;
+		if !delivered {
+			self.lost += 1;
+		}
+		if let (Some(floor), Some(rssi)) = (self.rssi_floor, rssi) {
+			self.rssi_ok &= rssi >= floor;
+		}
+
+		if self.lost > self.allowed_losses() {
+			self.reset_window();
+			let step_up = self.step.saturating_mul(2);
+			self.wanted = self.current.saturating_add(step_up).min(self.max);
+		} else if self.sent >= self.window {
+			let step_down = self.rssi_ok;
+			self.reset_window();
+			if !step_down {
+				return false;
+			}
+			self.wanted = self.current.saturating_sub(self.step).max(self.min);
+		} else {
+			return false;
+		}
+		self.wanted != self.current
+	}
+
+	/// Sets the radio to the power we want
+	pub fn apply<E, R: Radio<E>>(&mut self, radio: &mut R) -> Result<i8, E> {
+		self.current = radio.set_tx_power(self.wanted)?;
+		self.wanted = self.current;
+		Ok(self.current)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn power_follows_delivery() {
+		let capabilities = Capabilities {
+			tx_power_min: -18,
+			tx_power_max: 0,
+			data_rate_min: 250_000,
+			data_rate_max: 2_000_000,
+			channel_max: 125,
+			auto_ack: true,
+		};
+		let mut power = PowerControl::new(&capabilities, 6, 90, 10);
+		// Stand-in for the radio
+		let settle = |power: &mut PowerControl| {
+			power.current = power.wanted;
+		};
+
+		// Steps down a window at a time, not past the minimum
+		for expected in [-6, -12, -18] {
+			assert!(!(0..9).any(|_| power.record(true, None)));
+			assert!(power.record(true, None));
+			settle(&mut power);
+			assert_eq!(power.current(), expected);
+		}
+		assert!(!(0..10).any(|_| power.record(true, None)));
+
+		// One loss in 10 is fine, the second one steps up right away
+		assert!(!power.record(false, None));
+		assert!(power.record(false, None));
+		settle(&mut power);
+		assert_eq!(power.current(), -6);
+
+		// Not heard well enough to step down
+		let mut power = PowerControl::new(&capabilities, 6, 90, 10).with_rssi_floor(-80);
+		assert!(!(0..10).any(|_| power.record(true, Some(-85))));
+		assert!(!(0..9).any(|_| power.record(true, Some(-70))));
+		assert!(power.record(true, None));
+
+		// Big steps don't overflow
+		let mut power = PowerControl::new(&capabilities, 100, 90, 1);
+		power.current = -18;
+		assert!(power.record(false, None));
+		settle(&mut power);
+		assert_eq!(power.current(), 0);
+	}
+}
@@ -1,6 +1,10 @@
 use crate::radio::DEFAULT_PIPE;
 
 use super::{Payload, Radio};
+#[cfg(feature = "nrf24")]
+use super::helper;
+#[cfg(feature = "nrf24")]
+use crate::node::Message;
 #[cfg(feature = "cc1101")]
 use cc1101::Cc1101;
 use embedded_hal::{digital::OutputPin, spi::SpiDevice};
@@ -8,52 +12,188 @@ use embedded_hal::{digital::OutputPin, spi::SpiDevice};
 use nrf24::NRF24L01;
 
 #[cfg(feature = "nrf24")]
-impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error>>
-	for NRF24L01<SPI, CE>
-{
-	
-	fn init<D: embedded_hal::delay::DelayNs>(
+use super::nrf24_pipes::{address, Nrf24Pipes, NRF24_PIPES};
+/// dBm for each of the nrf24's power levels
+#[cfg(feature = "nrf24")]
+const NRF24_TX_POWERS: [i8; 4] = [-18, -12, -6, 0];
+#[cfg(feature = "nrf24")]
+const NRF24_DATA_RATES: [u32; 3] = [250_000, 1_000_000, 2_000_000];
+
+/// Our nrf24, keeps track of how the radio's been set up so we only tell it what changed
+///
+/// Every radio gets its own bookkeeping, so a gateway can drive more than one.
+#[cfg(feature = "nrf24")]
+pub struct SamnNrf24<SPI, CE> {
+	radio: NRF24L01<SPI, CE>,
+	pipes: Nrf24Pipes,
+	/// Index in [`NRF24_TX_POWERS`]
+	tx_power: u8,
+	data_rate: u32,
+}
+
+#[cfg(feature = "nrf24")]
+impl<SPI: SpiDevice<u8>, CE: OutputPin> SamnNrf24<SPI, CE> {
+	pub fn new(radio: NRF24L01<SPI, CE>) -> Self {
+		Self {
+			radio,
+			pipes: Nrf24Pipes::new(),
+			tx_power: NRF24_TX_POWERS.len() as u8 - 1,
+			data_rate: 1_000_000,
+		}
+	}
+	/// Anything changed directly on the radio isn't tracked,
+	/// call [`Radio::init`] afterwards if addresses or pipes were touched
+	pub fn inner(&mut self) -> &mut NRF24L01<SPI, CE> {
+		&mut self.radio
+	}
+	pub fn into_inner(self) -> NRF24L01<SPI, CE> {
+		self.radio
+	}
+
+	pub fn rx_enabled_pipes(&self) -> [bool; 6] {
+		self.pipes.rx_enabled()
+	}
+	pub fn set_rx_enabled_pipes(
 		&mut self,
-		delay: &mut D,
+		pipes: [bool; 6],
 	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-		self.initialize(delay)?;
-		self.configure()?;
-
-		// Enable first 2 pipes
-		const PIPES: [bool; 6] = [true, true, false, false, false, false];
-		self.set_auto_ack_pipes(&PIPES)?;
-		self.set_rx_enabled_pipes(&PIPES)?;
-		self.set_dynamic_payload_pipes(&PIPES)?;
+		if let Some(pipes) = self.pipes.rx_enabled_changed(pipes) {
+			self.radio.set_rx_enabled_pipes(&pipes)?;
+			self.pipes.set_rx_enabled(pipes);
+		}
 		Ok(())
 	}
 
-	fn transmit_start<D: embedded_hal::delay::DelayNs>(
+	/// Power and data rate go in the same register
+	fn set_rf(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		let data_rate = match self.data_rate {
+			250_000 => nrf24::DataRate::R250Kbps,
+			2_000_000 => nrf24::DataRate::R2Mbps,
+			_ => nrf24::DataRate::R1Mbps,
+		};
+		self.radio.set_rf(&data_rate, self.tx_power)
+	}
+
+	/// Sets HQ up to receive on all of [`NRF24_HQ_PIPES`](super::NRF24_HQ_PIPES), call after init
+	///
+	/// Pipe 0 stays where acks come in, the HQ pipes go on pipes 1 to 5.
+	pub fn listen_as_hq(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		for (i, pipe) in super::NRF24_HQ_PIPES.iter().enumerate() {
+			if i == 0 {
+				self.radio.set_rx_addr(1, &address(*pipe))?;
+			} else {
+				// Pipes 2 to 5 share all but the first byte with pipe 1
+				self.radio.set_rx_addr(i as u8 + 1, &[*pipe])?;
+			}
+		}
+		const ALL_PIPES: [bool; 6] = [true; 6];
+		self.radio.set_auto_ack_pipes(&ALL_PIPES)?;
+		self.radio.set_dynamic_payload_pipes(&ALL_PIPES)?;
+		self.set_rx_enabled_pipes(ALL_PIPES)
+	}
+
+	/// [`Radio::receive`], also giving the data pipe (0 to 5) the payload came in on
+	///
+	/// On HQ pipe `n` is [`NRF24_HQ_PIPES`](super::NRF24_HQ_PIPES)`[n - 1]`.
+	pub fn receive_with_pipe(
+		&mut self,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<(u8, Payload), nrf24::Error<SPI::Error, CE::Error>> {
+		if let Some((pipe, buf)) = self.radio.receive_maybe()? {
+			let payload = Payload(buf);
+			// Discard payloads that aren't for this address
+			if let (Some(address), Some(addresses)) = (payload.address(), rx_addresses) {
+				if super::address_accepted(address, addresses) {
+					return nb::Result::Ok((pipe, payload));
+				}
+			} else {
+				return nb::Result::Ok((pipe, payload));
+			}
+		}
+		nb::Result::Err(nb::Error::WouldBlock)
+	}
+
+	fn transmit<D: embedded_hal::delay::DelayNs>(
 		&mut self,
 		payload: &Payload,
+		payload_type: nrf24::PayloadType,
 		delay: &mut D,
 	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-		self.to_tx()?;
+		self.radio.tx()?;
 
-		// Set tx & rx0 to  pipe #
-		static mut LAST_PIPE: u8 = 0;
+		// Set tx & rx0 to pipe #, only when it changed
 		let pipe = payload.pipe();
-		if unsafe { LAST_PIPE } != pipe {
-			// Set the tx address
-			let addr = [pipe, DEFAULT_PIPE, DEFAULT_PIPE, DEFAULT_PIPE, DEFAULT_PIPE];
-			self.set_tx_addr(&addr)?;
-			self.set_rx_addr(0, &addr)?;
-			unsafe {
-				LAST_PIPE = pipe;
-			}
+		if let Some(addr) = self.pipes.tx_pipe_changed(pipe) {
+			self.radio.set_tx_addr(&addr)?;
+			self.radio.set_rx_addr(0, &addr)?;
+			self.pipes.set_tx_pipe(pipe);
 		}
 
-		self.transmission_start(payload.payload(), nrf24::PayloadType::Payload, delay)?;
+		self
+			.radio
+			.transmission_start(payload.payload(), payload_type, delay)?;
+		Ok(())
+	}
+
+	/// Sends a message to HQ
+	///
+	/// Pipe 0 is enabled while sending because the ack from HQ comes in on it,
+	/// but it's disabled again afterwards so we don't receive messages from other
+	/// nodes that are headed to HQ.
+	pub fn send_message<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		message: Message,
+		node_addr: u16,
+		delay: &mut D,
+	) -> helper::SendResult<nrf24::Error<SPI::Error, CE::Error>> {
+		let (sending, after) = self.pipes.sending_to_hq();
+		self.set_rx_enabled_pipes(sending)?;
+		let result = helper::send_message_(self, message, node_addr, delay);
+		self.set_rx_enabled_pipes(after)?;
+		result
+	}
+}
+
+#[cfg(feature = "nrf24")]
+impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error>>
+	for SamnNrf24<SPI, CE>
+{
+	fn init<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		delay: &mut D,
+	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		self.radio.initialize(delay)?;
+		self.radio.configure()?;
+		// Every pipe set_rx_filter might enable can take payloads
+		self.radio.set_auto_ack_pipes(&[true; 6])?;
+		self.radio.set_rx_enabled_pipes(&NRF24_PIPES)?;
+		self.radio.set_dynamic_payload_pipes(&[true; 6])?;
+		self.pipes.reset();
+		// Keep power and data rate through resets
+		self.set_rf()?;
 		Ok(())
 	}
+
+	fn transmit_start<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		self.transmit(payload, nrf24::PayloadType::Payload, delay)
+	}
+	/// Sent as `NO_ACK`, receivers don't ack and we don't wait for one
+	fn transmit_start_no_ack<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		self.transmit(payload, nrf24::PayloadType::PayloadNoAck, delay)
+	}
 	fn transmit_poll(&mut self) -> nb::Result<bool, nrf24::Error<SPI::Error, CE::Error>> {
-		self.transmission_ended()
+		self.radio.transmission_ended()
 	}
 
+
 	/// Receive with irq should work well (fast) :)
 	///
 	/// Had to turn off irq, because pin would go low on first packet read
@@ -63,19 +203,7 @@ impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error
 		_: &mut P,
 		rx_addresses: Option<&[u16]>,
 	) -> nb::Result<Payload, nrf24::Error<SPI::Error, CE::Error>> {
-
-		if let Some((_, buf)) = self.receive_maybe()? {
-			let payload = Payload(buf);
-			// Discard payloads that aren't for this address
-			if let (Some(address), Some(addresses)) = (payload.address(), rx_addresses) {
-				if addresses.contains(&address) {
-					return nb::Result::Ok(payload);
-				}
-			} else {
-				return nb::Result::Ok(payload);
-			}
-		}
-		nb::Result::Err(nb::Error::WouldBlock)
+		self.receive_with_pipe(rx_addresses).map(|(_, payload)| payload)
 	}
 	fn set_rx_filter(
 		&mut self,
@@ -83,7 +211,7 @@ impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error
 	) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
 		for (i, pipe) in rx_pipes.iter().enumerate() {
 			if i <= 1 {
-				self.set_rx_addr(
+				self.radio.set_rx_addr(
 					i as u8,
 					&[
 						*pipe,
@@ -93,26 +221,83 @@ impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error
 						DEFAULT_PIPE,
 					],
 				)?;
+				// Pipe 0 is also where acks come in, it has to be set again before sending
+				if i == 0 {
+					self.pipes.rx_pipe_0_changed();
+				}
 			} else if i <= 5 {
-				self.set_rx_addr(i as u8, &[*pipe])?;
+				self.radio.set_rx_addr(i as u8, &[*pipe])?;
 			}
 		}
-		Ok(())
+		self.set_rx_enabled_pipes(self.pipes.rx_filter_enabled(rx_pipes.len()))
+	}
+	fn set_channel(&mut self, channel: u8) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
+		self.radio.set_frequency(channel)
+	}
+	/// Share of samples where the nrf24 saw a carrier over -64dBm (RPD)
+	fn energy_scan<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		channel: u8,
+		delay: &mut D,
+	) -> Result<u8, nrf24::Error<SPI::Error, CE::Error>> {
+		const SAMPLES: u32 = 32;
+		self.radio.set_frequency(channel)?;
+		self.radio.rx()?;
+		let mut busy = 0;
+		for _ in 0..SAMPLES {
+			// RPD needs 170us in rx to latch
+			delay.delay_us(250);
+			if self.radio.has_carrier()? {
+				busy += 1;
+			}
+		}
+		Ok((busy * 255 / SAMPLES) as u8)
+	}
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, nrf24::Error<SPI::Error, CE::Error>> {
+		let level = NRF24_TX_POWERS
+			.iter()
+			.rposition(|power| *power <= dbm)
+			.unwrap_or(0);
+		self.tx_power = level as u8;
+		self.set_rf()?;
+		Ok(NRF24_TX_POWERS[level])
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, nrf24::Error<SPI::Error, CE::Error>> {
+		let data_rate = NRF24_DATA_RATES
+			.into_iter()
+			.min_by_key(|rate| rate.abs_diff(bps))
+			.unwrap_or(1_000_000);
+		self.data_rate = data_rate;
+		self.set_rf()?;
+		Ok(data_rate)
+	}
+	fn hq_pipe(&self, node_addr: u16) -> u8 {
+		super::addr_to_nrf24_hq_pipe(node_addr)
+	}
+	fn capabilities(&self) -> super::Capabilities {
+		super::Capabilities {
+			tx_power_min: NRF24_TX_POWERS[0],
+			tx_power_max: NRF24_TX_POWERS[NRF24_TX_POWERS.len() - 1],
+			data_rate_min: NRF24_DATA_RATES[0],
+			data_rate_max: NRF24_DATA_RATES[NRF24_DATA_RATES.len() - 1],
+			channel_max: 125,
+			auto_ack: true,
+		}
 	}
 	fn to_tx(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-		self.tx()
+		self.radio.tx()
 	}
 	fn to_rx(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-		self.rx()
+		self.radio.rx()
 	}
 	fn to_idle(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-		self.idle()
+		self.radio.idle()
 	}
 	fn flush_rx(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-			self.flush_rx()
+		self.radio.flush_rx()
 	}
 	fn flush_tx(&mut self) -> Result<(), nrf24::Error<SPI::Error, CE::Error>> {
-			self.flush_tx()
+		self.radio.flush_tx()
 	}
 
 	// Async function on nrf24 go straight to normal functions since they're non-blocking.
@@ -130,6 +315,23 @@ impl<SPI: SpiDevice<u8>, CE: OutputPin> Radio<nrf24::Error<SPI::Error, CE::Error
 	}
 }
 
+/// dBm and the PATABLE value for it, at 915MHz
+#[cfg(feature = "cc1101")]
+const CC1101_TX_POWERS: [(i8, u8); 8] = [
+	(-30, 0x03),
+	(-20, 0x0E),
+	(-15, 0x1E),
+	(-10, 0x27),
+	(0, 0x8E),
+	(5, 0xCD),
+	(7, 0xC7),
+	(10, 0xC0),
+];
+#[cfg(feature = "cc1101")]
+const CC1101_DATA_RATE_MIN: u32 = 600;
+#[cfg(feature = "cc1101")]
+const CC1101_DATA_RATE_MAX: u32 = 500_000;
+
 #[cfg(feature = "cc1101")]
 impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> Radio<cc1101::Error<SpiE>> for Cc1101<SPI> {
 	fn init<D: embedded_hal::delay::DelayNs>(
@@ -167,7 +369,7 @@ impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> Radio<cc1101::Error<SpiE>> for Cc11
 			let payload = Payload(buf);
 			// Discard payloads that aren't for this address
 			if let (Some(address), Some(addresses)) = (payload.address(), rx_addresses) {
-				if addresses.contains(&address) {
+				if super::address_accepted(address, addresses) {
 					nb::Result::Ok(payload)
 				} else {
 					nb::Result::Err(nb::Error::WouldBlock)
@@ -184,6 +386,55 @@ impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> Radio<cc1101::Error<SpiE>> for Cc11
 		}
 		Ok(())
 	}
+	fn set_channel(&mut self, channel: u8) -> Result<(), cc1101::Error<SpiE>> {
+		self.set_channel(channel)
+	}
+	/// Average RSSI, -120dBm and below is 0, -20dBm and above is 255
+	fn energy_scan<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		channel: u8,
+		delay: &mut D,
+	) -> Result<u8, cc1101::Error<SpiE>> {
+		const SAMPLES: i32 = 8;
+		self.set_channel(channel)?;
+		self.to_rx()?;
+		let mut rssi = 0;
+		for _ in 0..SAMPLES {
+			delay.delay_us(500);
+			rssi += self.get_rssi_dbm()? as i32;
+		}
+		let rssi = rssi / SAMPLES;
+		Ok(((rssi.clamp(-120, -20) + 120) * 255 / 100) as u8)
+	}
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, cc1101::Error<SpiE>> {
+		let (power, patable) = CC1101_TX_POWERS
+			.iter()
+			.rev()
+			.find(|(power, _)| *power <= dbm)
+			.unwrap_or(&CC1101_TX_POWERS[0]);
+		self.write_patable(*patable)?;
+		Ok(*power)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, cc1101::Error<SpiE>> {
+		let bps = bps.clamp(CC1101_DATA_RATE_MIN, CC1101_DATA_RATE_MAX);
+		self.set_data_rate(bps as u64)?;
+		Ok(bps)
+	}
+	fn capabilities(&self) -> super::Capabilities {
+		super::Capabilities {
+			tx_power_min: CC1101_TX_POWERS[0].0,
+			tx_power_max: CC1101_TX_POWERS[CC1101_TX_POWERS.len() - 1].0,
+			data_rate_min: CC1101_DATA_RATE_MIN,
+			data_rate_max: CC1101_DATA_RATE_MAX,
+			channel_max: 255,
+			auto_ack: false,
+		}
+	}
+	/// The live RSSI register, the cc1101 doesn't latch one per packet the way we set it up
+	fn rssi(&mut self) -> Option<i8> {
+		let dbm = self.get_rssi_dbm().ok()?;
+		Some(dbm.clamp(i8::MIN as i16, i8::MAX as i16) as i8)
+	}
 	fn to_tx(&mut self) -> Result<(), cc1101::Error<SpiE>> {
 		self.to_tx()
 	}
@@ -214,3 +465,314 @@ impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> Radio<cc1101::Error<SpiE>> for Cc11
 		self.to_idle_async().await
 	}
 }
+
+/// Listen before talk settings for [`SamnCc1101`]
+#[cfg(feature = "cc1101")]
+#[derive(Clone, Copy, Debug)]
+pub struct Csma {
+	/// The channel's clear below this RSSI
+	pub threshold_dbm: i16,
+	/// Backoff unit, the backoff is a random number of slots
+	pub slot_us: u32,
+	/// The backoff window doubles on each busy check, up to `2^max_backoff_exponent` slots
+	pub max_backoff_exponent: u8,
+	/// Channel checks before giving up on the payload
+	pub max_attempts: u8,
+}
+
+#[cfg(feature = "cc1101")]
+impl Csma {
+	/// Checks the channel, backing off a random time while it's busy
+	///
+	/// A radio that can't tell the RSSI is always clear.
+	fn wait_for_clear_channel<R: Radio<E>, E, D: embedded_hal::delay::DelayNs>(
+		&self,
+		radio: &mut R,
+		rng: &mut crate::schedule::Rng,
+		stats: &mut CollisionStats,
+		delay: &mut D,
+	) -> Result<bool, E> {
+		// RSSI needs a bit in rx before it's valid
+		const RSSI_SETTLE_US: u32 = 500;
+		radio.to_rx()?;
+		for attempt in 0..self.max_attempts {
+			delay.delay_us(RSSI_SETTLE_US);
+			let Some(rssi) = radio.rssi() else {
+				return Ok(true);
+			};
+			if (rssi as i16) < self.threshold_dbm {
+				if attempt > 0 {
+					stats.deferred += 1;
+				}
+				return Ok(true);
+			}
+			stats.busy += 1;
+			// 2 slots after the first busy check, so there's a chance of not waiting at all
+			let exponent = (attempt as u32 + 1).min(self.max_backoff_exponent as u32);
+			let slots = 1u32 << exponent.min(31);
+			delay.delay_us(rng.below(slots).saturating_mul(self.slot_us));
+		}
+		Ok(false)
+	}
+}
+
+#[cfg(feature = "cc1101")]
+impl Default for Csma {
+	fn default() -> Self {
+		Self {
+			threshold_dbm: -90,
+			slot_us: 500,
+			max_backoff_exponent: 5,
+			max_attempts: 6,
+		}
+	}
+}
+
+/// What listen before talk has been up to, for diagnostics
+#[cfg(feature = "cc1101")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollisionStats {
+	/// Payloads that went out
+	pub sent: u32,
+	/// Sent, but only after backing off at least once
+	pub deferred: u32,
+	/// Channel checks that found it busy
+	pub busy: u32,
+	/// Payloads dropped because the channel never cleared
+	pub dropped: u32,
+}
+
+/// Our cc1101, optionally checking the channel is clear before every transmit
+///
+/// Without [`Csma`] it sends right away, like the plain `Cc1101`.
+/// A payload dropped on a busy channel shows up as not acked in [`Radio::transmit_poll`].
+#[cfg(feature = "cc1101")]
+pub struct SamnCc1101<SPI> {
+	radio: Cc1101<SPI>,
+	csma: Option<Csma>,
+	rng: crate::schedule::Rng,
+	stats: CollisionStats,
+	/// The last payload never went out
+	dropped: bool,
+}
+
+#[cfg(feature = "cc1101")]
+impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> SamnCc1101<SPI> {
+	pub fn new(radio: Cc1101<SPI>) -> Self {
+		Self {
+			radio,
+			csma: None,
+			rng: crate::schedule::Rng::new(0),
+			stats: CollisionStats::default(),
+			dropped: false,
+		}
+	}
+	/// Seed with something that differs between nodes, so they back off differently
+	pub fn enable_csma(&mut self, csma: Csma, seed: u32) {
+		self.csma = Some(csma);
+		self.rng = crate::schedule::Rng::new(seed);
+	}
+	pub fn disable_csma(&mut self) {
+		self.csma = None;
+	}
+	pub fn stats(&self) -> CollisionStats {
+		self.stats
+	}
+	pub fn reset_stats(&mut self) {
+		self.stats = CollisionStats::default();
+	}
+	pub fn inner(&mut self) -> &mut Cc1101<SPI> {
+		&mut self.radio
+	}
+	pub fn into_inner(self) -> Cc1101<SPI> {
+		self.radio
+	}
+}
+
+#[cfg(feature = "cc1101")]
+impl<SPI: SpiDevice<u8, Error = SpiE>, SpiE> Radio<cc1101::Error<SpiE>>
+	for SamnCc1101<SPI>
+{
+	fn init<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		delay: &mut D,
+	) -> Result<(), cc1101::Error<SpiE>> {
+		self.dropped = false;
+		Radio::init(&mut self.radio, delay)
+	}
+	fn transmit_start<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		payload: &Payload,
+		delay: &mut D,
+	) -> Result<(), cc1101::Error<SpiE>> {
+		if let Some(csma) = self.csma {
+			let clear = csma.wait_for_clear_channel(
+				&mut self.radio,
+				&mut self.rng,
+				&mut self.stats,
+				delay,
+			)?;
+			if !clear {
+				self.stats.dropped += 1;
+				self.dropped = true;
+				return Ok(());
+			}
+		}
+		self.dropped = false;
+		self.stats.sent += 1;
+		Radio::transmit_start(&mut self.radio, payload, delay)
+	}
+	/// `false` when the payload was dropped because the channel was busy
+	fn transmit_poll(&mut self) -> nb::Result<bool, cc1101::Error<SpiE>> {
+		if self.dropped {
+			self.dropped = false;
+			return Ok(false);
+		}
+		Radio::transmit_poll(&mut self.radio)
+	}
+	fn receive<P: embedded_hal::digital::InputPin>(
+		&mut self,
+		packet_ready_pin: &mut P,
+		rx_addresses: Option<&[u16]>,
+	) -> nb::Result<Payload, cc1101::Error<SpiE>> {
+		Radio::receive(&mut self.radio, packet_ready_pin, rx_addresses)
+	}
+	fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::set_rx_filter(&mut self.radio, rx_pipes)
+	}
+	fn set_channel(&mut self, channel: u8) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::set_channel(&mut self.radio, channel)
+	}
+	fn energy_scan<D: embedded_hal::delay::DelayNs>(
+		&mut self,
+		channel: u8,
+		delay: &mut D,
+	) -> Result<u8, cc1101::Error<SpiE>> {
+		Radio::energy_scan(&mut self.radio, channel, delay)
+	}
+	fn set_tx_power(&mut self, dbm: i8) -> Result<i8, cc1101::Error<SpiE>> {
+		Radio::set_tx_power(&mut self.radio, dbm)
+	}
+	fn set_data_rate(&mut self, bps: u32) -> Result<u32, cc1101::Error<SpiE>> {
+		Radio::set_data_rate(&mut self.radio, bps)
+	}
+	fn capabilities(&self) -> super::Capabilities {
+		Radio::capabilities(&self.radio)
+	}
+	fn hq_pipe(&self, node_addr: u16) -> u8 {
+		Radio::hq_pipe(&self.radio, node_addr)
+	}
+	fn rssi(&mut self) -> Option<i8> {
+		Radio::rssi(&mut self.radio)
+	}
+	fn to_tx(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_tx(&mut self.radio)
+	}
+	fn to_rx(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_rx(&mut self.radio)
+	}
+	fn to_idle(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_idle(&mut self.radio)
+	}
+	fn flush_rx(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::flush_rx(&mut self.radio)
+	}
+	fn flush_tx(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::flush_tx(&mut self.radio)
+	}
+
+	#[cfg(feature = "tokio")]
+	async fn to_tx_async(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_tx_async(&mut self.radio).await
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_rx_async(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_rx_async(&mut self.radio).await
+	}
+	#[cfg(feature = "tokio")]
+	async fn to_idle_async(&mut self) -> Result<(), cc1101::Error<SpiE>> {
+		Radio::to_idle_async(&mut self.radio).await
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+	use super::*;
+	use crate::radio::mock::MockRadio;
+	use crate::schedule::Rng;
+
+	/// Adds up how long it was asked to wait
+	#[derive(Default)]
+	struct Clock(u32);
+	impl embedded_hal::delay::DelayNs for Clock {
+		fn delay_ns(&mut self, ns: u32) {
+			self.0 += ns / 1000;
+		}
+	}
+
+	#[test]
+	fn csma_backs_off_while_busy() {
+		let csma = Csma::default();
+		let mut stats = CollisionStats::default();
+		let mut rng = Rng::new(1);
+
+		let mut radio = MockRadio {
+			rssi: [-60, -60, -100].into(),
+			..Default::default()
+		};
+		let mut clock = Clock::default();
+		let clear = csma.wait_for_clear_channel(&mut radio, &mut rng, &mut stats, &mut clock);
+		assert_eq!(clear, Ok(true));
+		assert_eq!(
+			stats,
+			CollisionStats {
+				deferred: 1,
+				busy: 2,
+				..Default::default()
+			}
+		);
+		// 3 settles, then backoffs out of 2 and 4 slots
+		assert!(clock.0 >= 3 * 500 && clock.0 <= 3 * 500 + (1 + 3) * csma.slot_us);
+
+		// Never clears, the last one sticks
+		let mut radio = MockRadio {
+			rssi: [-60].into(),
+			..Default::default()
+		};
+		let clear =
+			csma.wait_for_clear_channel(&mut radio, &mut rng, &mut stats, &mut Clock(0));
+		assert_eq!(clear, Ok(false));
+		assert_eq!(stats.busy, 2 + csma.max_attempts as u32);
+
+		// Can't listen, goes right away
+		let mut radio = MockRadio::default();
+		let clear =
+			csma.wait_for_clear_channel(&mut radio, &mut rng, &mut stats, &mut Clock(0));
+		assert_eq!(clear, Ok(true));
+	}
+
+	#[test]
+	fn csma_window_starts_at_2_slots() {
+		let csma = Csma {
+			max_attempts: 2,
+			..Default::default()
+		};
+		let mut waited = [false; 2];
+		let mut rng = Rng::new(7);
+		for _ in 0..64 {
+			let mut radio = MockRadio {
+				rssi: [-60, -100].into(),
+				..Default::default()
+			};
+			let mut clock = Clock::default();
+			let mut stats = CollisionStats::default();
+			let clear =
+				csma.wait_for_clear_channel(&mut radio, &mut rng, &mut stats, &mut clock);
+			assert_eq!(clear, Ok(true));
+			let slots = (clock.0 - 2 * 500) / csma.slot_us;
+			waited[slots as usize] = true;
+		}
+		// Sometimes no wait, sometimes a slot, never more
+		assert_eq!(waited, [true, true]);
+	}
+}
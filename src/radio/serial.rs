@@ -0,0 +1,949 @@
+//! Tunnels the [`Radio`] API over a serial link, for HQ radios sitting on a USB dongle
+//!
+//! Frames are `[seq, op, ...args, crc16 (le)]`, COBS encoded and terminated by a `0`.
+//! The host sends a [`Request`] and the dongle answers each one with exactly one [`Reply`],
+//! carrying the request's `seq` so the host can drop late replies to requests it gave up on.
+//! - Host side: [`SerialRadio`] (std), implements [`Radio`] over any `Read + Write`
+//! - Dongle side: [`Dongle`] (no_std), feed it bytes from the host and write back what it gives you
+use embedded_hal::{delay::DelayNs, digital::InputPin};
+use errors::Discriminant;
+
+use super::{Capabilities, Payload, Radio};
+
+/// Biggest decoded frame, `seq + op + payload + crc`
+const RAW_MAX: usize = 1 + 1 + 32 + 2;
+/// Biggest encoded frame, delimiter included
+pub const FRAME_MAX: usize = RAW_MAX + RAW_MAX / 254 + 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameError {
+	TooLong,
+	InvalidCobs,
+	InvalidCrc,
+	InvalidLength,
+	InvalidOp,
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+	let mut crc = 0xFFFFu16;
+	for byte in data {
+		crc ^= (*byte as u16) << 8;
+		for _ in 0..8 {
+			crc = if crc & 0x8000 != 0 {
+				(crc << 1) ^ 0x1021
+			} else {
+				crc << 1
+			};
+		}
+	}
+	crc
+}
+
+/// `out` needs `data.len() + data.len() / 254 + 1` bytes
+fn cobs_encode(data: &[u8], out: &mut [u8]) -> usize {
+	let mut code_at = 0;
+	let mut code = 1u8;
+	let mut o = 1;
+	for byte in data {
+		if *byte != 0 {
+			out[o] = *byte;
+			o += 1;
+			code += 1;
+		}
+		if *byte == 0 || code == 0xFF {
+			out[code_at] = code;
+			code_at = o;
+			o += 1;
+			code = 1;
+		}
+	}
+	out[code_at] = code;
+	o
+}
+
+fn cobs_decode(data: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+	let (mut i, mut o) = (0, 0);
+	while i < data.len() {
+		let code = data[i] as usize;
+		if code == 0 {
+			return Err(FrameError::InvalidCobs);
+		}
+		let run = data
+			.get(i + 1..i + code)
+			.filter(|run| !run.contains(&0))
+			.ok_or(FrameError::InvalidCobs)?;
+		out
+			.get_mut(o..o + run.len())
+			.ok_or(FrameError::TooLong)?
+			.copy_from_slice(run);
+		o += run.len();
+		i += code;
+		// A block that isn't full ends on a zero, except for the last one
+		if code != 0xFF && i < data.len() {
+			*out.get_mut(o).ok_or(FrameError::TooLong)? = 0;
+			o += 1;
+		}
+	}
+	Ok(o)
+}
+
+/// Adds `seq` and the crc and encodes `raw` into `out`, returns the length written
+fn encode_frame(seq: u8, raw: &[u8], out: &mut [u8; FRAME_MAX]) -> usize {
+	let mut buf = [0u8; RAW_MAX];
+	let len = raw.len() + 1;
+	buf[0] = seq;
+	buf[1..len].copy_from_slice(raw);
+	let crc = crc16(&buf[..len]);
+	buf[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+	let len = cobs_encode(&buf[..len + 2], out);
+	out[len] = 0;
+	len + 1
+}
+
+/// A decoded frame, crc checked and stripped
+pub struct Frame {
+	buf: [u8; FRAME_MAX],
+	len: usize,
+}
+
+impl Frame {
+	pub fn seq(&self) -> u8 {
+		self.buf[0]
+	}
+	/// Everything after the seq, starting with the op
+	pub fn data(&self) -> &[u8] {
+		&self.buf[1..self.len]
+	}
+}
+
+/// Splits a byte stream on delimiters and decodes the frames
+pub struct FrameReader {
+	buf: [u8; FRAME_MAX],
+	len: usize,
+	overflowed: bool,
+}
+
+impl Default for FrameReader {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FrameReader {
+	pub const fn new() -> Self {
+		Self {
+			buf: [0; FRAME_MAX],
+			len: 0,
+			overflowed: false,
+		}
+	}
+
+	/// Gives a frame back once `byte` is a delimiter
+	///
+	/// Empty frames are skipped, so sending a lone `0` resyncs the other side.
+	pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+		if byte != 0 {
+			if self.len < self.buf.len() {
+				self.buf[self.len] = byte;
+				self.len += 1;
+			} else {
+				self.overflowed = true;
+			}
+			return None;
+		}
+
+		let (len, overflowed) = (self.len, self.overflowed);
+		self.len = 0;
+		self.overflowed = false;
+		if len == 0 {
+			return None;
+		}
+		if overflowed {
+			return Some(Err(FrameError::TooLong));
+		}
+
+		let mut frame = Frame {
+			buf: [0; FRAME_MAX],
+			len: 0,
+		};
+		Some(
+			cobs_decode(&self.buf[..len], &mut frame.buf).and_then(|len| {
+				if len > RAW_MAX {
+					return Err(FrameError::TooLong);
+				}
+				// Need at least a seq, an op and the crc
+				if len < 4 {
+					return Err(FrameError::InvalidLength);
+				}
+				let crc = u16::from_le_bytes([frame.buf[len - 2], frame.buf[len - 1]]);
+				if crc != crc16(&frame.buf[..len - 2]) {
+					return Err(FrameError::InvalidCrc);
+				}
+				frame.len = len - 2;
+				Ok(frame)
+			}),
+		)
+	}
+}
+
+fn read_payload(data: &[u8]) -> Result<Payload, FrameError> {
+	let buf: [u8; 32] = data.try_into().map_err(|_| FrameError::InvalidLength)?;
+	Ok(Payload(buf))
+}
+
+fn read_u32(data: &[u8]) -> Result<u32, FrameError> {
+	let bytes = data.get(..4).ok_or(FrameError::InvalidLength)?;
+	Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Host to dongle, one per [`Radio`] method
+pub enum Request {
+	Init,
+	TransmitStart(Payload),
+	TransmitPoll,
+	Receive,
+	SetRxFilter { pipes: [u8; 6], len: u8 },
+	ToRx,
+	ToTx,
+	ToIdle,
+	FlushRx,
+	FlushTx,
+	SetChannel(u8),
+	EnergyScan(u8),
+	SetTxPower(i8),
+	SetDataRate(u32),
+	Capabilities,
+	TransmitStartNoAck(Payload),
+}
+
+impl Request {
+	pub fn encode(&self, seq: u8, out: &mut [u8; FRAME_MAX]) -> usize {
+		let mut raw = [0u8; RAW_MAX];
+		let len = match self {
+			Self::Init => 1,
+			Self::TransmitStart(payload) | Self::TransmitStartNoAck(payload) => {
+				raw[1..33].copy_from_slice(&payload.0);
+				33
+			}
+			Self::TransmitPoll => 1,
+			Self::Receive => 1,
+			Self::SetRxFilter { pipes, len } => {
+				raw[1] = *len;
+				raw[2..8].copy_from_slice(pipes);
+				8
+			}
+			Self::ToRx | Self::ToTx | Self::ToIdle | Self::FlushRx | Self::FlushTx => 1,
+			Self::SetChannel(channel) | Self::EnergyScan(channel) => {
+				raw[1] = *channel;
+				2
+			}
+			Self::SetTxPower(dbm) => {
+				raw[1] = *dbm as u8;
+				2
+			}
+			Self::SetDataRate(bps) => {
+				raw[1..5].copy_from_slice(&bps.to_le_bytes());
+				5
+			}
+			Self::Capabilities => 1,
+		};
+		raw[0] = self.op();
+		encode_frame(seq, &raw[..len], out)
+	}
+
+	pub fn decode(frame: &Frame) -> Result<Self, FrameError> {
+		let data = frame.data();
+		let args = &data[1..];
+		let request = match data[0] {
+			0 => Self::Init,
+			1 => Self::TransmitStart(read_payload(args)?),
+			2 => Self::TransmitPoll,
+			3 => Self::Receive,
+			4 => {
+				let (len, pipes) = args.split_first().ok_or(FrameError::InvalidLength)?;
+				if *len > 6 {
+					return Err(FrameError::InvalidLength);
+				}
+				Self::SetRxFilter {
+					pipes: pipes.try_into().map_err(|_| FrameError::InvalidLength)?,
+					len: *len,
+				}
+			}
+			5 => Self::ToRx,
+			6 => Self::ToTx,
+			7 => Self::ToIdle,
+			8 => Self::FlushRx,
+			9 => Self::FlushTx,
+			10 => Self::SetChannel(*args.first().ok_or(FrameError::InvalidLength)?),
+			11 => Self::EnergyScan(*args.first().ok_or(FrameError::InvalidLength)?),
+			12 => Self::SetTxPower(*args.first().ok_or(FrameError::InvalidLength)? as i8),
+			13 => Self::SetDataRate(read_u32(args)?),
+			14 => Self::Capabilities,
+			15 => Self::TransmitStartNoAck(read_payload(args)?),
+			_ => return Err(FrameError::InvalidOp),
+		};
+		Ok(request)
+	}
+
+	fn op(&self) -> u8 {
+		match self {
+			Self::Init => 0,
+			Self::TransmitStart(_) => 1,
+			Self::TransmitPoll => 2,
+			Self::Receive => 3,
+			Self::SetRxFilter { .. } => 4,
+			Self::ToRx => 5,
+			Self::ToTx => 6,
+			Self::ToIdle => 7,
+			Self::FlushRx => 8,
+			Self::FlushTx => 9,
+			Self::SetChannel(_) => 10,
+			Self::EnergyScan(_) => 11,
+			Self::SetTxPower(_) => 12,
+			Self::SetDataRate(_) => 13,
+			Self::Capabilities => 14,
+			Self::TransmitStartNoAck(_) => 15,
+		}
+	}
+}
+
+/// Dongle to host
+pub enum Reply {
+	Ok,
+	/// Answer to [`Request::TransmitPoll`]
+	Transmitted(bool),
+	/// Answer to [`Request::TransmitPoll`] and [`Request::Receive`]
+	WouldBlock,
+	Received(Payload),
+	/// The dongle radio's error [`Discriminant`]
+	RadioError(u8),
+	/// The dongle couldn't decode the request
+	BadRequest(FrameError),
+	/// Answer to [`Request::EnergyScan`]
+	Energy(u8),
+	/// Answer to [`Request::SetTxPower`], the power that was set
+	TxPower(i8),
+	/// Answer to [`Request::SetDataRate`], the rate that was set
+	DataRate(u32),
+	Capabilities(Capabilities),
+}
+
+impl Reply {
+	pub fn encode(&self, seq: u8, out: &mut [u8; FRAME_MAX]) -> usize {
+		let mut raw = [0u8; RAW_MAX];
+		let len = match self {
+			Self::Ok => 1,
+			Self::Transmitted(success) => {
+				raw[1] = *success as u8;
+				2
+			}
+			Self::WouldBlock => 1,
+			Self::Received(payload) => {
+				raw[1..33].copy_from_slice(&payload.0);
+				33
+			}
+			Self::RadioError(discriminant) => {
+				raw[1] = *discriminant;
+				2
+			}
+			Self::BadRequest(err) => {
+				raw[1] = *err as u8;
+				2
+			}
+			Self::Energy(energy) => {
+				raw[1] = *energy;
+				2
+			}
+			Self::TxPower(dbm) => {
+				raw[1] = *dbm as u8;
+				2
+			}
+			Self::DataRate(bps) => {
+				raw[1..5].copy_from_slice(&bps.to_le_bytes());
+				5
+			}
+			Self::Capabilities(capabilities) => {
+				raw[1] = capabilities.tx_power_min as u8;
+				raw[2] = capabilities.tx_power_max as u8;
+				raw[3..7].copy_from_slice(&capabilities.data_rate_min.to_le_bytes());
+				raw[7..11].copy_from_slice(&capabilities.data_rate_max.to_le_bytes());
+				raw[11] = capabilities.channel_max;
+				raw[12] = capabilities.auto_ack as u8;
+				13
+			}
+		};
+		raw[0] = self.op();
+		encode_frame(seq, &raw[..len], out)
+	}
+
+	pub fn decode(frame: &Frame) -> Result<Self, FrameError> {
+		let data = frame.data();
+		let arg = || data.get(1).copied().ok_or(FrameError::InvalidLength);
+		let reply = match data[0] {
+			0 => Self::Ok,
+			1 => Self::Transmitted(arg()? != 0),
+			2 => Self::WouldBlock,
+			3 => Self::Received(read_payload(&data[1..])?),
+			4 => Self::RadioError(arg()?),
+			5 => Self::BadRequest(match arg()? {
+				0 => FrameError::TooLong,
+				1 => FrameError::InvalidCobs,
+				2 => FrameError::InvalidCrc,
+				3 => FrameError::InvalidLength,
+				_ => FrameError::InvalidOp,
+			}),
+			6 => Self::Energy(arg()?),
+			7 => Self::TxPower(arg()? as i8),
+			8 => Self::DataRate(read_u32(&data[1..])?),
+			9 => {
+				let args = data.get(1..13).ok_or(FrameError::InvalidLength)?;
+				Self::Capabilities(Capabilities {
+					tx_power_min: args[0] as i8,
+					tx_power_max: args[1] as i8,
+					data_rate_min: read_u32(&args[2..6])?,
+					data_rate_max: read_u32(&args[6..10])?,
+					channel_max: args[10],
+					auto_ack: args[11] != 0,
+				})
+			}
+			_ => return Err(FrameError::InvalidOp),
+		};
+		Ok(reply)
+	}
+
+	fn op(&self) -> u8 {
+		match self {
+			Self::Ok => 0,
+			Self::Transmitted(_) => 1,
+			Self::WouldBlock => 2,
+			Self::Received(_) => 3,
+			Self::RadioError(_) => 4,
+			Self::BadRequest(_) => 5,
+			Self::Energy(_) => 6,
+			Self::TxPower(_) => 7,
+			Self::DataRate(_) => 8,
+			Self::Capabilities(_) => 9,
+		}
+	}
+}
+
+/// Runs a request on the dongle's radio
+pub fn handle_request<E: Discriminant, R: Radio<E>, P: InputPin, D: DelayNs>(
+	radio: &mut R,
+	irq: &mut P,
+	delay: &mut D,
+	request: Request,
+) -> Reply {
+	let done = |result: Result<(), E>| match result {
+		Ok(()) => Reply::Ok,
+		Err(err) => Reply::RadioError(err.discriminant()),
+	};
+	match request {
+		Request::Init => done(radio.init(delay)),
+		Request::TransmitStart(payload) => done(radio.transmit_start(&payload, delay)),
+		Request::TransmitStartNoAck(payload) => {
+			done(radio.transmit_start_no_ack(&payload, delay))
+		}
+		Request::TransmitPoll => match radio.transmit_poll() {
+			Ok(success) => Reply::Transmitted(success),
+			Err(nb::Error::WouldBlock) => Reply::WouldBlock,
+			Err(nb::Error::Other(err)) => Reply::RadioError(err.discriminant()),
+		},
+		// Address filtering is left to the host
+		Request::Receive => match radio.receive(irq, None) {
+			Ok(payload) => Reply::Received(payload),
+			Err(nb::Error::WouldBlock) => Reply::WouldBlock,
+			Err(nb::Error::Other(err)) => Reply::RadioError(err.discriminant()),
+		},
+		Request::SetRxFilter { pipes, len } => {
+			done(radio.set_rx_filter(&pipes[..len as usize]))
+		}
+		Request::ToRx => done(radio.to_rx()),
+		Request::ToTx => done(radio.to_tx()),
+		Request::ToIdle => done(radio.to_idle()),
+		Request::FlushRx => done(radio.flush_rx()),
+		Request::FlushTx => done(radio.flush_tx()),
+		Request::SetChannel(channel) => done(radio.set_channel(channel)),
+		Request::EnergyScan(channel) => match radio.energy_scan(channel, delay) {
+			Ok(energy) => Reply::Energy(energy),
+			Err(err) => Reply::RadioError(err.discriminant()),
+		},
+		Request::SetTxPower(dbm) => match radio.set_tx_power(dbm) {
+			Ok(dbm) => Reply::TxPower(dbm),
+			Err(err) => Reply::RadioError(err.discriminant()),
+		},
+		Request::SetDataRate(bps) => match radio.set_data_rate(bps) {
+			Ok(bps) => Reply::DataRate(bps),
+			Err(err) => Reply::RadioError(err.discriminant()),
+		},
+		Request::Capabilities => Reply::Capabilities(radio.capabilities()),
+	}
+}
+
+/// Dongle side of the link
+///
+/// ```ignore
+/// let mut dongle = Dongle::new();
+/// let mut reply = [0u8; FRAME_MAX];
+/// loop {
+///   let byte = usb_serial.read_byte();
+///   if let Some(len) = dongle.push(byte, &mut radio, &mut irq, &mut delay, &mut reply) {
+///     usb_serial.write_all(&reply[..len]);
+///   }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Dongle {
+	reader: FrameReader,
+}
+
+impl Dongle {
+	pub const fn new() -> Self {
+		Self {
+			reader: FrameReader::new(),
+		}
+	}
+
+	/// Feeds a byte from the host, once a request is complete it's run on `radio`
+	/// and the encoded reply's length in `out` is returned
+	pub fn push<E: Discriminant, R: Radio<E>, P: InputPin, D: DelayNs>(
+		&mut self,
+		byte: u8,
+		radio: &mut R,
+		irq: &mut P,
+		delay: &mut D,
+		out: &mut [u8; FRAME_MAX],
+	) -> Option<usize> {
+		let (seq, reply) = match self.reader.push(byte)? {
+			Ok(frame) => match Request::decode(&frame) {
+				Ok(request) => (frame.seq(), handle_request(radio, irq, delay, request)),
+				Err(err) => (frame.seq(), Reply::BadRequest(err)),
+			},
+			// No seq to answer with, the host takes any BadRequest as its own
+			Err(err) => (0, Reply::BadRequest(err)),
+		};
+		Some(reply.encode(seq, out))
+	}
+}
+
+#[cfg(feature = "std")]
+pub use host::*;
+
+#[cfg(feature = "std")]
+mod host {
+	use std::io::{Read, Write};
+
+	use super::*;
+	use crate::radio::address_accepted;
+
+	#[derive(Debug)]
+	pub enum SerialError {
+		Io(std::io::Error),
+		/// We couldn't decode the dongle's reply
+		Frame(FrameError),
+		/// The dongle couldn't decode our request
+		BadRequest(FrameError),
+		/// The dongle radio's error [`Discriminant`]
+		Radio(u8),
+		/// The dongle answered with a reply that doesn't go with the request
+		UnexpectedReply,
+	}
+
+	impl From<std::io::Error> for SerialError {
+		fn from(value: std::io::Error) -> Self {
+			Self::Io(value)
+		}
+	}
+	impl From<FrameError> for SerialError {
+		fn from(value: FrameError) -> Self {
+			Self::Frame(value)
+		}
+	}
+
+	/// Host side of the link, a [`Radio`] that forwards everything to the dongle
+	///
+	/// Reads are blocking, set a timeout on the port so a dongle that went away
+	/// shows up as [`SerialError::Io`] instead of hanging.
+	pub struct SerialRadio<T> {
+		port: T,
+		reader: FrameReader,
+		/// Of the last request, replies with another one are stale
+		seq: u8,
+		/// Asked for on init, [`Radio::capabilities`] can't do a round trip
+		capabilities: Capabilities,
+	}
+
+	impl<T: Read + Write> SerialRadio<T> {
+		pub fn new(port: T) -> Self {
+			Self {
+				port,
+				reader: FrameReader::new(),
+				seq: 0,
+				capabilities: Capabilities::default(),
+			}
+		}
+		pub fn into_inner(self) -> T {
+			self.port
+		}
+
+		pub fn request(&mut self, request: &Request) -> Result<Reply, SerialError> {
+			self.seq = self.seq.wrapping_add(1);
+			let mut frame = [0u8; FRAME_MAX];
+			let len = request.encode(self.seq, &mut frame);
+			self.port.write_all(&frame[..len])?;
+			self.port.flush()?;
+
+			let mut byte = [0u8];
+			loop {
+				self.port.read_exact(&mut byte)?;
+				let Some(frame) = self.reader.push(byte[0]) else {
+					continue;
+				};
+				let frame = frame?;
+				match Reply::decode(&frame)? {
+					// The dongle might not have been able to read our seq
+					Reply::BadRequest(err) => return Err(SerialError::BadRequest(err)),
+					// Late answer to a request that timed out
+					_ if frame.seq() != self.seq => {}
+					Reply::RadioError(err) => return Err(SerialError::Radio(err)),
+					reply => return Ok(reply),
+				}
+			}
+		}
+
+		fn request_ok(&mut self, request: &Request) -> Result<(), SerialError> {
+			match self.request(request)? {
+				Reply::Ok => Ok(()),
+				_ => Err(SerialError::UnexpectedReply),
+			}
+		}
+	}
+
+	impl<T: Read + Write> Radio<SerialError> for SerialRadio<T> {
+		/// The dongle does its own delays
+		///
+		/// Dongles from before [`Request::Capabilities`] don't know it,
+		/// capabilities stay at the default with those.
+		fn init<D: DelayNs>(&mut self, _: &mut D) -> Result<(), SerialError> {
+			self.request_ok(&Request::Init)?;
+			self.capabilities = match self.request(&Request::Capabilities) {
+				Ok(Reply::Capabilities(capabilities)) => capabilities,
+				Err(SerialError::BadRequest(FrameError::InvalidOp)) => Capabilities::default(),
+				Ok(_) => return Err(SerialError::UnexpectedReply),
+				Err(err) => return Err(err),
+			};
+			Ok(())
+		}
+		fn transmit_start<D: DelayNs>(
+			&mut self,
+			payload: &Payload,
+			_: &mut D,
+		) -> Result<(), SerialError> {
+			self.request_ok(&Request::TransmitStart(Payload(payload.0)))
+		}
+		fn transmit_start_no_ack<D: DelayNs>(
+			&mut self,
+			payload: &Payload,
+			_: &mut D,
+		) -> Result<(), SerialError> {
+			self.request_ok(&Request::TransmitStartNoAck(Payload(payload.0)))
+		}
+		fn transmit_poll(&mut self) -> nb::Result<bool, SerialError> {
+			match self.request(&Request::TransmitPoll)? {
+				Reply::Transmitted(success) => Ok(success),
+				Reply::WouldBlock => Err(nb::Error::WouldBlock),
+				_ => Err(nb::Error::Other(SerialError::UnexpectedReply)),
+			}
+		}
+		/// `packet_ready_pin` isn't used, the dongle checks its own
+		fn receive<P: InputPin>(
+			&mut self,
+			_: &mut P,
+			rx_addresses: Option<&[u16]>,
+		) -> nb::Result<Payload, SerialError> {
+			match self.request(&Request::Receive)? {
+				Reply::Received(payload) => {
+					// Discard payloads that aren't for this address
+					match (payload.address(), rx_addresses) {
+						(Some(address), Some(addresses))
+							if !address_accepted(address, addresses) =>
+						{
+							Err(nb::Error::WouldBlock)
+						}
+						_ => Ok(payload),
+					}
+				}
+				Reply::WouldBlock => Err(nb::Error::WouldBlock),
+				_ => Err(nb::Error::Other(SerialError::UnexpectedReply)),
+			}
+		}
+		/// Only the first 6 pipes are sent, that's all any of our radios can filter on
+		fn set_rx_filter(&mut self, rx_pipes: &[u8]) -> Result<(), SerialError> {
+			let mut pipes = [0u8; 6];
+			let len = rx_pipes.len().min(pipes.len());
+			pipes[..len].copy_from_slice(&rx_pipes[..len]);
+			self.request_ok(&Request::SetRxFilter {
+				pipes,
+				len: len as u8,
+			})
+		}
+		fn set_channel(&mut self, channel: u8) -> Result<(), SerialError> {
+			self.request_ok(&Request::SetChannel(channel))
+		}
+		/// The dongle does the scan with its own delays
+		fn energy_scan<D: DelayNs>(
+			&mut self,
+			channel: u8,
+			_: &mut D,
+		) -> Result<u8, SerialError> {
+			match self.request(&Request::EnergyScan(channel))? {
+				Reply::Energy(energy) => Ok(energy),
+				_ => Err(SerialError::UnexpectedReply),
+			}
+		}
+		fn set_tx_power(&mut self, dbm: i8) -> Result<i8, SerialError> {
+			match self.request(&Request::SetTxPower(dbm))? {
+				Reply::TxPower(dbm) => Ok(dbm),
+				_ => Err(SerialError::UnexpectedReply),
+			}
+		}
+		fn set_data_rate(&mut self, bps: u32) -> Result<u32, SerialError> {
+			match self.request(&Request::SetDataRate(bps))? {
+				Reply::DataRate(bps) => Ok(bps),
+				_ => Err(SerialError::UnexpectedReply),
+			}
+		}
+		/// Zeroed until [`Radio::init`]
+		fn capabilities(&self) -> Capabilities {
+			self.capabilities
+		}
+		fn to_rx(&mut self) -> Result<(), SerialError> {
+			self.request_ok(&Request::ToRx)
+		}
+		fn to_tx(&mut self) -> Result<(), SerialError> {
+			self.request_ok(&Request::ToTx)
+		}
+		fn to_idle(&mut self) -> Result<(), SerialError> {
+			self.request_ok(&Request::ToIdle)
+		}
+		fn flush_rx(&mut self) -> Result<(), SerialError> {
+			self.request_ok(&Request::FlushRx)
+		}
+		fn flush_tx(&mut self) -> Result<(), SerialError> {
+			self.request_ok(&Request::FlushTx)
+		}
+
+		// Requests are quick round trips, async goes straight to the blocking ones
+		#[cfg(feature = "tokio")]
+		async fn to_rx_async(&mut self) -> Result<(), SerialError> {
+			self.to_rx()
+		}
+		#[cfg(feature = "tokio")]
+		async fn to_idle_async(&mut self) -> Result<(), SerialError> {
+			self.to_idle()
+		}
+		#[cfg(feature = "tokio")]
+		async fn to_tx_async(&mut self) -> Result<(), SerialError> {
+			self.to_tx()
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+	use std::collections::VecDeque;
+	use std::io::{Read, Write};
+
+	use super::*;
+	use crate::radio::mock::{MockRadio, NoDelay};
+	use crate::radio::NoIrq;
+
+	#[test]
+	fn cobs_and_crc() {
+		assert_eq!(crc16(b"123456789"), 0x29B1);
+
+		let cases: [&[u8]; 4] = [&[0], &[0, 0], &[1, 0, 2, 0], &[0x11; 300]];
+		for data in cases {
+			let mut encoded = [0u8; 310];
+			let len = cobs_encode(data, &mut encoded);
+			assert!(!encoded[..len].contains(&0));
+			let mut decoded = [0u8; 310];
+			let decoded_len = cobs_decode(&encoded[..len], &mut decoded).unwrap();
+			assert_eq!(&decoded[..decoded_len], data);
+		}
+		assert_eq!(
+			cobs_decode(&[5, 1, 2], &mut [0u8; 8]),
+			Err(FrameError::InvalidCobs)
+		);
+	}
+
+	#[test]
+	fn frame_reader_rejects_corruption() {
+		let mut frame = [0u8; FRAME_MAX];
+		let len = Request::TransmitStart(Payload::new_with_addr(&[0, 1, 0], 0x1234, 7))
+			.encode(42, &mut frame);
+		assert_eq!(frame[len - 1], 0);
+
+		let mut reader = FrameReader::new();
+		let decoded = frame[..len]
+			.iter()
+			.find_map(|b| reader.push(*b))
+			.unwrap()
+			.unwrap();
+		assert_eq!(decoded.seq(), 42);
+		let Request::TransmitStart(payload) = Request::decode(&decoded).unwrap() else {
+			panic!("wrong request");
+		};
+		assert_eq!(payload.data(), [0, 1, 0]);
+		assert_eq!(payload.address(), Some(0x1234));
+
+		frame[3] ^= 0x40;
+		let decoded = frame[..len].iter().find_map(|b| reader.push(*b)).unwrap();
+		assert!(matches!(
+			decoded,
+			Err(FrameError::InvalidCrc | FrameError::InvalidCobs)
+		));
+		// Garbage without a delimiter doesn't stop the next frame from decoding
+		for _ in 0..100 {
+			reader.push(0x55);
+		}
+		assert_eq!(
+			reader.push(0).map(|f| f.err()),
+			Some(Some(FrameError::TooLong))
+		);
+		let len = Request::ToRx.encode(0, &mut frame);
+		let decoded = frame[..len].iter().find_map(|b| reader.push(*b)).unwrap();
+		assert!(matches!(
+			Request::decode(&decoded.unwrap()),
+			Ok(Request::ToRx)
+		));
+	}
+
+	/// A serial port with the dongle on the other end
+	struct Wire {
+		dongle: Dongle,
+		radio: MockRadio,
+		to_host: VecDeque<u8>,
+	}
+	impl Default for Wire {
+		/// The radio loops transmitted payloads back and fails on flush_tx
+		fn default() -> Self {
+			Wire {
+				dongle: Dongle::default(),
+				radio: MockRadio {
+					loopback: true,
+					energy: 45,
+					fail_flush_tx: true,
+					..Default::default()
+				},
+				to_host: VecDeque::new(),
+			}
+		}
+	}
+	impl Write for Wire {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			let mut reply = [0u8; FRAME_MAX];
+			for byte in buf {
+				if let Some(len) =
+					self
+						.dongle
+						.push(*byte, &mut self.radio, &mut NoIrq, &mut NoDelay, &mut reply)
+				{
+					self.to_host.extend(&reply[..len]);
+				}
+			}
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+	impl Read for Wire {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			self.to_host.read(buf)
+		}
+	}
+
+	#[test]
+	fn host_through_dongle() {
+		let mut radio = SerialRadio::new(Wire::default());
+		radio.init(&mut NoDelay).unwrap();
+		radio.set_rx_filter(&[1, 2]).unwrap();
+
+		assert!(matches!(
+			radio.receive(&mut NoIrq, None),
+			Err(nb::Error::WouldBlock)
+		));
+		let payload = Payload::new_with_addr(&[4, 5, 6], 0x0102, 9);
+		radio.transmit_start(&payload, &mut NoDelay).unwrap();
+		assert!(matches!(radio.transmit_poll(), Ok(true)));
+		// Not for us, dropped on the host
+		assert!(matches!(
+			radio.receive(&mut NoIrq, Some(&[0x0103])),
+			Err(nb::Error::WouldBlock)
+		));
+		radio.transmit_start(&payload, &mut NoDelay).unwrap();
+		let received = radio.receive(&mut NoIrq, Some(&[0x0102])).unwrap();
+		assert_eq!(received.data(), [4, 5, 6]);
+		assert_eq!(received.pipe(), 9);
+		radio.transmit_start_no_ack(&payload, &mut NoDelay).unwrap();
+		let received = radio.receive(&mut NoIrq, None).unwrap();
+		assert_eq!(received.data(), [4, 5, 6]);
+
+		assert!(matches!(radio.flush_tx(), Err(SerialError::Radio(0))));
+		radio.set_channel(76).unwrap();
+		assert_eq!(radio.energy_scan(90, &mut NoDelay).unwrap(), 45);
+		assert_eq!(radio.set_tx_power(-7).unwrap(), -7);
+		assert_eq!(radio.set_data_rate(250_000).unwrap(), 250_000);
+		assert_eq!(radio.capabilities().tx_power_min, -30);
+		assert_eq!(radio.capabilities().data_rate_max, 2_000_000);
+		let dongle = radio.into_inner();
+		assert_eq!(dongle.radio.rx_filter, [1, 2]);
+		assert_eq!(dongle.radio.channel, 90);
+	}
+
+	/// Answers with whatever's queued, whatever's asked
+	struct Canned(VecDeque<u8>);
+	impl Write for Canned {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+	impl Read for Canned {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			self.0.read(buf)
+		}
+	}
+
+	#[test]
+	fn dongle_without_capabilities() {
+		let mut replies = VecDeque::new();
+		let mut frame = [0u8; FRAME_MAX];
+		for (seq, reply) in [(1, Reply::Ok), (2, Reply::BadRequest(FrameError::InvalidOp))] {
+			let len = reply.encode(seq, &mut frame);
+			replies.extend(&frame[..len]);
+		}
+		let mut radio = SerialRadio::new(Canned(replies));
+		radio.init(&mut NoDelay).unwrap();
+		assert_eq!(radio.capabilities(), Capabilities::default());
+	}
+
+	#[test]
+	fn stale_replies_are_dropped() {
+		let mut wire = Wire::default();
+		// Answer to a request from before, that timed out
+		let mut frame = [0u8; FRAME_MAX];
+		let len = Reply::TxPower(5).encode(0, &mut frame);
+		wire.to_host.extend(&frame[..len]);
+
+		let mut radio = SerialRadio::new(wire);
+		assert_eq!(radio.set_tx_power(-7).unwrap(), -7);
+		assert_eq!(radio.set_tx_power(-20).unwrap(), -20);
+		assert!(radio.into_inner().to_host.is_empty());
+	}
+}
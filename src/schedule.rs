@@ -0,0 +1,123 @@
+//! Spreading node transmissions out in time
+//!
+//! Nodes that boot together and report on the same interval would otherwise keep
+//! transmitting at the same moment and keep colliding. [`ReportTimer`] adds jitter to
+//! every interval, [`Rng`] is the small random source for it (and for radio backoff).
+
+/// xorshift32, plenty for jitter, not for anything secret
+///
+/// Seed it with something that differs between nodes, like the node id.
+#[derive(Clone, Debug)]
+pub struct Rng(u32);
+
+impl Rng {
+	pub const fn new(seed: u32) -> Self {
+		// 0 would stay 0 forever
+		Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+	}
+	pub fn next_u32(&mut self) -> u32 {
+		let mut x = self.0;
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		self.0 = x;
+		x
+	}
+	/// A number in `0..n`, 0 when `n` is 0
+	pub fn below(&mut self, n: u32) -> u32 {
+		if n == 0 {
+			return 0;
+		}
+		self.next_u32() % n
+	}
+}
+
+/// When to send the next report, every `interval` give or take `jitter_percent`
+///
+/// Times are local milliseconds that are allowed to wrap around.
+#[derive(Clone, Debug)]
+pub struct ReportTimer {
+	interval_ms: u32,
+	jitter_percent: u8,
+	next_ms: u32,
+}
+
+impl ReportTimer {
+	/// The first report is anywhere in the first interval, so nodes that start
+	/// at the same time don't report together
+	pub fn new(interval_s: u16, jitter_percent: u8, now_ms: u32, rng: &mut Rng) -> Self {
+		let interval_ms = interval_s as u32 * 1000;
+		Self {
+			interval_ms,
+			jitter_percent: jitter_percent.min(100),
+			next_ms: now_ms.wrapping_add(rng.below(interval_ms)),
+		}
+	}
+
+	pub fn next_ms(&self) -> u32 {
+		self.next_ms
+	}
+
+	/// The report interval changed (like with [`Command::SetLimbType`](crate::node::Command::SetLimbType)),
+	/// reschedules from now
+	pub fn set_interval(&mut self, interval_s: u16, now_ms: u32, rng: &mut Rng) {
+		self.interval_ms = interval_s as u32 * 1000;
+		self.next_ms = now_ms.wrapping_add(self.jittered(rng));
+	}
+
+	fn jittered(&self, rng: &mut Rng) -> u32 {
+		let jitter = (self.interval_ms as u64 * self.jitter_percent as u64 / 100) as u32;
+		let offset = rng.below(jitter.saturating_mul(2).saturating_add(1));
+		(self.interval_ms - jitter).saturating_add(offset)
+	}
+
+	/// `true` when it's time to report, the next one is scheduled right away
+	///
+	/// Scheduled from the last due time so reports don't drift later,
+	/// unless we're more than an interval late.
+	pub fn poll(&mut self, now_ms: u32, rng: &mut Rng) -> bool {
+		let late = now_ms.wrapping_sub(self.next_ms);
+		if (late as i32) < 0 {
+			return false;
+		}
+		let from = if late > self.interval_ms {
+			now_ms
+		} else {
+			self.next_ms
+		};
+		self.next_ms = from.wrapping_add(self.jittered(rng));
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn jittered_reports() {
+		let mut rng = Rng::new(7);
+		let mut timer = ReportTimer::new(60, 10, 0, &mut rng);
+		let first = timer.next_ms();
+		assert!(first < 60_000);
+		assert!(!timer.poll(first.wrapping_sub(1), &mut rng));
+		assert!(timer.poll(first, &mut rng));
+		assert!(!timer.poll(first, &mut rng));
+		let gap = timer.next_ms() - first;
+		assert!((54_000..=66_000).contains(&gap), "{gap}");
+
+		// Two nodes booting together end up apart
+		let mut other = ReportTimer::new(60, 10, 0, &mut Rng::new(8));
+		assert_ne!(other.next_ms(), first);
+
+		// Wraps around
+		let mut timer = ReportTimer::new(1, 0, u32::MAX - 100, &mut rng);
+		let due = timer.next_ms();
+		assert!(timer.poll(due, &mut rng));
+		assert_eq!(timer.next_ms(), due.wrapping_add(1000));
+
+		// Way late, rescheduled from now
+		assert!(other.poll(1_000_000, &mut rng));
+		assert!(other.next_ms() >= 1_054_000);
+	}
+}
@@ -0,0 +1,288 @@
+use std::io::Write;
+use std::ops::Range;
+
+use sonnerie::{DatabaseReader, Record, Wildcard};
+
+use super::*;
+use crate::node::{Actuator, Limb, LimbType, NodeInfo, ResetReason, Sensor, Timestamp};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+	/// `time,key,field,value,unit`, one line per field
+	Csv,
+	/// `{"time", "key", "fields": [{"field", "value", "unit"}]}`, one line per record
+	Ndjson,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+	Number(f64),
+	Text(String),
+}
+
+/// A decoded value of a record
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+	pub name: &'static str,
+	pub value: FieldValue,
+	pub unit: Option<&'static str>,
+}
+
+fn number(
+	name: &'static str,
+	value: impl Into<f64>,
+	unit: Option<&'static str>,
+) -> Field {
+	Field {
+		name,
+		value: FieldValue::Number(value.into()),
+		unit,
+	}
+}
+
+fn sensor_fields(sensor: &Sensor) -> Vec<Field> {
+	match sensor {
+		Sensor::Battery(level) => vec![number("battery", *level, Some("%"))],
+		Sensor::TempHum((temp, hum)) => vec![
+			number("temperature", *temp as f64 / 100., Some("°C")),
+			number("humidity", *hum, Some("%")),
+		],
+		Sensor::Current(current) => vec![number("current", *current, Some("mA"))],
+	}
+}
+fn actuator_fields(actuator: &Actuator) -> Vec<Field> {
+	match actuator {
+		Actuator::Light(on) => vec![number("on", *on as u8, None)],
+	}
+}
+
+/// Decodes any record HQ stores into named fields with units
+pub fn record_fields(record: &Record) -> std::io::Result<Vec<Field>> {
+	let key = record.key();
+	let name = key.rsplit('/').next().unwrap_or_default();
+	// Limb values are columns, older limb records are postcard 'L' blobs handled below
+	if key.contains("/limb/") {
+		if let Ok(value) = limb_value_from_record(record) {
+			return Ok(match value {
+				LimbValue::Sensor(sensor) => sensor_fields(&sensor),
+				LimbValue::Actuator(actuator) => actuator_fields(&actuator),
+			});
+		}
+	}
+	let fields = match (name, record.format()) {
+		(_, "L") => {
+			let Limb(_, limb_type) = decode::<Limb>(record)?;
+			match limb_type {
+				LimbType::Sensor {
+					report_interval,
+					data,
+				} => {
+					let mut fields = vec![number("report_interval", report_interval, Some("s"))];
+					fields.extend(data.iter().flat_map(sensor_fields));
+					fields
+				}
+				LimbType::Actuator(actuator) => actuator_fields(&actuator),
+			}
+		}
+		(_, "N") => {
+			let info = decode::<NodeInfo>(record)?;
+			vec![
+				Field {
+					name: "board",
+					value: FieldValue::Text(format!("{:?}", info.board)),
+					unit: None,
+				},
+				number("heartbeat_interval", info.heartbeat_interval, Some("s")),
+			]
+		}
+		("heartbeat", "u") => vec![number("node_time", record.get::<u32>(0), Some("s"))],
+		("address", "u") => vec![number("address", record.get::<u32>(0), None)],
+		("debug", "s") => vec![Field {
+			name: "message",
+			value: FieldValue::Text(record.get::<&str>(0).to_string()),
+			unit: None,
+		}],
+		("link", "iuu") => vec![
+			number("rssi", record.get::<i32>(0), Some("dBm")),
+			number("retries", record.get::<u32>(1), None),
+			number("failed", record.get::<u32>(2), None),
+		],
+		("fault", "uuuu") => vec![
+			number("code", record.get::<u32>(0), None),
+			number("count", record.get::<u32>(1), None),
+			number("uptime", record.get::<u32>(2), Some("s")),
+			Field {
+				name: "reset",
+				value: FieldValue::Text(format!(
+					"{:?}",
+					ResetReason::from_code(record.get::<u32>(3) as u8)
+				)),
+				unit: None,
+			},
+		],
+		("fault_text", "s") => vec![Field {
+			name: "message",
+			value: FieldValue::Text(record.get::<&str>(0).to_string()),
+			unit: None,
+		}],
+		(name, format) => {
+			return Err(invalid_data(format!(
+				"don't know how to export '{name}' from '{format}'"
+			)))
+		}
+	};
+	Ok(fields)
+}
+
+fn csv_escape(value: &str) -> String {
+	if value.contains([',', '"', '\n']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_string()
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+	pub records: usize,
+	/// Records that couldn't be decoded, these are left out
+	pub skipped: usize,
+}
+
+/// Streams every record under `prefix` within `range` to `out`
+pub fn export(
+	reader: &DatabaseReader,
+	prefix: &str,
+	range: Range<Timestamp>,
+	format: ExportFormat,
+	out: &mut impl Write,
+) -> std::io::Result<ExportSummary> {
+	let mut summary = ExportSummary::default();
+	if format == ExportFormat::Csv {
+		writeln!(out, "time,key,field,value,unit")?;
+	}
+	for record in reader.get_filter(&Wildcard::new(&format!("{prefix}%"))) {
+		let timestamp = datetime_to_timestamp(record.time());
+		if !range.contains(&timestamp) {
+			continue;
+		}
+		let Ok(fields) = record_fields(&record) else {
+			summary.skipped += 1;
+			continue;
+		};
+		let time = record.time().format("%Y-%m-%dT%H:%M:%SZ");
+		match format {
+			ExportFormat::Csv => {
+				for field in fields {
+					let value = match field.value {
+						FieldValue::Number(n) => n.to_string(),
+						FieldValue::Text(text) => csv_escape(&text),
+					};
+					writeln!(
+						out,
+						"{time},{},{},{value},{}",
+						csv_escape(record.key()),
+						field.name,
+						field.unit.unwrap_or_default()
+					)?;
+				}
+			}
+			ExportFormat::Ndjson => {
+				let fields: Vec<_> = fields
+					.into_iter()
+					.map(|field| {
+						let value = match field.value {
+							FieldValue::Number(n) => serde_json::json!(n),
+							FieldValue::Text(text) => serde_json::json!(text),
+						};
+						serde_json::json!({"field": field.name, "value": value, "unit": field.unit})
+					})
+					.collect();
+				let line = serde_json::json!({
+					"time": time.to_string(),
+					"key": record.key(),
+					"fields": fields,
+				});
+				writeln!(out, "{line}")?;
+			}
+		}
+		summary.records += 1;
+	}
+	Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use sonnerie::{record, CreateTx};
+
+	#[test]
+	fn exports_csv_and_ndjson() {
+		let dir = std::env::temp_dir().join(format!("samn-export-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let at = |s: u32| timestamp_to_datetime(1_700_000_000 + s);
+		// Sonnerie wants records in key order
+		let mut tx = CreateTx::new(&dir).unwrap();
+		tx.add_record(&debug_key(4), at(0), record("low, \"very\" low"))
+			.unwrap();
+		// Doesn't decode
+		tx.add_record(&heartbeat_key(4), at(180), record("?"))
+			.unwrap();
+		tx.add_record(
+			&sensor_key(4, 1, &Sensor::TempHum((0, 0))),
+			at(60),
+			record(-125i32).add(40u32),
+		)
+		.unwrap();
+		tx.add_record(&link_key(4), at(120), record(-70i32).add(1u32).add(0u32))
+			.unwrap();
+		tx.add_record(&debug_key(5), at(0), record("other node"))
+			.unwrap();
+		tx.commit().unwrap();
+
+		let reader = DatabaseReader::new(&dir).unwrap();
+		let export = |range: Range<Timestamp>, format| {
+			let mut out = vec![];
+			let summary = export(&reader, "node/4/", range, format, &mut out).unwrap();
+			(String::from_utf8(out).unwrap(), summary)
+		};
+		let (csv, summary) = export(0..Timestamp::MAX, ExportFormat::Csv);
+		let (ndjson, _) = export(1_700_000_060..1_700_000_120, ExportFormat::Ndjson);
+		std::fs::remove_dir_all(&dir).ok();
+
+		assert_eq!(
+			summary,
+			ExportSummary {
+				records: 3,
+				skipped: 1
+			}
+		);
+		assert_eq!(
+			csv.lines().collect::<Vec<_>>(),
+			[
+				"time,key,field,value,unit",
+				"2023-11-14T22:13:20Z,node/4/debug,message,\"low, \"\"very\"\" low\",",
+				"2023-11-14T22:14:20Z,node/4/limb/1/temp_hum,temperature,-1.25,°C",
+				"2023-11-14T22:14:20Z,node/4/limb/1/temp_hum,humidity,40,%",
+				"2023-11-14T22:15:20Z,node/4/link,rssi,-70,dBm",
+				"2023-11-14T22:15:20Z,node/4/link,retries,1,",
+				"2023-11-14T22:15:20Z,node/4/link,failed,0,",
+			]
+		);
+		let lines: Vec<serde_json::Value> = ndjson
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect();
+		assert_eq!(
+			lines,
+			[serde_json::json!({
+				"time": "2023-11-14T22:14:20Z",
+				"key": "node/4/limb/1/temp_hum",
+				"fields": [
+					{"field": "temperature", "value": -1.25, "unit": "°C"},
+					{"field": "humidity", "value": 40.0, "unit": "%"},
+				],
+			})]
+		);
+	}
+}
@@ -0,0 +1,100 @@
+//! Frozen copies of stored types, as they were when each record version was written.
+//!
+//! Postcard has no schema, so once `Limb`/`NodeInfo` change shape the old
+//! bytes only decode with the old types. Never edit these, add a new version instead.
+use serde::Deserialize;
+
+use crate::node::{Actuator, Board, Limb, LimbType, NodeInfo, Sensor};
+
+/// Records without a version byte, written before [`RECORD_VERSION`](super::RECORD_VERSION)
+#[derive(Deserialize)]
+pub enum BoardV0 {
+	SamnV8,
+	SamnV9,
+	SamnDC,
+	SamnSwitch,
+}
+
+#[derive(Deserialize)]
+pub struct NodeInfoV0 {
+	pub board: BoardV0,
+	pub heartbeat_interval: u16,
+}
+
+#[derive(Deserialize)]
+pub enum SensorV0 {
+	Battery(u8),
+	TempHum((i16, u8)),
+	Current(u16),
+}
+
+#[derive(Deserialize)]
+pub enum ActuatorV0 {
+	Light(bool),
+}
+
+#[derive(Deserialize)]
+pub enum LimbTypeV0 {
+	Sensor {
+		report_interval: u16,
+		data: Option<SensorV0>,
+	},
+	Actuator(ActuatorV0),
+}
+
+#[derive(Deserialize)]
+pub struct LimbV0(pub u8, pub LimbTypeV0);
+
+impl From<BoardV0> for Board {
+	fn from(board: BoardV0) -> Self {
+		match board {
+			BoardV0::SamnV8 => Board::SamnV8,
+			BoardV0::SamnV9 => Board::SamnV9,
+			BoardV0::SamnDC => Board::SamnDC,
+			BoardV0::SamnSwitch => Board::SamnSwitch,
+		}
+	}
+}
+impl From<NodeInfoV0> for NodeInfo {
+	fn from(info: NodeInfoV0) -> Self {
+		NodeInfo {
+			board: info.board.into(),
+			heartbeat_interval: info.heartbeat_interval,
+		}
+	}
+}
+impl From<SensorV0> for Sensor {
+	fn from(sensor: SensorV0) -> Self {
+		match sensor {
+			SensorV0::Battery(level) => Sensor::Battery(level),
+			SensorV0::TempHum(temp_hum) => Sensor::TempHum(temp_hum),
+			SensorV0::Current(current) => Sensor::Current(current),
+		}
+	}
+}
+impl From<ActuatorV0> for Actuator {
+	fn from(actuator: ActuatorV0) -> Self {
+		match actuator {
+			ActuatorV0::Light(on) => Actuator::Light(on),
+		}
+	}
+}
+impl From<LimbTypeV0> for LimbType {
+	fn from(limb_type: LimbTypeV0) -> Self {
+		match limb_type {
+			LimbTypeV0::Sensor {
+				report_interval,
+				data,
+			} => LimbType::Sensor {
+				report_interval,
+				data: data.map(Into::into),
+			},
+			LimbTypeV0::Actuator(actuator) => LimbType::Actuator(actuator.into()),
+		}
+	}
+}
+impl From<LimbV0> for Limb {
+	fn from(limb: LimbV0) -> Self {
+		Limb(limb.0, limb.1.into())
+	}
+}
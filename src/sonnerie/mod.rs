@@ -0,0 +1,268 @@
+use crate::node::{Actuator, Limb, LimbId, NodeId, NodeInfo, Sensor, Timestamp};
+use sonnerie::{FromRecord, ToRecord};
+
+mod export;
+pub mod legacy;
+mod query;
+mod store;
+pub use export::*;
+pub use query::*;
+pub use store::*;
+
+// Keys are `node/<node_id>/...`, so a prefix scan on `node/<node_id>/` gets everything from a node.
+//
+// - `node/<id>/limb/<limb_id>/<sensor|actuator name>` limb values as columns, see [`TimeseriesTx`]
+// - `node/<id>/heartbeat` node clock (u)
+// - `node/<id>/info` [`NodeInfo`] (N)
+// - `node/<id>/address` network address given to the node (u)
+// - `node/<id>/debug` debug messages and logs (s)
+// - `node/<id>/link` [`LinkStats`] (iuu)
+// - `node/<id>/fault` [`Fault`](crate::node::Fault) code, count, uptime, reset reason (uuuu)
+// - `node/<id>/fault_text` the fault's code described, see [`DescribeFault`] (s)
+
+pub fn node_key(node_id: NodeId) -> String {
+	format!("node/{node_id}")
+}
+pub fn sensor_key(node_id: NodeId, limb: LimbId, sensor: &Sensor) -> String {
+	format!("node/{node_id}/limb/{limb}/{}", sensor.name())
+}
+pub fn actuator_key(node_id: NodeId, limb: LimbId, actuator: &Actuator) -> String {
+	format!("node/{node_id}/limb/{limb}/{}", actuator.name())
+}
+pub fn heartbeat_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/heartbeat")
+}
+pub fn info_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/info")
+}
+pub fn address_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/address")
+}
+pub fn debug_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/debug")
+}
+pub fn link_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/link")
+}
+pub fn fault_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/fault")
+}
+pub fn fault_text_key(node_id: NodeId) -> String {
+	format!("node/{node_id}/fault_text")
+}
+
+pub fn timestamp_to_datetime(timestamp: Timestamp) -> chrono::NaiveDateTime {
+	chrono::DateTime::from_timestamp(timestamp as i64, 0)
+		.unwrap_or_default()
+		.naive_utc()
+}
+pub fn datetime_to_timestamp(datetime: chrono::NaiveDateTime) -> Timestamp {
+	datetime.and_utc().timestamp().clamp(0, Timestamp::MAX as i64) as Timestamp
+}
+
+/// Bytes every postcard record takes, unused bytes are zero
+pub const RECORD_SIZE: usize = 32;
+/// First byte of every postcard record
+///
+/// Records written before versioning don't have one, they start straight with
+/// postcard data which is always < 0x80 there (a [`LimbId`] or a [`Board`](crate::node::Board) index).
+/// When the stored types change in a way postcard can't read old data, freeze the current types
+/// in [`legacy`], bump this and add a branch to the [`FromRecord`] impls.
+pub const RECORD_VERSION: u8 = 0x80 | 1;
+
+/// A postcard record with its version byte, made with `TryFrom` so encoding can fail
+///
+/// ```ignore
+/// tx.add_record(&key, at, record(VersionedRecord::try_from(&limb)?))?;
+/// ```
+pub struct VersionedRecord {
+	format_char: u8,
+	bytes: [u8; RECORD_SIZE],
+}
+
+impl VersionedRecord {
+	fn encode<T: serde::Serialize>(format_char: u8, value: &T) -> std::io::Result<Self> {
+		let mut bytes = [0u8; RECORD_SIZE];
+		bytes[0] = RECORD_VERSION;
+		postcard::to_slice(value, &mut bytes[1..]).map_err(|err| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("cannot encode '{}' record: {err}", format_char as char),
+			)
+		})?;
+		Ok(Self { format_char, bytes })
+	}
+}
+impl TryFrom<&Limb> for VersionedRecord {
+	type Error = std::io::Error;
+	fn try_from(limb: &Limb) -> std::io::Result<Self> {
+		Self::encode(b'L', limb)
+	}
+}
+impl TryFrom<&NodeInfo> for VersionedRecord {
+	type Error = std::io::Error;
+	fn try_from(info: &NodeInfo) -> std::io::Result<Self> {
+		Self::encode(b'N', info)
+	}
+}
+
+impl ToRecord for &VersionedRecord {
+	fn store(&self, buf: &mut std::vec::Vec<u8>) {
+		buf.extend_from_slice(&self.bytes);
+	}
+	fn format_char(&self) -> u8 {
+		self.format_char
+	}
+	fn size(&self) -> usize {
+		RECORD_SIZE
+	}
+	fn variable_size(&self) -> bool {
+		false
+	}
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn check_format_char(fmt_char: u8, expected: u8, name: &str) -> std::io::Result<()> {
+	if fmt_char != expected {
+		return Err(invalid_data(format!(
+			"cannot decode {name} from '{}'",
+			fmt_char as char
+		)));
+	}
+	Ok(())
+}
+
+/// Splits off the version byte, legacy records come back as version 0
+fn record_version(bytes: &[u8]) -> std::io::Result<(u8, &[u8])> {
+	match bytes.first() {
+		None => Err(invalid_data("empty record".into())),
+		Some(first) if first & 0x80 == 0 => Ok((0, bytes)),
+		Some(first) => Ok((first & !0x80, &bytes[1..])),
+	}
+}
+
+fn from_postcard<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8], name: &str) -> std::io::Result<T> {
+	postcard::from_bytes(bytes).map_err(|err| invalid_data(format!("cannot decode {name}: {err}")))
+}
+
+impl FromRecord<'_> for Limb {
+	fn get(fmt_char: u8, bytes: &[u8]) -> std::io::Result<Self> {
+		check_format_char(fmt_char, b'L', "Limb")?;
+		match record_version(bytes)? {
+			(0, bytes) => from_postcard::<legacy::LimbV0>(bytes, "Limb v0").map(Into::into),
+			(1, bytes) => from_postcard(bytes, "Limb"),
+			(version, _) => Err(invalid_data(format!("unknown Limb record version {version}"))),
+		}
+	}
+}
+
+impl FromRecord<'_> for NodeInfo {
+	fn get(fmt_char: u8, bytes: &[u8]) -> std::io::Result<Self> {
+		check_format_char(fmt_char, b'N', "NodeInfo")?;
+		match record_version(bytes)? {
+			(0, bytes) => from_postcard::<legacy::NodeInfoV0>(bytes, "NodeInfo v0").map(Into::into),
+			(1, bytes) => from_postcard(bytes, "NodeInfo"),
+			(version, _) => Err(invalid_data(format!("unknown NodeInfo record version {version}"))),
+		}
+	}
+}
+
+/// Decodes a single column record, without panicking on a format mismatch
+pub fn decode<'a, T: FromRecord<'a>>(record: &'a sonnerie::Record) -> std::io::Result<T> {
+	match record.format().as_bytes() {
+		[fmt_char] => T::get(*fmt_char, record.value()),
+		_ => Err(invalid_data(format!(
+			"expected a single column, got '{}'",
+			record.format()
+		))),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::node::{Board, LimbType, Sensor};
+	use sonnerie::{record, CreateTx, DatabaseReader};
+
+	/// Whatever bytes we want, to fake corrupted records
+	struct Raw(u8, [u8; RECORD_SIZE]);
+	impl ToRecord for &Raw {
+		fn store(&self, buf: &mut std::vec::Vec<u8>) {
+			buf.extend_from_slice(&self.1);
+		}
+		fn format_char(&self) -> u8 {
+			self.0
+		}
+		fn size(&self) -> usize {
+			RECORD_SIZE
+		}
+		fn variable_size(&self) -> bool {
+			false
+		}
+	}
+
+	fn info() -> NodeInfo {
+		NodeInfo {
+			board: Board::SamnSwitch,
+			heartbeat_interval: 60,
+		}
+	}
+
+	#[test]
+	fn records_decode_versions() {
+		let limb = Limb(
+			2,
+			LimbType::Sensor {
+				report_interval: 300,
+				data: Some(Sensor::TempHum((-250, 40))),
+			},
+		);
+		let versioned = VersionedRecord::try_from(&limb).unwrap();
+		assert_eq!(versioned.bytes[0], RECORD_VERSION);
+		assert_eq!(Limb::get(b'L', &versioned.bytes).unwrap(), limb);
+
+		// Written before versioning, straight postcard
+		let mut legacy = [0u8; RECORD_SIZE];
+		postcard::to_slice(&limb, &mut legacy).unwrap();
+		assert_eq!(Limb::get(b'L', &legacy).unwrap(), limb);
+		postcard::to_slice(&info(), &mut legacy).unwrap();
+		assert_eq!(NodeInfo::get(b'N', &legacy).unwrap(), info());
+
+		assert!(Limb::get(b'N', &versioned.bytes).is_err());
+		assert!(Limb::get(b'L', &[]).is_err());
+		assert!(Limb::get(b'L', &[0x80 | 9, 0, 0]).is_err());
+	}
+
+	#[test]
+	fn corrupted_database_doesnt_panic() {
+		let dir = std::env::temp_dir().join(format!("samn-sonnerie-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let good = VersionedRecord::try_from(&info()).unwrap();
+		let mut bad_board = [0u8; RECORD_SIZE];
+		bad_board[0] = RECORD_VERSION;
+		bad_board[1] = 0x09;
+		let mut tx = CreateTx::new(&dir).unwrap();
+		let key = info_key(1);
+		let at = |s: u32| timestamp_to_datetime(1_700_000_000 + s);
+		tx.add_record(&key, at(0), record(&good)).unwrap();
+		tx.add_record(&key, at(1), record(&Raw(b'N', [0xff; RECORD_SIZE]))).unwrap();
+		tx.add_record(&key, at(2), record(&Raw(b'N', bad_board))).unwrap();
+		tx.add_record(&key, at(3), record(&Raw(b'N', [0x07; RECORD_SIZE]))).unwrap();
+		tx.add_record(&key, at(4), record(7u32)).unwrap();
+		tx.commit().unwrap();
+
+		let reader = DatabaseReader::new(&dir).unwrap();
+		let decoded: Vec<_> = reader
+			.get(&key)
+			.into_iter()
+			.map(|record| decode::<NodeInfo>(&record).ok())
+			.collect();
+		std::fs::remove_dir_all(&dir).ok();
+
+		assert_eq!(decoded, [Some(info()), None, None, None, None]);
+	}
+}
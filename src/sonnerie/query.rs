@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+
+use sonnerie::{DatabaseReader, Record, Wildcard};
+
+use super::*;
+use crate::node::{
+	Actuator, Fault, LimbId, NodeAddress, NodeId, NodeInfo, ResetReason, Sensor, Timestamp,
+};
+
+/// A decoded `node/<id>/limb/<limb_id>/<name>` record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimbValue {
+	Sensor(Sensor),
+	Actuator(Actuator),
+}
+
+/// Decodes a limb record written by [`TimeseriesTx`], from its key name and columns
+///
+/// Checks the columns before reading them so a schema mismatch is an error, not a panic.
+pub fn limb_value_from_record(record: &Record) -> std::io::Result<LimbValue> {
+	let name = record.key().rsplit('/').next().unwrap_or_default();
+	let value = match (name, record.format()) {
+		("battery", "u") => LimbValue::Sensor(Sensor::Battery(record.get::<u32>(0) as u8)),
+		("temp_hum", "iu") => LimbValue::Sensor(Sensor::TempHum((
+			record.get::<i32>(0) as i16,
+			record.get::<u32>(1) as u8,
+		))),
+		("current", "u") => LimbValue::Sensor(Sensor::Current(record.get::<u32>(0) as u16)),
+		("light", "u") => LimbValue::Actuator(Actuator::Light(record.get::<u32>(0) != 0)),
+		(name, format) => {
+			return Err(invalid_data(format!(
+				"cannot decode limb value '{name}' from '{format}'"
+			)))
+		}
+	};
+	Ok(value)
+}
+
+/// Limb id from a `node/<id>/limb/<limb_id>/<name>` key
+fn limb_id_from_key(key: &str) -> Option<LimbId> {
+	let mut parts = key.rsplit('/');
+	parts.next()?;
+	parts.next()?.parse().ok()
+}
+
+/// Node id from a `node/<id>/...` key
+fn node_id_from_key(key: &str) -> Option<NodeId> {
+	key.strip_prefix("node/")?.split('/').next()?.parse().ok()
+}
+
+/// One of each [`Sensor`], for the key names a limb's readings can be under
+const SENSORS: [Sensor; 3] = [
+	Sensor::Battery(0),
+	Sensor::TempHum((0, 0)),
+	Sensor::Current(0),
+];
+
+/// What HQ has stored about a node, see [`TimeseriesQuery::nodes`]
+#[derive(Clone, Debug, Default)]
+pub struct NodeEntry {
+	pub node_id: NodeId,
+	/// Last address HQ gave the node
+	pub address: Option<NodeAddress>,
+	pub info: Option<NodeInfo>,
+	/// When HQ got the node's last heartbeat
+	pub last_heartbeat: Option<Timestamp>,
+}
+
+/// Reads node history back out of a sonnerie database
+pub struct TimeseriesQuery {
+	reader: DatabaseReader,
+}
+
+impl TimeseriesQuery {
+	pub fn open(dir: &Path) -> std::io::Result<Self> {
+		Ok(Self {
+			reader: DatabaseReader::new(dir)?,
+		})
+	}
+
+	fn limb_records(&self, node_id: NodeId) -> impl Iterator<Item = Record> + '_ {
+		let pattern = format!("{}/limb/%", node_key(node_id));
+		self.reader.get_filter(&Wildcard::new(&pattern)).into_iter()
+	}
+
+	/// Sensor readings of a limb within `range`, oldest first
+	///
+	/// Only reads the limb's sensor keys, each up to the end of `range`.
+	/// Records that don't decode are skipped, the count of them is returned alongside.
+	pub fn sensor_history(
+		&self,
+		node_id: NodeId,
+		limb: LimbId,
+		range: Range<Timestamp>,
+	) -> (Vec<(Timestamp, Sensor)>, usize) {
+		let mut samples = vec![];
+		let mut invalid = 0;
+		for sensor in &SENSORS {
+			let key = sensor_key(node_id, limb, sensor);
+			// A key's records come oldest first
+			let records = self
+				.reader
+				.get(&key)
+				.into_iter()
+				.skip_while(|record| datetime_to_timestamp(record.time()) < range.start)
+				.take_while(|record| datetime_to_timestamp(record.time()) < range.end);
+			for record in records {
+				match limb_value_from_record(&record) {
+					Ok(LimbValue::Sensor(sensor)) => {
+						samples.push((datetime_to_timestamp(record.time()), sensor))
+					}
+					Ok(LimbValue::Actuator(_)) => {}
+					Err(_) => invalid += 1,
+				}
+			}
+		}
+		// Different sensor names are different keys, put them back in time order
+		samples.sort_by_key(|(timestamp, _)| *timestamp);
+		(samples, invalid)
+	}
+
+	/// Faults a node reported within `range`, oldest first
+	///
+	/// Records that don't decode are skipped.
+	pub fn faults(
+		&self,
+		node_id: NodeId,
+		range: Range<Timestamp>,
+	) -> Vec<(Timestamp, Fault)> {
+		self
+			.reader
+			.get(&fault_key(node_id))
+			.into_iter()
+			.filter(|record| record.format() == "uuuu")
+			.map(|record| {
+				let fault = Fault {
+					code: record.get::<u32>(0) as u8,
+					count: record.get::<u32>(1) as u16,
+					uptime: record.get::<u32>(2),
+					reset: ResetReason::from_code(record.get::<u32>(3) as u8),
+				};
+				(datetime_to_timestamp(record.time()), fault)
+			})
+			.skip_while(|(timestamp, _)| *timestamp < range.start)
+			.take_while(|(timestamp, _)| *timestamp < range.end)
+			.collect()
+	}
+
+	/// Every node that was given an address, sent its info or a heartbeat, by node id
+	pub fn nodes(&self) -> Vec<NodeEntry> {
+		let mut nodes = BTreeMap::<NodeId, NodeEntry>::new();
+		for leaf in ["address", "info", "heartbeat"] {
+			let pattern = format!("node/%/{leaf}");
+			// Records come oldest first for each key, so the last one wins
+			for record in self.reader.get_filter(&Wildcard::new(&pattern)) {
+				let Some(node_id) = node_id_from_key(record.key()) else {
+					continue;
+				};
+				let entry = nodes.entry(node_id).or_insert_with(|| NodeEntry {
+					node_id,
+					..Default::default()
+				});
+				match (leaf, record.format()) {
+					("address", "u") => entry.address = Some(record.get::<u32>(0) as NodeAddress),
+					("info", _) => {
+						// Keep the last one that decodes
+						if let Ok(info) = decode::<NodeInfo>(&record) {
+							entry.info = Some(info);
+						}
+					}
+					("heartbeat", _) => {
+						entry.last_heartbeat = Some(datetime_to_timestamp(record.time()))
+					}
+					_ => {}
+				}
+			}
+		}
+		nodes.into_values().collect()
+	}
+
+	/// Latest value stored for each of a node's limbs
+	pub fn last_values(&self, node_id: NodeId) -> BTreeMap<LimbId, (Timestamp, LimbValue)> {
+		let mut values = BTreeMap::<LimbId, (Timestamp, LimbValue)>::new();
+		let mut records = self.limb_records(node_id).peekable();
+		while let Some(record) = records.next() {
+			// Records come oldest first for each key, only the newest one is decoded
+			if records
+				.peek()
+				.is_some_and(|next| next.key() == record.key())
+			{
+				continue;
+			}
+			let (Some(limb), Ok(value)) = (
+				limb_id_from_key(record.key()),
+				limb_value_from_record(&record),
+			) else {
+				continue;
+			};
+			let timestamp = datetime_to_timestamp(record.time());
+			if values.get(&limb).is_none_or(|(last, _)| *last <= timestamp) {
+				values.insert(limb, (timestamp, value));
+			}
+		}
+		values
+	}
+}
+
+/// Aggregate of the readings in a time bucket
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bucket {
+	/// Start of the bucket, a multiple of the bucket size
+	pub start: Timestamp,
+	pub count: usize,
+	pub min: Sensor,
+	pub max: Sensor,
+	pub avg: Sensor,
+}
+
+/// Running min/max/sum of one sensor in a bucket
+struct Aggregate {
+	start: Timestamp,
+	sensor: Sensor,
+	count: usize,
+	min: [i32; 2],
+	max: [i32; 2],
+	sum: [i64; 2],
+}
+
+impl Aggregate {
+	fn new(start: Timestamp, sensor: &Sensor) -> Self {
+		let fields = sensor.fields();
+		Self {
+			start,
+			sensor: sensor.clone(),
+			count: 1,
+			min: fields,
+			max: fields,
+			sum: fields.map(i64::from),
+		}
+	}
+	fn add(&mut self, sensor: &Sensor) {
+		for (i, field) in sensor.fields().into_iter().enumerate() {
+			self.min[i] = self.min[i].min(field);
+			self.max[i] = self.max[i].max(field);
+			self.sum[i] += field as i64;
+		}
+		self.count += 1;
+	}
+	fn finish(self) -> Bucket {
+		let count = self.count as i64;
+		Bucket {
+			start: self.start,
+			count: self.count,
+			min: self.sensor.with_fields(self.min),
+			max: self.sensor.with_fields(self.max),
+			avg: self
+				.sensor
+				.with_fields(self.sum.map(|field| (field / count) as i32)),
+		}
+	}
+}
+
+/// Min/max/avg of `samples` per `bucket` seconds, samples must be oldest first
+///
+/// Averages per field with integer division like [`Sensor`]'s `Add`, but over the whole
+/// bucket instead of pairwise. Like `Add` only readings of the same sensor go together,
+/// a bucket with different sensors gives one [`Bucket`] per sensor, in the order they
+/// first show up.
+pub fn downsample(samples: &[(Timestamp, Sensor)], bucket: u32) -> Vec<Bucket> {
+	let bucket = bucket.max(1);
+	let mut aggregates: Vec<Aggregate> = vec![];
+	// Where the aggregates of the current bucket start
+	let mut current = 0;
+	for (timestamp, sensor) in samples {
+		let start = timestamp - timestamp % bucket;
+		if aggregates.last().is_some_and(|last| last.start != start) {
+			current = aggregates.len();
+		}
+		match aggregates[current..]
+			.iter_mut()
+			.find(|aggregate| aggregate.sensor.is_same_sensor(sensor))
+		{
+			Some(aggregate) => aggregate.add(sensor),
+			None => aggregates.push(Aggregate::new(start, sensor)),
+		}
+	}
+	aggregates.into_iter().map(Aggregate::finish).collect()
+}
+
+/// Time ranges where reports are missing, samples must be oldest first
+///
+/// A gap is two consecutive samples more than 1.5 `report_interval`s apart,
+/// so at least one report didn't make it. The range is between those two samples.
+pub fn gaps(
+	samples: &[(Timestamp, Sensor)],
+	report_interval: u16,
+) -> Vec<Range<Timestamp>> {
+	let max_delta = report_interval as u32 + report_interval as u32 / 2;
+	samples
+		.windows(2)
+		.filter_map(|pair| {
+			let (from, to) = (pair[0].0, pair[1].0);
+			(to.saturating_sub(from) > max_delta).then_some(from..to)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use sonnerie::{record, CreateTx};
+
+	#[test]
+	fn history_and_last_values_from_database() {
+		let dir = std::env::temp_dir().join(format!("samn-query-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let at = |s: u32| timestamp_to_datetime(1_700_000_000 + s);
+		let battery = sensor_key(3, 1, &Sensor::Battery(0));
+		let temp_hum = sensor_key(3, 1, &Sensor::TempHum((0, 0)));
+		let light = actuator_key(3, 2, &Actuator::Light(false));
+		let mut tx = CreateTx::new(&dir).unwrap();
+		for (s, level) in [(0, 90), (100, 89), (200, 88), (300, 87)] {
+			tx.add_record(&battery, at(s), record(level as u32))
+				.unwrap();
+		}
+		tx.add_record(&temp_hum, at(150), record(2150i32).add(50u32))
+			.unwrap();
+		// Wrong columns for the key
+		tx.add_record(&temp_hum, at(160), record(7u32)).unwrap();
+		tx.add_record(&temp_hum, at(400), record(-20i32).add(60u32))
+			.unwrap();
+		tx.add_record(&light, at(50), record(1u32)).unwrap();
+		tx.add_record(&light, at(500), record(0u32)).unwrap();
+		// Another node, same limb
+		tx.add_record(
+			&sensor_key(30, 1, &Sensor::Battery(0)),
+			at(120),
+			record(5u32),
+		)
+		.unwrap();
+		tx.commit().unwrap();
+
+		let query = TimeseriesQuery::open(&dir).unwrap();
+		let history = query.sensor_history(3, 1, 1_700_000_100..1_700_000_300);
+		let last = query.last_values(3);
+		std::fs::remove_dir_all(&dir).ok();
+
+		let t = 1_700_000_000;
+		assert_eq!(
+			history,
+			(
+				vec![
+					(t + 100, Sensor::Battery(89)),
+					(t + 150, Sensor::TempHum((2150, 50))),
+					(t + 200, Sensor::Battery(88)),
+				],
+				1
+			)
+		);
+		assert_eq!(
+			last.into_iter().collect::<Vec<_>>(),
+			[
+				(1, (t + 400, LimbValue::Sensor(Sensor::TempHum((-20, 60))))),
+				(2, (t + 500, LimbValue::Actuator(Actuator::Light(false)))),
+			]
+		);
+	}
+
+	#[test]
+	fn downsample_buckets() {
+		let samples = [
+			(1000, Sensor::TempHum((2000, 40))),
+			(1100, Sensor::TempHum((2100, 50))),
+			(1150, Sensor::TempHum((2300, 45))),
+			(1150, Sensor::Battery(90)),
+			(1190, Sensor::Battery(81)),
+			(1300, Sensor::TempHum((1900, 60))),
+		];
+		let buckets = downsample(&samples, 200);
+		assert_eq!(
+			buckets,
+			[
+				Bucket {
+					start: 1000,
+					count: 3,
+					min: Sensor::TempHum((2000, 40)),
+					max: Sensor::TempHum((2300, 50)),
+					avg: Sensor::TempHum((2133, 45)),
+				},
+				Bucket {
+					start: 1000,
+					count: 2,
+					min: Sensor::Battery(81),
+					max: Sensor::Battery(90),
+					avg: Sensor::Battery(85),
+				},
+				Bucket {
+					start: 1200,
+					count: 1,
+					min: Sensor::TempHum((1900, 60)),
+					max: Sensor::TempHum((1900, 60)),
+					avg: Sensor::TempHum((1900, 60)),
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn gaps_from_report_interval() {
+		let samples: Vec<_> = [0, 300, 610, 1500, 1800, 2500]
+			.into_iter()
+			.map(|t| (t, Sensor::Battery(50)))
+			.collect();
+		assert_eq!(gaps(&samples, 300), [610..1500, 1800..2500]);
+		assert_eq!(limb_id_from_key("node/5/limb/2/temp_hum"), Some(2));
+		assert_eq!(node_id_from_key("node/5/address"), Some(5));
+		assert_eq!(node_id_from_key("hq/5"), None);
+	}
+}
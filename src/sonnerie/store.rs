@@ -0,0 +1,508 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use sonnerie::{record, CreateTx, WriteFailure};
+
+use super::*;
+use crate::node::{
+	Actuator, Fault, LimbType, Limbs, Message, MessageData, NodeAddress, NodeId, NodeInfo,
+	Response, Sensor, Timestamp,
+};
+
+/// Radio link quality HQ measured for a node
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+	/// Signal strength of the last frame received, in dBm
+	pub rssi: i16,
+	/// Retransmissions it took to reach the node
+	pub retries: u8,
+	/// Sends to the node that were never acked
+	pub failed: u16,
+}
+
+#[derive(Clone, Debug)]
+enum Row {
+	Sensor(Sensor),
+	Actuator(Actuator),
+	Heartbeat(Timestamp),
+	Info(NodeInfo),
+	Address(NodeAddress),
+	Debug(String),
+	Link(LinkStats),
+	Fault(Fault),
+	FaultText(String),
+}
+
+/// Turns a [`Fault`] code into text, same as [`errors::Discriminant::describe`]
+///
+/// Codes depend on the error type the node's firmware reports with, so HQ has to be
+/// told which one: `store.describe_faults_with(<MyError as Discriminant>::describe)`.
+pub type DescribeFault = fn(u8, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+fn describe_code(code: u8, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	write!(f, "error {code}")
+}
+
+struct Described(DescribeFault, u8);
+impl fmt::Display for Described {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		(self.0)(self.1, f)
+	}
+}
+
+/// Writes decoded [`Message`]s from HQ into a sonnerie database
+///
+/// ```ignore
+/// let store = TimeseriesStore::new("db/");
+/// let mut tx = store.begin()?;
+/// tx.add_message(node_id, &message, now);
+/// tx.commit()?;
+/// ```
+pub struct TimeseriesStore {
+	dir: PathBuf,
+	describe_fault: DescribeFault,
+}
+
+impl TimeseriesStore {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self {
+			dir: dir.into(),
+			describe_fault: describe_code,
+		}
+	}
+	pub fn dir(&self) -> &std::path::Path {
+		&self.dir
+	}
+	/// Fault texts are just `error <code>` until this is set
+	pub fn describe_faults_with(&mut self, describe: DescribeFault) {
+		self.describe_fault = describe;
+	}
+	pub fn begin(&self) -> std::io::Result<TimeseriesTx> {
+		Ok(TimeseriesTx {
+			tx: CreateTx::new(&self.dir)?,
+			rows: Default::default(),
+			appended_ns: None,
+			describe_fault: self.describe_fault,
+		})
+	}
+	/// Writes a single message in its own transaction, returns the number of records written
+	pub fn write(
+		&self,
+		node_id: NodeId,
+		message: &Message,
+		received_at: Timestamp,
+	) -> std::io::Result<usize> {
+		let mut tx = self.begin()?;
+		let n = tx.add_message(node_id, message, received_at);
+		tx.commit()?;
+		Ok(n)
+	}
+}
+
+/// A batch of records, written on [`TimeseriesTx::commit`]
+///
+/// Rows are kept sorted by key and time since that's the order sonnerie wants them in,
+/// a row for the same key and time replaces the previous one.
+///
+/// Faults, debug messages and logs are events rather than values, each one is kept.
+/// They're stored with the nanoseconds into the second HQ added them at, so two in the
+/// same second (even from different transactions) don't replace each other.
+pub struct TimeseriesTx {
+	tx: CreateTx,
+	/// (key, time, nanoseconds into the second)
+	rows: BTreeMap<(String, Timestamp, u32), Row>,
+	/// Nanoseconds of the last appended row
+	appended_ns: Option<u32>,
+	describe_fault: DescribeFault,
+}
+
+impl TimeseriesTx {
+	/// Adds the records a message carries, returns how many
+	///
+	/// `received_at` is used for values that don't carry their own timestamp.
+	/// Commands and network search messages don't hold telemetry, they add nothing.
+	/// Neither do [`Message::Log`] chunks, put them together and add the text with
+	/// [`TimeseriesTx::add_log`].
+	pub fn add_message(
+		&mut self,
+		node_id: NodeId,
+		message: &Message,
+		received_at: Timestamp,
+	) -> usize {
+		match message {
+			Message::Message(data) => self.add_message_data(node_id, data, received_at),
+			Message::RelayMessage(node_id, data) => {
+				self.add_message_data(*node_id, data, received_at)
+			}
+			Message::Network(node_id, address) => {
+				self.add_row(address_key(*node_id), received_at, Row::Address(*address))
+			}
+			Message::DebugMessage(node_id, message) => {
+				let end = message.iter().position(|b| *b == 0).unwrap_or(message.len());
+				let text = String::from_utf8_lossy(&message[..end]).into_owned();
+				self.append_row(debug_key(*node_id), received_at, Row::Debug(text))
+			}
+			Message::FaultReport(node_id, fault) => {
+				let text = Described(self.describe_fault, fault.code).to_string();
+				// Same time for both, so the text goes with its fault
+				let ns = self.append_ns();
+				self.insert_row(fault_key(*node_id), received_at, ns, Row::Fault(*fault))
+					+ self.insert_row(
+						fault_text_key(*node_id),
+						received_at,
+						ns,
+						Row::FaultText(text),
+					)
+			}
+			Message::SearchingNetwork(_) | Message::Log(_, _) => 0,
+		}
+	}
+
+	fn add_message_data(
+		&mut self,
+		node_id: NodeId,
+		data: &MessageData,
+		received_at: Timestamp,
+	) -> usize {
+		let MessageData::Response { response, .. } = data else {
+			return 0;
+		};
+		match response {
+			Response::Info(info) => {
+				self.add_row(info_key(node_id), received_at, Row::Info(info.clone()))
+			}
+			Response::Limbs(limbs) => self.add_limbs(node_id, limbs, received_at),
+			Response::LimbsAt(timestamp, limbs) => self.add_limbs(node_id, limbs, *timestamp),
+			Response::Heartbeat(timestamp) => {
+				self.add_row(heartbeat_key(node_id), received_at, Row::Heartbeat(*timestamp))
+			}
+			Response::History(history) => history
+				.samples
+				.iter()
+				.flatten()
+				.map(|(timestamp, sensor)| {
+					self.add_row(
+						sensor_key(node_id, history.limb, sensor),
+						*timestamp,
+						Row::Sensor(sensor.clone()),
+					)
+				})
+				.sum(),
+			Response::Ok | Response::ErrLimbNotFound | Response::ErrLimbTypeDoesntMatch => 0,
+		}
+	}
+
+	pub fn add_limbs(&mut self, node_id: NodeId, limbs: &Limbs, at: Timestamp) -> usize {
+		limbs
+			.iter()
+			.flatten()
+			.map(|Limb(limb_id, limb_type)| match limb_type {
+				LimbType::Sensor {
+					data: Some(sensor), ..
+				} => self.add_row(
+					sensor_key(node_id, *limb_id, sensor),
+					at,
+					Row::Sensor(sensor.clone()),
+				),
+				LimbType::Sensor { data: None, .. } => 0,
+				LimbType::Actuator(actuator) => self.add_row(
+					actuator_key(node_id, *limb_id, actuator),
+					at,
+					Row::Actuator(actuator.clone()),
+				),
+			})
+			.sum()
+	}
+
+	pub fn add_link_stats(&mut self, node_id: NodeId, stats: &LinkStats, at: Timestamp) {
+		self.add_row(link_key(node_id), at, Row::Link(stats.clone()));
+	}
+
+	/// A log resolved with [`LogTable`](crate::log::LogTable), kept with the debug messages
+	pub fn add_log(&mut self, node_id: NodeId, text: String, at: Timestamp) {
+		self.append_row(debug_key(node_id), at, Row::Debug(text));
+	}
+
+	/// Adds a value, replacing the one for the same key and time, returns 0 if it did
+	fn add_row(&mut self, key: String, at: Timestamp, row: Row) -> usize {
+		self.insert_row(key, at, 0, row)
+	}
+
+	/// Adds an event, kept next to any others at the same time
+	fn append_row(&mut self, key: String, at: Timestamp, row: Row) -> usize {
+		let ns = self.append_ns();
+		self.insert_row(key, at, ns, row)
+	}
+
+	fn insert_row(&mut self, key: String, at: Timestamp, ns: u32, row: Row) -> usize {
+		self.rows.insert((key, at, ns), row).is_none() as usize
+	}
+
+	/// Nanoseconds into the second for a row that's kept next to others at the same time
+	///
+	/// From the clock, moved on when it didn't since the last one.
+	fn append_ns(&mut self) -> u32 {
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap_or_default()
+			.subsec_nanos();
+		let ns = match self.appended_ns {
+			Some(last) if now <= last => (last + 1) % 1_000_000_000,
+			_ => now,
+		};
+		self.appended_ns = Some(ns);
+		ns
+	}
+
+	pub fn commit(mut self) -> std::io::Result<()> {
+		for ((key, at, ns), row) in std::mem::take(&mut self.rows) {
+			let at = timestamp_to_datetime(at) + chrono::Duration::nanoseconds(ns as i64);
+			match row {
+				Row::Sensor(Sensor::Battery(level)) => {
+					self.tx.add_record(&key, at, record(level as u32))
+				}
+				Row::Sensor(Sensor::TempHum((temp, hum))) => {
+					self.tx.add_record(&key, at, record(temp as i32).add(hum as u32))
+				}
+				Row::Sensor(Sensor::Current(current)) => {
+					self.tx.add_record(&key, at, record(current as u32))
+				}
+				Row::Actuator(Actuator::Light(on)) => self.tx.add_record(&key, at, record(on as u32)),
+				Row::Heartbeat(timestamp) => self.tx.add_record(&key, at, record(timestamp)),
+				Row::Info(info) => {
+					let info = VersionedRecord::try_from(&info)?;
+					self.tx.add_record(&key, at, record(&info))
+				}
+				Row::Address(address) => self.tx.add_record(&key, at, record(address as u32)),
+				Row::Debug(text) => self.tx.add_record(&key, at, record(text.as_str())),
+				Row::Link(stats) => self.tx.add_record(
+					&key,
+					at,
+					record(stats.rssi as i32)
+						.add(stats.retries as u32)
+						.add(stats.failed as u32),
+				),
+				Row::Fault(fault) => self.tx.add_record(
+					&key,
+					at,
+					record(fault.code as u32)
+						.add(fault.count as u32)
+						.add(fault.uptime)
+						.add(fault.reset.code() as u32),
+				),
+				Row::FaultText(text) => self.tx.add_record(&key, at, record(text.as_str())),
+			}
+			.map_err(write_failure)?;
+		}
+		self.tx.commit().map_err(write_failure)
+	}
+}
+
+fn write_failure(err: WriteFailure) -> std::io::Error {
+	std::io::Error::other(format!("sonnerie write failed: {err:?}"))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::node::ResetReason;
+	use sonnerie::DatabaseReader;
+
+	fn sensor(limb_id: LimbId, sensor: Sensor) -> Option<Limb> {
+		Some(Limb(
+			limb_id,
+			LimbType::Sensor {
+				report_interval: 300,
+				data: Some(sensor),
+			},
+		))
+	}
+
+	#[test]
+	fn stored_values_come_back() {
+		let dir = std::env::temp_dir().join(format!("samn-store-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let t = 1_700_000_000;
+		let limbs_at = |at, temp, battery, on| {
+			Message::Message(MessageData::Response {
+				id: None,
+				response: Response::LimbsAt(
+					at,
+					[
+						sensor(0, Sensor::TempHum((temp, 50))),
+						sensor(1, Sensor::Battery(battery)),
+						Some(Limb(2, LimbType::Actuator(Actuator::Light(on)))),
+					],
+				),
+			})
+		};
+		let stats = LinkStats {
+			rssi: -71,
+			retries: 2,
+			failed: 5,
+		};
+
+		let store = TimeseriesStore::new(&dir);
+		let mut tx = store.begin().unwrap();
+		assert_eq!(tx.add_message(7, &limbs_at(t, -1250, 90, true), t + 5), 3);
+		assert_eq!(
+			tx.add_message(7, &limbs_at(t + 300, 2150, 89, false), t + 305),
+			3
+		);
+		tx.add_link_stats(7, &stats, t + 305);
+		tx.commit().unwrap();
+
+		let query = TimeseriesQuery::open(&dir).unwrap();
+		let (samples, invalid) = query.sensor_history(7, 0, t..t + 600);
+		assert_eq!(
+			samples,
+			[
+				(t, Sensor::TempHum((-1250, 50))),
+				(t + 300, Sensor::TempHum((2150, 50)))
+			]
+		);
+		assert_eq!(invalid, 0);
+		assert_eq!(query.sensor_history(7, 1, t + 1..t + 600).0.len(), 1);
+		let last = query.last_values(7);
+		assert_eq!(last[&1], (t + 300, LimbValue::Sensor(Sensor::Battery(89))));
+		assert_eq!(
+			last[&2],
+			(t + 300, LimbValue::Actuator(Actuator::Light(false)))
+		);
+
+		let reader = DatabaseReader::new(&dir).unwrap();
+		let links: Vec<_> = reader
+			.get(&link_key(7))
+			.into_iter()
+			.map(|record| {
+				assert_eq!(record.format(), "iuu");
+				LinkStats {
+					rssi: record.get::<i32>(0) as i16,
+					retries: record.get::<u32>(1) as u8,
+					failed: record.get::<u32>(2) as u16,
+				}
+			})
+			.collect();
+		std::fs::remove_dir_all(&dir).ok();
+
+		assert_eq!(links, [stats]);
+	}
+
+	#[test]
+	fn debug_messages_in_the_same_second_are_kept() {
+		let dir = std::env::temp_dir().join(format!("samn-events-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let t = 1_700_000_000;
+		let debug = |text: &[u8]| {
+			let mut message = [0u8; 20];
+			message[..text.len()].copy_from_slice(text);
+			Message::DebugMessage(7, message)
+		};
+
+		let store = TimeseriesStore::new(&dir);
+		let mut tx = store.begin().unwrap();
+		assert_eq!(tx.add_message(7, &debug(b"one"), t), 1);
+		assert_eq!(tx.add_message(7, &debug(b"two"), t), 1);
+		tx.commit().unwrap();
+		assert_eq!(store.write(7, &debug(b"three"), t).unwrap(), 1);
+
+		let mut texts: Vec<_> = DatabaseReader::new(&dir)
+			.unwrap()
+			.get(&debug_key(7))
+			.into_iter()
+			.map(|record| {
+				assert_eq!(datetime_to_timestamp(record.time()), t);
+				record.get::<&str>(0).to_string()
+			})
+			.collect();
+		std::fs::remove_dir_all(&dir).ok();
+
+		texts.sort();
+		assert_eq!(texts, ["one", "three", "two"]);
+	}
+
+	#[test]
+	fn faults_in_the_same_second_are_kept() {
+		let dir = std::env::temp_dir().join(format!("samn-faults-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let t = 1_700_000_000;
+		let fault = |code| Fault {
+			code,
+			count: 1,
+			uptime: 60,
+			reset: ResetReason::Watchdog,
+		};
+
+		let store = TimeseriesStore::new(&dir);
+		let mut tx = store.begin().unwrap();
+		assert_eq!(tx.add_message(7, &Message::FaultReport(7, fault(1)), t), 2);
+		assert_eq!(tx.add_message(7, &Message::FaultReport(7, fault(2)), t), 2);
+		tx.commit().unwrap();
+		assert_eq!(
+			store
+				.write(7, &Message::FaultReport(7, fault(3)), t)
+				.unwrap(),
+			2
+		);
+		// Values still replace each other
+		let mut tx = store.begin().unwrap();
+		let heartbeat = |at| {
+			Message::Message(MessageData::Response {
+				id: None,
+				response: Response::Heartbeat(at),
+			})
+		};
+		assert_eq!(tx.add_message(7, &heartbeat(t), t), 1);
+		assert_eq!(tx.add_message(7, &heartbeat(t + 1), t), 0);
+
+		let query = TimeseriesQuery::open(&dir).unwrap();
+		let mut faults = query.faults(7, t..t + 1);
+		let mut texts: Vec<_> = DatabaseReader::new(&dir)
+			.unwrap()
+			.get(&fault_text_key(7))
+			.into_iter()
+			.map(|record| record.get::<&str>(0).to_string())
+			.collect();
+		std::fs::remove_dir_all(&dir).ok();
+
+		// The clock may have gone into the next second between transactions
+		faults.sort_by_key(|(_, fault)| fault.code);
+		texts.sort();
+		assert_eq!(faults, [(t, fault(1)), (t, fault(2)), (t, fault(3))]);
+		assert_eq!(texts, ["error 1", "error 2", "error 3"]);
+	}
+
+	#[test]
+	fn debug_messages_and_logs_in_the_same_second_are_kept() {
+		let dir = std::env::temp_dir().join(format!("samn-debug-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let t = 1_700_000_000;
+		let mut message = [0u8; 20];
+		message[..5].copy_from_slice(b"hello");
+
+		let store = TimeseriesStore::new(&dir);
+		let mut tx = store.begin().unwrap();
+		assert_eq!(tx.add_message(7, &Message::DebugMessage(7, message), t), 1);
+		tx.add_log(7, "boot 1".to_string(), t);
+		tx.add_log(7, "boot 2".to_string(), t);
+		tx.commit().unwrap();
+		let mut tx = store.begin().unwrap();
+		tx.add_log(7, "boot 3".to_string(), t);
+		tx.commit().unwrap();
+
+		let mut texts: Vec<_> = DatabaseReader::new(&dir)
+			.unwrap()
+			.get(&debug_key(7))
+			.into_iter()
+			.map(|record| {
+				assert_eq!(datetime_to_timestamp(record.time()), t);
+				record.get::<&str>(0).to_string()
+			})
+			.collect();
+		std::fs::remove_dir_all(&dir).ok();
+
+		texts.sort();
+		assert_eq!(texts, ["boot 1", "boot 2", "boot 3", "hello"]);
+	}
+}
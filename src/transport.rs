@@ -0,0 +1,511 @@
+//! Sending messages without caring how they get there
+//!
+//! A [`Transport`] moves [`Message`]s to and from [`NodeAddress`]es. [`RadioTransport`]
+//! does it over any [`Radio`](crate::radio::Radio), and since transports take and give
+//! the same thing, layers (routing, retries, encryption...) wrap one another:
+//!
+//! ```ignore
+//! let radio = RadioTransport::node(radio, irq, delay, &[node_addr]);
+//! let mut transport = Retry::new(radio, 3);
+//! transport.send(node_addr, &message)?;
+//! ```
+use crate::node::{Message, NodeAddress};
+
+pub trait Transport {
+	type Error;
+
+	/// Sends `message` to `address`, `false` if it went out but wasn't acknowledged
+	fn send(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<bool, Self::Error>;
+	/// The next message that came in, with the address it was sent with
+	fn receive(&mut self) -> nb::Result<(NodeAddress, Message), Self::Error>;
+	/// Sends `message` to a group or to everyone, nobody acks it
+	///
+	/// Transports that can't broadcast send it like any other message.
+	fn broadcast(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<(), Self::Error> {
+		self.send(address, message).map(|_| ())
+	}
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+	type Error = T::Error;
+
+	fn send(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<bool, Self::Error> {
+		(**self).send(address, message)
+	}
+	fn receive(&mut self) -> nb::Result<(NodeAddress, Message), Self::Error> {
+		(**self).receive()
+	}
+	fn broadcast(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<(), Self::Error> {
+		(**self).broadcast(address, message)
+	}
+}
+
+/// Sends again when a message isn't acknowledged, up to `attempts` times in total
+pub struct Retry<T> {
+	inner: T,
+	attempts: u8,
+}
+
+impl<T> Retry<T> {
+	pub fn new(inner: T, attempts: u8) -> Self {
+		Self {
+			inner,
+			attempts: attempts.max(1),
+		}
+	}
+	pub fn inner(&mut self) -> &mut T {
+		&mut self.inner
+	}
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
+}
+
+impl<T: Transport> Transport for Retry<T> {
+	type Error = T::Error;
+
+	fn send(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<bool, Self::Error> {
+		for _ in 0..self.attempts {
+			if self.inner.send(address, message)? {
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+	fn receive(&mut self) -> nb::Result<(NodeAddress, Message), Self::Error> {
+		self.inner.receive()
+	}
+	/// Nothing to retry, broadcasts aren't acked
+	fn broadcast(
+		&mut self,
+		address: NodeAddress,
+		message: &Message,
+	) -> Result<(), Self::Error> {
+		self.inner.broadcast(address, message)
+	}
+}
+
+#[cfg(any(
+	feature = "cc1101",
+	feature = "nrf24",
+	feature = "serial",
+	feature = "ip"
+))]
+pub use radio::{RadioTransport, RX_ADDRESSES};
+
+#[cfg(any(
+	feature = "cc1101",
+	feature = "nrf24",
+	feature = "serial",
+	feature = "ip"
+))]
+mod radio {
+	use core::marker::PhantomData;
+
+	use embedded_hal::{delay::DelayNs, digital::InputPin};
+
+	use super::Transport;
+	use crate::dedup::{DedupCache, Sequences};
+	use crate::node::{Message, NodeAddress};
+	use crate::radio::helper::{send_broadcast, send_payload, Error};
+	use crate::radio::{addr_to_rx_pipe, Payload, Radio};
+
+	/// A [`Transport`] over a [`Radio`]
+	///
+	/// Which pipe a message goes out on depends on which side we're on,
+	/// see [`RadioTransport::node`] and [`RadioTransport::hq`].
+	///
+	/// Payloads can go out with a sequence number, sending the same message again after
+	/// it wasn't acked (like [`Retry`](super::Retry) does) keeps it, so the other end
+	/// drops the repeat if only the ack got lost. Repeats coming in are dropped too.
+	///
+	/// Firmware from before sequence numbers can't read payloads with one, so they're
+	/// only sent to addresses we've had one from, or to everyone after
+	/// [`RadioTransport::with_sequence`]. Each address gets its own numbers.
+	///
+	/// Broadcasts go out a few times since nobody acks them, see
+	/// [`RadioTransport::with_broadcast`].
+	pub struct RadioTransport<R, E, P, D> {
+		radio: R,
+		irq: P,
+		delay: D,
+		/// Pipe to send a message for this address on, `None` for [`Radio::hq_pipe`]
+		tx_pipe: Option<fn(NodeAddress) -> u8>,
+		/// Only payloads for these addresses (and broadcasts) are received,
+		/// `None` for everything
+		rx_addresses: Option<([NodeAddress; RX_ADDRESSES], usize)>,
+		/// Extra times a broadcast goes out, and ms between them
+		broadcast_repeats: (u8, u32),
+		/// Send every address a sequence, not only the ones that sent us one
+		always_sequence: bool,
+		sequences: Sequences<16>,
+		/// Last message that wasn't acked, (address, data, sequence)
+		unacked: Option<(NodeAddress, [u8; 32], Option<u8>)>,
+		dedup: DedupCache<16>,
+		_error: PhantomData<E>,
+	}
+
+	/// Most addresses a [`RadioTransport`] receives for
+	pub const RX_ADDRESSES: usize = 8;
+
+	impl<E, R: Radio<E>, P: InputPin, D: DelayNs> RadioTransport<R, E, P, D> {
+		pub fn new(
+			radio: R,
+			irq: P,
+			delay: D,
+			tx_pipe: Option<fn(NodeAddress) -> u8>,
+			rx_addresses: Option<&[NodeAddress]>,
+		) -> Self {
+			let mut transport = Self {
+				radio,
+				irq,
+				delay,
+				tx_pipe,
+				rx_addresses: None,
+				broadcast_repeats: (2, 10),
+				always_sequence: false,
+				sequences: Sequences::new(),
+				unacked: None,
+				dedup: DedupCache::new(),
+				_error: PhantomData,
+			};
+			if let Some(rx_addresses) = rx_addresses {
+				transport.set_rx_addresses(rx_addresses);
+			}
+			transport
+		}
+		/// Node side, everything goes to HQ's pipe and only `rx_addresses` are received
+		///
+		/// That's our address, and the groups we're in if any, like
+		/// [`Groups::rx_addresses`](crate::group::Groups::rx_addresses).
+		/// The rx filter isn't touched, set it with
+		/// [`node_rx_filter`](crate::radio::node_rx_filter).
+		pub fn node(radio: R, irq: P, delay: D, rx_addresses: &[NodeAddress]) -> Self {
+			Self::new(radio, irq, delay, None, Some(rx_addresses))
+		}
+		/// HQ side, messages go to each node's pipe and everything is received
+		pub fn hq(radio: R, irq: P, delay: D) -> Self {
+			Self::new(radio, irq, delay, Some(addr_to_rx_pipe), None)
+		}
+
+		/// Sequence numbers to every address, once whoever's on the other end knows them
+		///
+		/// Nodes turn it on so HQ knows it can send them sequences too.
+		pub fn with_sequence(mut self) -> Self {
+			self.always_sequence = true;
+			self
+		}
+		/// Broadcasts go out `1 + repeats` times, `gap_ms` apart, 2 and 10 by default
+		pub fn with_broadcast(mut self, repeats: u8, gap_ms: u32) -> Self {
+			self.broadcast_repeats = (repeats, gap_ms);
+			self
+		}
+
+		/// After our address changed or we joined or left a group
+		///
+		/// Panics with more than [`RX_ADDRESSES`].
+		pub fn set_rx_addresses(&mut self, rx_addresses: &[NodeAddress]) {
+			assert!(rx_addresses.len() <= RX_ADDRESSES, "too many rx addresses");
+			let mut addresses = [0; RX_ADDRESSES];
+			addresses[..rx_addresses.len()].copy_from_slice(rx_addresses);
+			self.rx_addresses = Some((addresses, rx_addresses.len()));
+		}
+
+		pub fn radio(&mut self) -> &mut R {
+			&mut self.radio
+		}
+		pub fn into_parts(self) -> (R, P, D) {
+			(self.radio, self.irq, self.delay)
+		}
+	}
+
+	impl<E, R: Radio<E>, P: InputPin, D: DelayNs> Transport for RadioTransport<R, E, P, D> {
+		type Error = Error<E>;
+
+		fn send(
+			&mut self,
+			address: NodeAddress,
+			message: &Message,
+		) -> Result<bool, Self::Error> {
+			let mut data = [0u8; 32];
+			let data_l = message
+				.serialize_to_bytes(&mut data)
+				.map_err(Error::SerializationError)?;
+			let sequence = match self.unacked {
+				Some((unacked_address, unacked_data, sequence))
+					if unacked_address == address && unacked_data == data =>
+				{
+					sequence
+				}
+				_ => {
+					if self.always_sequence {
+						self.sequences.insert(address);
+					}
+					self.sequences.advance(address)
+				}
+			};
+			let pipe = match self.tx_pipe {
+				Some(tx_pipe) => tx_pipe(address),
+				None => self.radio.hq_pipe(address),
+			};
+			let mut payload = Payload::new_with_addr_from_array(data, data_l, address, pipe);
+			if let Some(sequence) = sequence {
+				// Full payloads go without one
+				payload.set_sequence(sequence);
+			}
+			let acked = send_payload(&mut self.radio, &payload, &mut self.delay)?;
+			self.unacked = (!acked).then_some((address, data, sequence));
+			Ok(acked)
+		}
+		/// Make sure the radio's in rx, this doesn't switch it
+		fn receive(&mut self) -> nb::Result<(NodeAddress, Message), Self::Error> {
+			let rx_addresses = self
+				.rx_addresses
+				.as_ref()
+				.map(|(addresses, len)| &addresses[..*len]);
+			let payload = self
+				.radio
+				.receive(&mut self.irq, rx_addresses)
+				.map_err(|err| err.map(Error::RadioError))?;
+			// Garbled, data() would go out of bounds
+			if !payload.len_is_valid() {
+				return Err(nb::Error::WouldBlock);
+			}
+			if let (Some(address), Some(sequence)) = (payload.address(), payload.sequence()) {
+				if self.dedup.is_repeat(address, sequence) {
+					return Err(nb::Error::WouldBlock);
+				}
+				// It knows about sequences, it gets them from now on
+				self.sequences.insert(address);
+			}
+			let (message, _) = Message::deserialize_from_bytes(payload.data())
+				.map_err(|err| nb::Error::Other(Error::SerializationError(err)))?;
+			Ok((payload.address().unwrap_or_default(), message))
+		}
+		fn broadcast(
+			&mut self,
+			address: NodeAddress,
+			message: &Message,
+		) -> Result<(), Self::Error> {
+			let (repeats, gap_ms) = self.broadcast_repeats;
+			send_broadcast(
+				&mut self.radio,
+				message.clone(),
+				address,
+				repeats,
+				gap_ms,
+				&mut self.delay,
+			)
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Acks every other send, receives what it sent
+	#[derive(Default)]
+	struct Flaky {
+		sends: usize,
+		sent: Vec<(NodeAddress, Message)>,
+	}
+	impl Transport for Flaky {
+		type Error = ();
+
+		fn send(&mut self, address: NodeAddress, message: &Message) -> Result<bool, ()> {
+			self.sends += 1;
+			self.sent.push((address, message.clone()));
+			Ok(self.sends % 2 == 0)
+		}
+		fn receive(&mut self) -> nb::Result<(NodeAddress, Message), ()> {
+			self.sent.pop().ok_or(nb::Error::WouldBlock)
+		}
+	}
+
+	#[test]
+	fn retry_until_acked() {
+		let message = Message::SearchingNetwork(5);
+		let mut retry = Retry::new(Flaky::default(), 3);
+		assert_eq!(retry.send(7, &message), Ok(true));
+		assert_eq!(retry.inner().sends, 2);
+		assert_eq!(retry.receive(), Ok((7, message.clone())));
+
+		// Stacks on itself, 1 attempt never gets an odd send acked
+		let mut once = Retry::new(Retry::new(Flaky::default(), 1), 1);
+		assert_eq!(once.send(7, &message), Ok(false));
+		assert_eq!(once.into_inner().into_inner().sends, 1);
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn garbled_payloads_are_dropped() {
+		use crate::radio::mock::{MockRadio, NoDelay};
+		use crate::radio::{NoIrq, DEFAULT_PIPE};
+
+		let mut radio = MockRadio::default();
+		// Says it has 60 bytes of data, and none at all
+		radio.push_rx(&[DEFAULT_PIPE, 0x80 | 60, 4, 2]);
+		radio.push_rx(&[DEFAULT_PIPE, 0x80, 4, 2]);
+		let mut data = [0u8; 32];
+		let message = Message::SearchingNetwork(5);
+		let data_l = message.serialize_to_bytes(&mut data).unwrap();
+		radio
+			.push_rx(&[&[DEFAULT_PIPE, 0x80 | data_l as u8, 4, 2], &data[..data_l]].concat());
+
+		let mut hq = RadioTransport::hq(radio, NoIrq, NoDelay);
+		assert!(matches!(hq.receive(), Err(nb::Error::WouldBlock)));
+		assert!(matches!(hq.receive(), Err(nb::Error::WouldBlock)));
+		assert!(matches!(hq.receive(), Ok((0x0204, received)) if received == message));
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn sequences_only_to_peers_that_know_them() {
+		use crate::radio::mock::{MockRadio, NoDelay};
+		use crate::radio::{NoIrq, Payload, DEFAULT_PIPE};
+
+		let message = Message::SearchingNetwork(5);
+		let mut data = [0u8; 32];
+		let data_l = message.serialize_to_bytes(&mut data).unwrap();
+		let mut hq = RadioTransport::hq(MockRadio::default(), NoIrq, NoDelay);
+		// Could be old firmware
+		assert!(matches!(hq.send(0x0204, &message), Ok(true)));
+		for address in [0x0204, 0x0305] {
+			let mut payload =
+				Payload::new_with_addr_from_array(data, data_l, address, DEFAULT_PIPE);
+			assert!(payload.set_sequence(7));
+			hq.radio().rx.push_back(payload);
+			assert!(matches!(hq.receive(), Ok((a, _)) if a == address));
+		}
+		for address in [0x0204, 0x0204, 0x0305] {
+			assert!(matches!(hq.send(address, &message), Ok(true)));
+		}
+		let sequences: Vec<_> = hq.radio().tx.iter().map(Payload::sequence).collect();
+		assert_eq!(sequences, [None, Some(0), Some(1), Some(0)]);
+
+		let mut node = RadioTransport::node(MockRadio::default(), NoIrq, NoDelay, &[0x0204])
+			.with_sequence();
+		assert!(matches!(node.send(0x0204, &message), Ok(true)));
+		assert_eq!(node.radio().tx[0].sequence(), Some(0));
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn groups_and_broadcasts() {
+		use crate::group::Groups;
+		use crate::node::{group_address, BROADCAST_ADDRESS};
+		use crate::radio::mock::{MockRadio, NoDelay};
+		use crate::radio::{NoIrq, Payload, BROADCAST_PIPE};
+
+		let message = Message::SearchingNetwork(5);
+		let mut data = [0u8; 32];
+		let data_l = message.serialize_to_bytes(&mut data).unwrap();
+		let mut groups = Groups::<4>::new(0x0204);
+		let mut node =
+			RadioTransport::node(MockRadio::default(), NoIrq, NoDelay, groups.rx_addresses());
+		let push = |node: &mut RadioTransport<MockRadio, _, _, _>, address| {
+			let payload =
+				Payload::new_with_addr_from_array(data, data_l, address, BROADCAST_PIPE);
+			node.radio().rx.push_back(payload);
+		};
+		push(&mut node, group_address(3));
+		assert!(matches!(node.receive(), Err(nb::Error::WouldBlock)));
+		groups.join(3);
+		node.set_rx_addresses(groups.rx_addresses());
+		for address in [group_address(3), BROADCAST_ADDRESS, 0x0204] {
+			push(&mut node, address);
+			assert!(matches!(node.receive(), Ok((a, _)) if a == address));
+		}
+		push(&mut node, group_address(4));
+		assert!(matches!(node.receive(), Err(nb::Error::WouldBlock)));
+
+		// Not acked, and not retried for it either
+		let mut hq = Retry::new(RadioTransport::hq(MockRadio::default(), NoIrq, NoDelay), 3);
+		hq.inner().radio().acked = false;
+		assert!(matches!(hq.broadcast(group_address(3), &message), Ok(())));
+		let radio = hq.inner().radio();
+		assert_eq!(radio.no_ack, [true; 3]);
+		assert!(radio
+			.tx
+			.iter()
+			.all(|p| p.address() == Some(group_address(3)) && p.pipe() == BROADCAST_PIPE));
+	}
+
+	#[cfg(feature = "ip")]
+	#[test]
+	fn radio_transport_over_udp() {
+		use crate::radio::ip::UdpRadio;
+		use crate::radio::mock::NoDelay;
+		use crate::radio::{addr_to_rx_pipe, NoIrq, Payload, Radio, NRF24_HQ_PIPES};
+
+		fn receive_for_a_bit<T: Transport>(
+			transport: &mut T,
+		) -> Option<(NodeAddress, Message)> {
+			for _ in 0..200 {
+				match transport.receive() {
+					Err(nb::Error::WouldBlock) => {
+						std::thread::sleep(std::time::Duration::from_millis(5))
+					}
+					result => return result.ok(),
+				}
+			}
+			None
+		}
+
+		let node_addr = 0x0204;
+		let mut hq_radio =
+			UdpRadio::bind("127.0.0.1:0", "127.0.0.1:9".parse().unwrap()).unwrap();
+		hq_radio.set_rx_filter(&NRF24_HQ_PIPES).unwrap();
+		let mut node_radio =
+			UdpRadio::bind("127.0.0.1:0", hq_radio.local_addr().unwrap()).unwrap();
+		node_radio
+			.set_rx_filter(&[addr_to_rx_pipe(node_addr)])
+			.unwrap();
+		let mut hq = RadioTransport::hq(hq_radio, NoIrq, NoDelay);
+		let mut node =
+			RadioTransport::node(node_radio, NoIrq, NoDelay, &[node_addr]).with_sequence();
+
+		let hello = Message::SearchingNetwork(5);
+		assert!(matches!(node.send(node_addr, &hello), Ok(true)));
+		assert_eq!(receive_for_a_bit(&mut hq), Some((node_addr, hello.clone())));
+
+		let answer = Message::Network(5, node_addr);
+		assert!(matches!(hq.send(node_addr, &answer), Ok(true)));
+		assert_eq!(receive_for_a_bit(&mut node), Some((node_addr, answer)));
+
+		// Same sequence twice, like when the ack got lost, only the first one is handled
+		let mut data = [0u8; 32];
+		let data_l = hello.serialize_to_bytes(&mut data).unwrap();
+		let hq_pipe = node.radio().hq_pipe(node_addr);
+		let mut payload = Payload::new_with_addr_from_array(data, data_l, node_addr, hq_pipe);
+		payload.set_sequence(200);
+		for _ in 0..2 {
+			node.radio().transmit_start(&payload, &mut NoDelay).unwrap();
+		}
+		assert_eq!(receive_for_a_bit(&mut hq), Some((node_addr, hello.clone())));
+		assert_eq!(receive_for_a_bit(&mut hq), None);
+		assert!(matches!(node.send(node_addr, &hello), Ok(true)));
+		assert_eq!(receive_for_a_bit(&mut hq), Some((node_addr, hello)));
+	}
+}
@@ -0,0 +1,465 @@
+-- Wireshark dissector for captures written by samn_common::radio::pcap
+--
+--   wireshark -X lua_script:tools/samn.lua capture.pcap
+--
+-- or copy it to ~/.local/lib/wireshark/plugins/. Records are LINKTYPE_USER0:
+-- [capture version, rssi (i8, 0x80 unknown)] then the payload, pipe first.
+-- The message bit layout follows src/node.rs, keep the two in sync.
+
+local CAPTURE_VERSION = 1
+local MESSAGE_VERSION = 1
+local RSSI_UNKNOWN = 0x80
+local LIMBS_MAX = 3
+
+local samn = Proto("samn", "SAMN radio")
+
+local messages = {
+	[0] = "Message",
+	[1] = "RelayMessage",
+	[2] = "SearchingNetwork",
+	[3] = "Network",
+	[4] = "DebugMessage",
+	[5] = "FaultReport",
+	[6] = "Log",
+}
+local commands = {
+	[0] = "Info",
+	[1] = "Limbs",
+	[2] = "SetLimb",
+	[3] = "ToggleLimb",
+	[4] = "SetLimbType",
+	[5] = "SyncTime",
+	[6] = "FetchHistory",
+	[7] = "ChangeChannel",
+	[8] = "JoinGroup",
+	[9] = "LeaveGroup",
+}
+local responses = {
+	[0] = "Ok",
+	[1] = "Info",
+	[2] = "Limbs",
+	[3] = "Heartbeat",
+	[4] = "LimbsAt",
+	[5] = "History",
+}
+local sensors = { [0] = "Battery", [1] = "TempHum", [2] = "Current" }
+local actuators = { [0] = "Light" }
+local boards = { [0] = "SamnV8", [1] = "SamnV9", [2] = "SamnDC", [3] = "SamnSwitch" }
+local resets = {
+	[0] = "PowerOn",
+	[1] = "External",
+	[2] = "Brownout",
+	[3] = "Watchdog",
+	[4] = "Software",
+	[5] = "Panic",
+	[7] = "Unknown",
+}
+
+local f = {
+	capture_version = ProtoField.uint8("samn.capture_version", "Capture version"),
+	rssi = ProtoField.int8("samn.rssi", "RSSI (dBm)"),
+	pipe = ProtoField.uint8("samn.pipe", "Pipe", base.HEX),
+	has_address = ProtoField.bool("samn.has_address", "Has address", 8, nil, 0x80),
+	has_sequence = ProtoField.bool("samn.has_sequence", "Has sequence", 8, nil, 0x40),
+	len = ProtoField.uint8("samn.len", "Data length"),
+	address = ProtoField.uint16("samn.address", "Address", base.HEX),
+	sequence = ProtoField.uint8("samn.sequence", "Sequence"),
+
+	version = ProtoField.uint8("samn.version", "Message version"),
+	message = ProtoField.uint8("samn.message", "Message", base.DEC, messages),
+	node_id = ProtoField.uint32("samn.node_id", "Node id"),
+	node_address = ProtoField.uint16("samn.node_address", "Node address", base.HEX),
+	debug = ProtoField.string("samn.debug", "Debug message"),
+	fault_code = ProtoField.uint8("samn.fault.code", "Fault code"),
+	fault_count = ProtoField.uint16("samn.fault.count", "Times"),
+	fault_uptime = ProtoField.uint32("samn.fault.uptime", "Uptime (s)"),
+	fault_reset = ProtoField.uint8("samn.fault.reset", "Reset reason", base.DEC, resets),
+	log = ProtoField.uint8("samn.log", "Log"),
+	log_part = ProtoField.uint8("samn.log.part", "Part"),
+	log_last = ProtoField.bool("samn.log.last", "Last part"),
+	log_len = ProtoField.uint8("samn.log.len", "Length"),
+	log_data = ProtoField.bytes("samn.log.data", "Packed log"),
+
+	is_command = ProtoField.bool("samn.is_command", "Is command"),
+	id = ProtoField.uint8("samn.id", "Command id"),
+	command = ProtoField.uint8("samn.command", "Command", base.DEC, commands),
+	response = ProtoField.uint8("samn.response", "Response", base.DEC, responses),
+
+	timestamp = ProtoField.uint32("samn.timestamp", "Timestamp"),
+	millis = ProtoField.uint16("samn.millis", "Millis"),
+	latency = ProtoField.uint8("samn.latency", "Latency (ms)"),
+	channel = ProtoField.uint8("samn.channel", "Channel"),
+	group = ProtoField.uint8("samn.group", "Group"),
+	board = ProtoField.uint8("samn.board", "Board", base.DEC, boards),
+	heartbeat_interval = ProtoField.uint16("samn.heartbeat_interval", "Heartbeat interval (s)"),
+
+	limb_present = ProtoField.bool("samn.limb.present", "Present"),
+	limb_id = ProtoField.uint8("samn.limb.id", "Limb id"),
+	is_sensor = ProtoField.bool("samn.limb.is_sensor", "Is sensor"),
+	report_interval = ProtoField.uint16("samn.limb.report_interval", "Report interval (s)"),
+	has_data = ProtoField.bool("samn.limb.has_data", "Has data"),
+	sensor = ProtoField.uint8("samn.sensor", "Sensor", base.DEC, sensors),
+	actuator = ProtoField.uint8("samn.actuator", "Actuator", base.DEC, actuators),
+	battery = ProtoField.uint8("samn.sensor.battery", "Battery"),
+	temperature = ProtoField.int16("samn.sensor.temperature", "Temperature"),
+	humidity = ProtoField.uint8("samn.sensor.humidity", "Humidity"),
+	current = ProtoField.uint16("samn.sensor.current", "Current (mA)"),
+	light = ProtoField.bool("samn.actuator.light", "Light on"),
+
+	history_count = ProtoField.uint8("samn.history.count", "Samples"),
+	full_time = ProtoField.bool("samn.history.full_time", "Full timestamp"),
+	time_delta = ProtoField.uint16("samn.history.time_delta", "Time delta (s)"),
+	full_reading = ProtoField.bool("samn.history.full_reading", "Full reading"),
+}
+samn.fields = f
+
+local malformed = ProtoExpert.new("samn.malformed", "Malformed message", expert.group.MALFORMED, expert.severity.ERROR)
+samn.experts = { malformed }
+
+-- Reads bits MSB first like bity, adding each field over the bytes it touches
+local Bits = {}
+Bits.__index = Bits
+
+local function bits(range)
+	return setmetatable({ range = range, pos = 0 }, Bits)
+end
+
+function Bits:read(n)
+	if self.pos + n > self.range:len() * 8 then
+		error("truncated")
+	end
+	local value = self.range:bitfield(self.pos, n)
+	self.pos = self.pos + n
+	return value
+end
+
+-- Adds `field` read from the next `n` bits, `convert` turns the raw bits into the value
+function Bits:add(tree, field, n, convert)
+	local start = self.pos
+	local value = self:read(n)
+	if convert then
+		value = convert(value)
+	end
+	local first = math.floor(start / 8)
+	local last = math.floor((start + n - 1) / 8)
+	return value, tree:add(field, self.range(first, last - first + 1), value)
+end
+
+-- A subtree over the bytes from `start` bits to where the reader is now
+function Bits:close(item, start)
+	local first = math.floor(start / 8)
+	local last = math.max(first, math.floor((self.pos - 1) / 8))
+	item:set_len(last - first + 1)
+end
+
+function Bits:subtree(tree, label)
+	local first = math.floor(self.pos / 8)
+	return tree:add(samn, self.range(first, 1), label), self.pos
+end
+
+local function flag(value)
+	return value == 1
+end
+
+local function signed16(value)
+	if value >= 0x8000 then
+		return value - 0x10000
+	end
+	return value
+end
+
+local function unzigzag(value)
+	if value % 2 == 0 then
+		return math.floor(value / 2)
+	end
+	return -math.floor((value + 1) / 2)
+end
+
+local function add_timestamp(r, tree, field)
+	local value, item = r:add(tree, field or f.timestamp, 32)
+	if value > 0 then
+		item:append_text(os.date("! (%Y-%m-%d %H:%M:%S UTC)", value))
+	end
+	return value
+end
+
+-- Returns { code, a, b } so history deltas can build on it
+local function sensor(r, tree)
+	local code = r:add(tree, f.sensor, 4)
+	if code == 0 then
+		return { code, (r:add(tree, f.battery, 8)) }
+	elseif code == 1 then
+		local temperature = r:add(tree, f.temperature, 16, signed16)
+		return { code, temperature, (r:add(tree, f.humidity, 8)) }
+	elseif code == 2 then
+		return { code, (r:add(tree, f.current, 16)) }
+	end
+	error("sensor code " .. code)
+end
+
+local function sensor_delta(r, tree, prev)
+	if r:add(tree, f.full_reading, 1, flag) then
+		return sensor(r, tree)
+	end
+	local code = prev[1]
+	tree:add(f.sensor, r.range(math.floor(r.pos / 8), 1), code):set_generated()
+	local function delta(field, n, base_value)
+		return r:add(tree, field, n, function(value)
+			return base_value + unzigzag(value)
+		end)
+	end
+	if code == 0 then
+		return { code, (delta(f.battery, 4, prev[2])) }
+	elseif code == 1 then
+		local temperature = delta(f.temperature, 8, prev[2])
+		return { code, temperature, (delta(f.humidity, 5, prev[3])) }
+	else
+		return { code, (delta(f.current, 8, prev[2])) }
+	end
+end
+
+local function actuator(r, tree)
+	local code = r:add(tree, f.actuator, 4)
+	if code == 0 then
+		r:add(tree, f.light, 1, flag)
+		return
+	end
+	error("actuator code " .. code)
+end
+
+local function limb_type(r, tree)
+	if r:add(tree, f.is_sensor, 1, flag) then
+		r:add(tree, f.report_interval, 16)
+		if r:add(tree, f.has_data, 1, flag) then
+			sensor(r, tree)
+		end
+	else
+		actuator(r, tree)
+	end
+end
+
+local function limb(r, tree)
+	local item, start = r:subtree(tree, "Limb")
+	local id = r:add(item, f.limb_id, 4)
+	item:append_text(" " .. id)
+	limb_type(r, item)
+	r:close(item, start)
+end
+
+local function limbs(r, tree)
+	for _ = 1, LIMBS_MAX do
+		if r:add(tree, f.limb_present, 1, flag) then
+			limb(r, tree)
+		end
+	end
+end
+
+local function history(r, tree)
+	r:add(tree, f.limb_id, 4)
+	local count = r:add(tree, f.history_count, 3)
+	if count > 6 then
+		error("history count " .. count)
+	end
+	local time, reading
+	for i = 1, count do
+		local item, start = r:subtree(tree, "Sample " .. i)
+		if time == nil then
+			time = add_timestamp(r, item)
+			reading = sensor(r, item)
+		else
+			if r:add(item, f.full_time, 1, flag) then
+				time = add_timestamp(r, item)
+			else
+				time = time + r:add(item, f.time_delta, 10)
+			end
+			reading = sensor_delta(r, item, reading)
+		end
+		r:close(item, start)
+	end
+end
+
+local function command(r, tree)
+	local code = r:add(tree, f.command, 4)
+	if code == 2 then
+		limb(r, tree)
+	elseif code == 3 then
+		r:add(tree, f.limb_id, 4)
+	elseif code == 4 then
+		limb_type(r, tree)
+	elseif code == 5 then
+		add_timestamp(r, tree)
+		r:add(tree, f.millis, 10)
+		r:add(tree, f.latency, 8)
+	elseif code == 6 then
+		r:add(tree, f.limb_id, 4)
+		add_timestamp(r, tree)
+	elseif code == 7 then
+		r:add(tree, f.channel, 8)
+		add_timestamp(r, tree)
+	elseif code == 8 or code == 9 then
+		r:add(tree, f.group, 8)
+	elseif commands[code] == nil then
+		error("command code " .. code)
+	end
+	return commands[code]
+end
+
+local function response(r, tree)
+	local code = r:add(tree, f.response, 4)
+	if code == 1 then
+		r:add(tree, f.board, 2)
+		r:add(tree, f.heartbeat_interval, 16)
+	elseif code == 2 then
+		limbs(r, tree)
+	elseif code == 3 then
+		add_timestamp(r, tree)
+	elseif code == 4 then
+		add_timestamp(r, tree)
+		limbs(r, tree)
+	elseif code == 5 then
+		history(r, tree)
+	elseif responses[code] == nil then
+		error("response code " .. code)
+	end
+	return responses[code]
+end
+
+local function message_data(r, tree)
+	local is_command = r:add(tree, f.is_command, 1, flag)
+	local id = r:add(tree, f.id, 6)
+	if is_command then
+		return string.format("%s (id %d)", command(r, tree), id)
+	end
+	local name = response(r, tree)
+	if id == 0 then
+		return name
+	end
+	return string.format("%s (to %d)", name, id)
+end
+
+-- Returns a summary for the info column
+local function message(r, tree)
+	local version = r:add(tree, f.version, 2)
+	if version ~= MESSAGE_VERSION then
+		return "message version " .. version
+	end
+	local code = r:add(tree, f.message, 4)
+	if code == 0 then
+		return message_data(r, tree)
+	elseif code == 1 then
+		local node_id = r:add(tree, f.node_id, 32)
+		return string.format("Relay to %d: %s", node_id, message_data(r, tree))
+	elseif code == 2 then
+		return "SearchingNetwork " .. r:add(tree, f.node_id, 32)
+	elseif code == 3 then
+		local node_id = r:add(tree, f.node_id, 32)
+		local address = r:add(tree, f.node_address, 16)
+		return string.format("Network %d -> 0x%04x", node_id, address)
+	elseif code == 4 then
+		local node_id = r:add(tree, f.node_id, 32)
+		local start = r.pos
+		local chars = {}
+		for i = 1, 20 do
+			local byte = r:read(8)
+			if byte ~= 0 then
+				chars[#chars + 1] = string.char(byte)
+			end
+		end
+		local first = math.floor(start / 8)
+		local text = table.concat(chars)
+		tree:add(f.debug, r.range(first, r.range:len() - first), text)
+		return string.format("Debug %d: %s", node_id, text)
+	elseif code == 5 then
+		local node_id = r:add(tree, f.node_id, 32)
+		local fault = r:add(tree, f.fault_code, 8)
+		local count = r:add(tree, f.fault_count, 16)
+		r:add(tree, f.fault_uptime, 32)
+		local reset = r:add(tree, f.fault_reset, 3)
+		return string.format(
+			"Fault %d: error %d x%d (after %s)",
+			node_id,
+			fault,
+			count,
+			resets[reset] or "Unknown"
+		)
+	elseif code == 6 then
+		-- Format strings are only in the firmware, this shows the packed bytes
+		local node_id = r:add(tree, f.node_id, 32)
+		local log = r:add(tree, f.log, 4)
+		local part = r:add(tree, f.log_part, 4)
+		local last = r:add(tree, f.log_last, 1, flag)
+		local len = r:add(tree, f.log_len, 5)
+		local start = r.pos
+		for i = 1, len do
+			r:read(8)
+		end
+		if len > 0 then
+			-- Not byte aligned, the item spans the bytes the data touches
+			local first = math.floor(start / 8)
+			local last_byte = math.floor((r.pos - 1) / 8)
+			tree:add(f.log_data, r.range(first, last_byte - first + 1))
+		end
+		return string.format("Log %d: #%d part %d%s", node_id, log, part, last and " (last)" or "")
+	end
+	error("message code " .. code)
+end
+
+function samn.dissector(tvb, pinfo, tree)
+	if tvb:len() < 4 or tvb(0, 1):uint() ~= CAPTURE_VERSION then
+		return 0
+	end
+	pinfo.cols.protocol = "SAMN"
+	local root = tree:add(samn, tvb(), "SAMN radio")
+	root:add(f.capture_version, tvb(0, 1))
+	local rssi = root:add(f.rssi, tvb(1, 1))
+	if tvb(1, 1):uint() == RSSI_UNKNOWN then
+		rssi:append_text(" (unknown)")
+	end
+
+	local payload = tvb(2)
+	local pipe = payload(0, 1):uint()
+	local flags = payload(1, 1):uint()
+	local has_address = flags >= 0x80
+	local has_sequence = has_address and flags % 0x80 >= 0x40
+	-- Both flag bits are masked off once there's an address
+	local len = flags % 0x80
+	local header = 2
+	if has_address then
+		len = flags % 0x40
+		header = has_sequence and 5 or 4
+	end
+
+	local item = root:add(samn, payload(0, math.min(header, payload:len())), "Payload header")
+	item:add(f.pipe, payload(0, 1))
+	item:add(f.has_address, payload(1, 1))
+	item:add(f.has_sequence, payload(1, 1))
+	item:add(f.len, payload(1, 1), len)
+	local summary = string.format("pipe 0x%02x", pipe)
+	if has_address then
+		local address = payload(2, 2):le_uint()
+		item:add_le(f.address, payload(2, 2))
+		summary = summary .. string.format(" addr 0x%04x", address)
+	end
+	if has_sequence then
+		item:add(f.sequence, payload(4, 1))
+		summary = summary .. " seq " .. payload(4, 1):uint()
+	end
+
+	if len == 0 or header + len > payload:len() then
+		root:add_proto_expert_info(malformed, "bad data length " .. len)
+		pinfo.cols.info = summary
+		return tvb:len()
+	end
+	local data = payload(header, len)
+	local body = root:add(samn, data, "Message")
+	local ok, result = pcall(message, bits(data), body)
+	if ok then
+		pinfo.cols.info = summary .. " " .. result
+	else
+		body:add_proto_expert_info(malformed, tostring(result))
+		pinfo.cols.info = summary .. " malformed"
+	end
+	return tvb:len()
+end
+
+DissectorTable.get("wtap_encap"):add(wtap_encaps.USER0, samn)